@@ -95,7 +95,11 @@ export default function App() {
         if (res.reply) {
           setMessages((prev) => [
             ...prev,
-            { role: "assistant", content: res.reply },
+            {
+              role: "assistant",
+              content: res.reply,
+              personality: res.personality || null,
+            },
           ]);
         }
       } catch (e) {
@@ -1,20 +1,45 @@
 const BASE = "/api";
 
+// Errors carry the server's stable `code` (not_found, validation_failed,
+// llm_rate_limited, llm_unavailable, conflict, budget_exceeded, shutting_down,
+// internal).
+export class ApiError extends Error {
+  constructor(status, code, message) {
+    super(message);
+    this.status = status;
+    this.code = code;
+  }
+}
+
+async function check(res) {
+  if (res.ok) return;
+  let code = "internal";
+  let message = `HTTP ${res.status}`;
+  try {
+    const body = await res.json();
+    code = body.error.code;
+    message = body.error.message;
+  } catch {
+    // Not one of ours, e.g. a proxy error page
+  }
+  throw new ApiError(res.status, code, message);
+}
+
 export async function createDoc() {
   const res = await fetch(`${BASE}/docs`, { method: "POST" });
-  if (!res.ok) throw new Error(`HTTP ${res.status}`);
+  await check(res);
   return res.json();
 }
 
 export async function getDoc(id) {
   const res = await fetch(`${BASE}/docs/${id}`);
-  if (!res.ok) throw new Error(`HTTP ${res.status}`);
+  await check(res);
   return res.json();
 }
 
 export async function getMessages(id) {
   const res = await fetch(`${BASE}/docs/${id}/messages`);
-  if (!res.ok) throw new Error(`HTTP ${res.status}`);
+  await check(res);
   return res.json();
 }
 
@@ -27,7 +52,7 @@ export async function chat(id, message, hoverNodeId) {
       hover_node_id: hoverNodeId || null,
     }),
   });
-  if (!res.ok) throw new Error(`HTTP ${res.status}`);
+  await check(res);
   return res.json();
 }
 
@@ -43,13 +68,13 @@ export async function heartbeat(id) {
   const res = await fetch(`${BASE}/docs/${id}/heartbeat`, {
     method: "POST",
   });
-  if (!res.ok) throw new Error(`HTTP ${res.status}`);
+  await check(res);
   return res.json();
 }
 
 export async function getPersonalities(id) {
   const res = await fetch(`${BASE}/docs/${id}/personalities`);
-  if (!res.ok) throw new Error(`HTTP ${res.status}`);
+  await check(res);
   return res.json();
 }
 
@@ -59,7 +84,7 @@ export async function setPersonalities(id, personalityIds) {
     headers: { "Content-Type": "application/json" },
     body: JSON.stringify({ personality_ids: personalityIds }),
   });
-  if (!res.ok) throw new Error(`HTTP ${res.status}`);
+  await check(res);
 }
 
 export async function updateDocSettings(id, settings) {
@@ -68,7 +93,7 @@ export async function updateDocSettings(id, settings) {
     headers: { "Content-Type": "application/json" },
     body: JSON.stringify(settings),
   });
-  if (!res.ok) throw new Error(`HTTP ${res.status}`);
+  await check(res);
 }
 
 export async function getSummary(id, voice, forceRefresh) {
@@ -80,6 +105,6 @@ export async function getSummary(id, voice, forceRefresh) {
       force_refresh: forceRefresh || false,
     }),
   });
-  if (!res.ok) throw new Error(`HTTP ${res.status}`);
+  await check(res);
   return res.json();
 }
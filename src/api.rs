@@ -1,64 +1,465 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use anyhow::Context;
 use axum::extract::{Path, State};
 use axum::http::StatusCode;
-use axum::Json;
-use rand::seq::SliceRandom;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
+use crate::backup;
+use crate::config::BackupConfig;
 use crate::db::Db;
+use crate::debate;
+use crate::diff;
+use crate::error::{ApiError, ErrorCode};
+use crate::extract::{Json, Query};
+use crate::merge;
+use crate::metrics;
 use crate::llm::{self, count_nodes, LlmClient};
 use crate::models::*;
+use crate::selection::{select_speakers, SelectionInput};
+use crate::validate;
+
+/// Heartbeats an `ask_agent` question may wait for its answer before it expires.
+const MAX_QUESTION_AGE: u32 = 3;
+
+const MAX_TITLE_CHARS: usize = 200;
 
 pub struct AppState {
     pub db: Db,
     pub llm: LlmClient,
+    pub backup: BackupConfig,
+    /// Set once the server starts shutting down: new heartbeats are
+    /// refused while in-flight requests finish.
+    pub draining: AtomicBool,
 }
 
 pub async fn create_doc(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<CreateDocResponse>, (StatusCode, String)> {
+) -> Result<Json<CreateDocResponse>, ApiError> {
     let id = generate_short_id();
     state
         .db
         .create_document(&id)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .await?;
     Ok(Json(CreateDocResponse { id }))
 }
 
 pub async fn get_doc(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> Result<Json<Document>, StatusCode> {
-    match state.db.get_document(&id) {
-        Ok(Some(doc)) => Ok(Json(doc)),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+) -> Result<Json<Document>, ApiError> {
+    let doc = state
+        .db
+        .get_document(&id)
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
+    Ok(Json(doc))
+}
+
+/// Set the title, or regenerate it from the tree when none is given.
+pub async fn set_title(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(req): Json<TitleRequest>,
+) -> Result<Json<TitleResponse>, ApiError> {
+    let doc = state
+        .db
+        .get_document(&id)
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
+
+    let title = match req.title {
+        Some(title) => {
+            let title = title.trim().to_string();
+            if title.is_empty() || title.chars().count() > MAX_TITLE_CHARS {
+                return Err(ApiError::validation(format!(
+                    "title must be 1 to {MAX_TITLE_CHARS} characters"
+                )));
+            }
+            title
+        }
+        None => state
+            .llm
+            .generate_title(&doc.tree)
+            .await
+            .map_err(|e| ApiError::llm("Title generation error", e))?,
+    };
+    state
+        .db
+        .set_title(&id, &title)
+        .await?;
+    Ok(Json(TitleResponse { title }))
+}
+
+pub async fn archive_doc(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    set_archived(&state, &id, true).await
+}
+
+pub async fn unarchive_doc(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    set_archived(&state, &id, false).await
+}
+
+async fn set_archived(state: &AppState, id: &str, archived: bool) -> Result<StatusCode, ApiError> {
+    let found = state
+        .db
+        .set_archived(id, archived)
+        .await?;
+    if !found {
+        return Err(ApiError::document_not_found());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Copy the tree, edges, personas and settings into a new document.
+pub async fn duplicate_doc(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<CreateDocResponse>, ApiError> {
+    let doc = state
+        .db
+        .get_document(&id)
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
+
+    let new_id = generate_short_id();
+    let title = doc.title.map(|t| format!("{t} (copy)"));
+    state
+        .db
+        .duplicate_document(&id, &new_id, title.as_deref())
+        .await?;
+    Ok(Json(CreateDocResponse { id: new_id }))
+}
+
+/// Copy the document into a fork that remembers its parent and the fork
+/// point, so it can be merged back later.
+pub async fn fork_doc(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<CreateDocResponse>, ApiError> {
+    let doc = state
+        .db
+        .get_document(&id)
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
+
+    let new_id = generate_short_id();
+    let title = doc.title.map(|t| format!("{t} (fork)"));
+    state
+        .db
+        .fork_document(&id, &new_id, title.as_deref())
+        .await?;
+    Ok(Json(CreateDocResponse { id: new_id }))
+}
+
+/// Preview merging a fork back into its parent.
+pub async fn preview_merge(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<MergeResponse>, ApiError> {
+    let (parent_id, outcome) = three_way_merge(&state, &id, &HashMap::new()).await?;
+    Ok(Json(MergeResponse {
+        parent_id,
+        applied: false,
+        report: outcome.report,
+        tree: outcome.tree,
+        edges: outcome.edges,
+    }))
+}
+
+/// Merge a fork back into its parent. Every conflict must have a side picked
+/// in `resolutions`; otherwise nothing is written.
+pub async fn merge_fork(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(req): Json<MergeRequest>,
+) -> Result<Json<MergeResponse>, ApiError> {
+    let (parent_id, outcome) = three_way_merge(&state, &id, &req.resolutions).await?;
+    let unresolved: Vec<&str> = outcome
+        .report
+        .conflicts
+        .iter()
+        .filter(|c| c.resolution.is_none())
+        .map(|c| c.id.as_str())
+        .collect();
+    if !unresolved.is_empty() {
+        return Err(ApiError::conflict(format!(
+            "Pick a side for each conflict: {}",
+            unresolved.join(", ")
+        )));
+    }
+    state
+        .db
+        .apply_merge(&parent_id, &id, &outcome.tree, &outcome.edges)
+        .await?;
+    Ok(Json(MergeResponse {
+        parent_id,
+        applied: true,
+        report: outcome.report,
+        tree: outcome.tree,
+        edges: outcome.edges,
+    }))
+}
+
+pub async fn get_revisions(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<RevisionsResponse>, ApiError> {
+    let revisions = state
+        .db
+        .list_revisions(&id)
+        .await?;
+    if revisions.is_empty() {
+        return Err(ApiError::document_not_found());
+    }
+    Ok(Json(RevisionsResponse { revisions }))
+}
+
+/// Structural diff between two states of a document, as JSON plus a
+/// Markdown report.
+pub async fn get_diff(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<DiffQuery>,
+) -> Result<Json<DiffResponse>, ApiError> {
+    let doc = state
+        .db
+        .get_document(&id)
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
+
+    let revision = async |rev: i64| {
+        state
+            .db
+            .get_revision(&id, rev)
+            .await?
+            .ok_or(ApiError::not_found(format!("Revision {rev} not found")))
+    };
+
+    let (to_label, to_tree, to_edges) = match query.to {
+        Some(rev) => {
+            let (tree, edges, at) = revision(rev).await?;
+            (format!("revision {rev} ({at})"), tree, edges)
+        }
+        None => ("the current state".to_string(), doc.tree.clone(), doc.edges.clone()),
+    };
+
+    let (from_label, from_tree, from_edges) = match (&query.from, &query.since, query.origin) {
+        (Some(rev), None, false) => {
+            let (tree, edges, at) = revision(*rev).await?;
+            (format!("revision {rev} ({at})"), tree, edges)
+        }
+        (None, Some(since), false) => {
+            validate::validate_timestamp("since", since).map_err(ApiError::validation)?;
+            let (tree, edges, _) = state
+                .db
+                .get_revision_at(&id, since)
+                .await?
+                .ok_or(ApiError::not_found("Document has no revisions"))?;
+            (format!("the state as of {since}"), tree, edges)
+        }
+        (None, None, true) => {
+            let parent_id = doc
+                .forked_from
+                .clone()
+                .ok_or_else(|| ApiError::validation("Document is not a fork"))?;
+            let parent = state
+                .db
+                .get_document(&parent_id)
+                .await?
+                .ok_or_else(|| {
+                    ApiError::not_found(format!("Parent document \"{parent_id}\" no longer exists"))
+                })?;
+            (format!("origin {parent_id}"), parent.tree, parent.edges)
+        }
+        _ => {
+            return Err(ApiError::validation("Give exactly one of from, since or origin=true"));
+        }
+    };
+
+    let diff = diff::diff(&from_tree, &from_edges, &to_tree, &to_edges);
+    let markdown = diff::render_markdown(&diff, &from_label, &to_label);
+    Ok(Json(DiffResponse {
+        from: from_label,
+        to: to_label,
+        diff,
+        markdown,
+    }))
+}
+
+async fn three_way_merge(
+    state: &AppState,
+    fork_id: &str,
+    resolutions: &HashMap<String, MergeSide>,
+) -> Result<(String, merge::MergeOutcome), ApiError> {
+    let fork = state
+        .db
+        .get_document(fork_id)
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
+    let parent_id = fork
+        .forked_from
+        .clone()
+        .ok_or_else(|| ApiError::validation("Document is not a fork"))?;
+    let parent = state
+        .db
+        .get_document(&parent_id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Parent document \"{parent_id}\" no longer exists")))?;
+    let (base_tree, base_edges) = state
+        .db
+        .get_fork_base(fork_id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Fork has no recorded fork point"))?;
+
+    let outcome = merge::three_way(
+        &merge::Snapshot { tree: &base_tree, edges: &base_edges },
+        &merge::Snapshot { tree: &parent.tree, edges: &parent.edges },
+        &merge::Snapshot { tree: &fork.tree, edges: &fork.edges },
+        resolutions,
+    );
+    Ok((parent_id, outcome))
+}
+
+/// Permanently delete a document with its messages, personas, settings,
+/// summaries and agent questions.
+pub async fn delete_doc(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let found = state
+        .db
+        .delete_document(&id)
+        .await?;
+    if !found {
+        return Err(ApiError::document_not_found());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The document with everything attached to it, as a portable bundle.
+pub async fn export_bundle(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<DocumentBundle>, ApiError> {
+    let bundle = state
+        .db
+        .export_bundle(&id)
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
+    Ok(Json(bundle))
+}
+
+/// Recreate a bundled document under a fresh id.
+pub async fn import_bundle(
+    State(state): State<Arc<AppState>>,
+    Json(bundle): Json<DocumentBundle>,
+) -> Result<Json<CreateDocResponse>, ApiError> {
+    if bundle.bundle_version > BUNDLE_VERSION {
+        return Err(ApiError::validation(format!(
+            "Bundle version {} is not supported",
+            bundle.bundle_version
+        )));
     }
+    validate::validate_bundle(&bundle).map_err(ApiError::validation)?;
+    let id = generate_short_id();
+    state
+        .db
+        .import_bundle(&id, bundle)
+        .await?;
+    Ok(Json(CreateDocResponse { id }))
+}
+
+/// Back up the database into the rotating backup directory now.
+pub async fn backup_now(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<BackupInfo>, ApiError> {
+    let task_state = state.clone();
+    let info = tokio::task::spawn_blocking(move || backup::backup_now(&task_state.db, &task_state.backup))
+        .await
+        .map_err(anyhow::Error::from)?
+        .context("Backup failed")?;
+    tracing::info!("Backed up to {} ({} bytes)", info.file, info.bytes);
+    Ok(Json(info))
+}
+
+/// Liveness: the process is up and answering.
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Readiness: the database answers, the model can be called, and the server
+/// isn't shutting down. 503 until all three hold.
+pub async fn readyz(State(state): State<Arc<AppState>>) -> (StatusCode, Json<ReadyResponse>) {
+    // The reasons go to the log, not to whoever is probing
+    let outcome = |check: &str, result: anyhow::Result<()>| match result {
+        Ok(()) => "ok".to_string(),
+        Err(e) => {
+            tracing::warn!("Readiness check {check} failed: {e:#}");
+            "fail".to_string()
+        }
+    };
+    let mut checks = BTreeMap::new();
+    checks.insert(
+        "database".to_string(),
+        outcome("database", state.db.schema_version().await.map(|_| ())),
+    );
+    checks.insert("config".to_string(), outcome("config", state.llm.config().require_api_key()));
+    checks.insert(
+        "shutdown".to_string(),
+        outcome("shutdown", match state.draining.load(Ordering::Relaxed) {
+            true => Err(anyhow::anyhow!("Shutting down")),
+            false => Ok(()),
+        }),
+    );
+    let ready = checks.values().all(|v| v == "ok");
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(ReadyResponse { ready, checks }))
 }
 
 pub async fn chat(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
     Json(req): Json<ChatRequest>,
-) -> Result<Json<ChatResponse>, (StatusCode, String)> {
+) -> Result<Json<ChatResponse>, ApiError> {
     let doc = state
         .db
         .get_document(&id)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .ok_or((StatusCode::NOT_FOUND, "Document not found".to_string()))?;
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
+
+    // An explicit personality wins over an @mention in the message
+    let personality = match req.personality.as_deref() {
+        Some(pid) => Some(
+            llm::get_personality(pid)
+                .ok_or_else(|| ApiError::validation(format!("Unknown personality \"{pid}\"")))?,
+        ),
+        None => llm::mentioned_personality(&req.message),
+    };
 
     let messages = state
         .db
         .get_messages(&id, 50)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .await?;
+
+    let budget = state
+        .db
+        .get_change_budget(&id)
+        .await?;
 
     // Save user message first
     state
         .db
         .add_message(&id, "human", &req.message, req.hover_node_id.as_deref(), None)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .await?;
 
     let (mut reply, updated_tree, updated_edges) = state
         .llm
@@ -68,12 +469,11 @@ pub async fn chat(
             &messages,
             &req.message,
             req.hover_node_id.as_deref(),
+            personality,
+            &budget,
         )
         .await
-        .map_err(|e| {
-            tracing::error!("LLM chat error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("LLM error: {e}"))
-        })?;
+        .map_err(|e| ApiError::llm("LLM chat error", e))?;
 
     // If Claude only used tools and didn't write text, note that the tree changed
     if reply.is_empty() && updated_tree.children.len() != doc.tree.children.len() {
@@ -84,21 +484,22 @@ pub async fn chat(
     if !reply.is_empty() {
         state
             .db
-            .add_message(&id, "assistant", &reply, None, None)
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            .add_message(&id, "assistant", &reply, None, personality.map(|p| p.id))
+            .await?;
     }
 
     // Persist updated tree and edges
     state
         .db
         .update_tree(&id, &updated_tree, &updated_edges)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .await?;
 
     // Auto-generate title if tree has 3+ nodes and no title yet
     let title = maybe_generate_title(&state, &id, &updated_tree).await;
 
     Ok(Json(ChatResponse {
         reply,
+        personality: personality.map(|p| p.id.to_string()),
         tree: updated_tree,
         edges: updated_edges,
         title,
@@ -108,82 +509,163 @@ pub async fn chat(
 pub async fn heartbeat(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> Result<Json<HeartbeatResponse>, (StatusCode, String)> {
+    Query(query): Query<HeartbeatQuery>,
+) -> Result<Json<HeartbeatResponse>, ApiError> {
+    if state.draining.load(Ordering::Relaxed) {
+        return Err(ApiError::new(
+            ErrorCode::ShuttingDown,
+            "Grove is restarting; the next heartbeat will run once it is back",
+        ));
+    }
+    run_heartbeat(&state, &id, query.seed).await.map(Json)
+}
+
+/// One heartbeat tick for `id`: the classic heartbeat, or the personalities
+/// picked by the document's speaker strategy. Shared with `grove heartbeat`.
+pub async fn run_heartbeat(
+    state: &AppState,
+    id: &str,
+    seed: Option<u64>,
+) -> Result<HeartbeatResponse, ApiError> {
     let doc = state
         .db
-        .get_document(&id)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .ok_or((StatusCode::NOT_FOUND, "Document not found".to_string()))?;
+        .get_document(id)
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
+    let archived = doc.archived_at.is_some();
+    let before = llm::collect_node_ids(&doc.tree);
+
+    let result = heartbeat_tick(state, id, doc, seed).await;
+    match &result {
+        Ok(_) if archived => metrics::heartbeat("skipped", 0, 0),
+        Ok(response) => {
+            let after = llm::collect_node_ids(&response.tree);
+            let outcome = if response.changed { "changed" } else { "passed" };
+            metrics::heartbeat(
+                outcome,
+                after.difference(&before).count(),
+                before.difference(&after).count(),
+            );
+        }
+        Err(_) => metrics::heartbeat("error", 0, 0),
+    }
+    result
+}
+
+async fn heartbeat_tick(
+    state: &AppState,
+    id: &str,
+    doc: Document,
+    seed: Option<u64>,
+) -> Result<HeartbeatResponse, ApiError> {
+    // Archived groves are left alone
+    if doc.archived_at.is_some() {
+        return Ok(HeartbeatResponse {
+            thinking: None,
+            tree: doc.tree,
+            edges: doc.edges,
+            changed: false,
+            results: vec![],
+            title: doc.title,
+        });
+    }
 
     let messages = state
         .db
-        .get_messages(&id, 20)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .get_messages(id, 20)
+        .await?;
+
+    let budget = state
+        .db
+        .get_change_budget(id)
+        .await?;
 
     // Check for active personalities
     let active_personality_ids = state
         .db
-        .get_active_personalities(&id)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .get_active_personalities(id)
+        .await?;
 
     if active_personality_ids.is_empty() {
         // No personalities active — use classic heartbeat
         let (thinking, updated_tree, updated_edges, changed) =
-            state.llm.heartbeat(&doc.tree, &doc.edges, &messages).await.map_err(|e| {
-                tracing::error!("Heartbeat LLM error: {}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, format!("LLM error: {e}"))
-            })?;
+            state
+                .llm
+                .heartbeat(&doc.tree, &doc.edges, &messages, &budget)
+                .await
+                .map_err(|e| ApiError::llm("Heartbeat LLM error", e))?;
 
         if changed {
             state
                 .db
-                .update_tree(&id, &updated_tree, &updated_edges)
-                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                .update_tree(id, &updated_tree, &updated_edges)
+                .await?;
         }
 
-        if let Some(ref text) = thinking {
-            if !text.is_empty() {
-                state
-                    .db
-                    .add_message(&id, "assistant", text, None, None)
-                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-            }
+        if let Some(ref text) = thinking
+            && !text.is_empty()
+        {
+            state
+                .db
+                .add_message(id, "assistant", text, None, None)
+                .await?;
         }
 
-        let title = maybe_generate_title(&state, &id, &updated_tree).await;
+        let title = maybe_generate_title(state, id, &updated_tree).await;
 
-        return Ok(Json(HeartbeatResponse {
+        return Ok(HeartbeatResponse {
             thinking,
             tree: updated_tree,
             edges: updated_edges,
             changed,
             results: vec![],
             title,
-        }));
+        });
     }
 
-    // Personality heartbeat: roll dice to pick how many speak
+    // Personality heartbeat: roll dice to pick how many speak, then let the
+    // document's strategy pick who
     let dice_sides = state
         .db
-        .get_dice_sides(&id)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    // Scope rng so it's dropped before any .await (ThreadRng is !Send)
-    let mut selected = {
-        let mut rng = rand::thread_rng();
-        let roll: u32 = rng.gen_range(1..=dice_sides);
-        let count = (roll as usize).min(active_personality_ids.len());
-        let mut sel = active_personality_ids.clone();
-        sel.shuffle(&mut rng);
-        sel.truncate(count);
-        sel
+        .get_dice_sides(id)
+        .await?;
+    let (strategy, cursor) = state
+        .db
+        .get_speaker_strategy(id)
+        .await?;
+    let weights = state
+        .db
+        .get_personality_weights(id)
+        .await?;
+    let last_spoke = state
+        .db
+        .get_last_spoke(id)
+        .await?;
+
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
     };
+    let input = SelectionInput {
+        active: &active_personality_ids,
+        weights: &weights,
+        cursor,
+        last_spoke: &last_spoke,
+        tree: &doc.tree,
+    };
+    let (mut selected, next_cursor) = select_speakers(strategy, dice_sides, &input, &mut rng);
+    if next_cursor != cursor {
+        state
+            .db
+            .set_speaker_cursor(id, next_cursor)
+            .await?;
+    }
 
     // Merge reserved agents (bonus slots from ask_agent questions)
     let reserved = state
         .db
-        .get_reserved_agents(&id)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .get_reserved_agents(id)
+        .await?;
     for agent in &reserved {
         if !selected.contains(agent) && active_personality_ids.contains(agent) {
             selected.push(agent.clone());
@@ -191,12 +673,12 @@ pub async fn heartbeat(
     }
 
     // Build per-agent pending questions map
-    let mut questions_map: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    let mut questions_map: HashMap<String, Vec<(i64, String, String)>> = HashMap::new();
     for agent_id in &selected {
         let pending = state
             .db
-            .get_pending_questions_for(&id, agent_id)
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            .get_pending_questions_for(id, agent_id)
+            .await?;
         if !pending.is_empty() {
             questions_map.insert(agent_id.clone(), pending);
         }
@@ -211,40 +693,50 @@ pub async fn heartbeat(
     if personalities.is_empty() {
         // All selected IDs were invalid — fall back to classic
         let (thinking, updated_tree, updated_edges, changed) =
-            state.llm.heartbeat(&doc.tree, &doc.edges, &messages).await.map_err(|e| {
-                tracing::error!("Heartbeat LLM error: {}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, format!("LLM error: {e}"))
-            })?;
+            state
+                .llm
+                .heartbeat(&doc.tree, &doc.edges, &messages, &budget)
+                .await
+                .map_err(|e| ApiError::llm("Heartbeat LLM error", e))?;
 
         if changed {
             state
                 .db
-                .update_tree(&id, &updated_tree, &updated_edges)
-                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                .update_tree(id, &updated_tree, &updated_edges)
+                .await?;
         }
 
-        let title = maybe_generate_title(&state, &id, &updated_tree).await;
+        let title = maybe_generate_title(state, id, &updated_tree).await;
 
-        return Ok(Json(HeartbeatResponse {
+        return Ok(HeartbeatResponse {
             thinking,
             tree: updated_tree,
             edges: updated_edges,
             changed,
             results: vec![],
             title,
-        }));
+        });
     }
 
     // Fire parallel personality heartbeats
     let original_ids = llm::collect_node_ids(&doc.tree);
     let original_edges = doc.edges.clone();
+    let prompt_questions: HashMap<&str, Vec<(String, String)>> = questions_map
+        .iter()
+        .map(|(agent, qs)| {
+            let pairs = qs.iter().map(|(_, from, q)| (from.clone(), q.clone())).collect();
+            (agent.as_str(), pairs)
+        })
+        .collect();
     let empty_questions: Vec<(String, String)> = Vec::new();
 
     let futures: Vec<_> = personalities
         .iter()
         .map(|p| {
-            let pq = questions_map.get(p.id).unwrap_or(&empty_questions);
-            state.llm.personality_heartbeat(&doc.tree, &doc.edges, &messages, p, pq)
+            let pq = prompt_questions.get(p.id).unwrap_or(&empty_questions);
+            state
+                .llm
+                .personality_heartbeat(&doc.tree, &doc.edges, &messages, p, pq, &budget)
         })
         .collect();
 
@@ -261,10 +753,24 @@ pub async fn heartbeat(
     for (i, outcome) in outcomes.into_iter().enumerate() {
         let personality = personalities[i];
         match outcome {
-            Ok((thinking, result_tree, result_edges, changed, questions)) => {
+            Ok((result_tree, result_edges, result)) => {
+                let changed = result.changed;
+                let thinking = if result.text.is_empty() { None } else { Some(result.text) };
                 if changed {
                     any_changed = true;
-                    llm::merge_tree_additions(&mut merged_tree, &result_tree, &original_ids);
+                    let dropped = llm::merge_tree_additions(
+                        &mut merged_tree,
+                        &result_tree,
+                        &original_ids,
+                        budget.max_tree_size as usize,
+                    );
+                    if dropped > 0 {
+                        tracing::info!(
+                            "Dropped {} node(s) from {}: tree is at its size limit",
+                            dropped,
+                            personality.id
+                        );
+                    }
                     llm::merge_edges(&mut merged_edges, &result_edges, &original_edges);
                 }
 
@@ -272,21 +778,34 @@ pub async fn heartbeat(
                     all_thinking_parts.push(format!("**{}**: {}", personality.name, text));
                 }
 
-                // Save personality message
-                if let Some(ref text) = thinking {
-                    if !text.is_empty() {
-                        let _ = state.db.add_message(
-                            &id,
-                            "assistant",
-                            text,
-                            None,
-                            Some(personality.id),
-                        );
+                // Save personality message; it answers any questions put to this agent
+                if let Some(ref text) = thinking
+                    && !text.is_empty()
+                {
+                    let message_id = state
+                        .db
+                        .add_message(id, "assistant", text, None, Some(personality.id))
+                        .await
+                        .ok();
+                    if let Some(pending) = questions_map.get(personality.id) {
+                        let ids: Vec<i64> = pending.iter().map(|(qid, _, _)| *qid).collect();
+                        let _ = state.db.answer_agent_questions(&ids, message_id).await;
                     }
                 }
 
+                // Post this agent's comments to node threads
+                for (node_id, comment) in &result.comments {
+                    let _ = state.db.add_thread_message(
+                        id,
+                        node_id,
+                        "assistant",
+                        comment,
+                        Some(personality.id),
+                    ).await;
+                }
+
                 // Collect outgoing questions from this agent
-                for (to_agent, question) in questions {
+                for (to_agent, question) in result.questions {
                     outgoing_questions.push((personality.id, to_agent, question));
                 }
 
@@ -310,20 +829,20 @@ pub async fn heartbeat(
     if any_changed {
         state
             .db
-            .update_tree(&id, &merged_tree, &merged_edges)
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            .update_tree(id, &merged_tree, &merged_edges)
+            .await?;
     }
 
-    // Expire questions that were consumed this tick
-    let _ = state.db.expire_agent_questions(&id);
+    // Unanswered questions carry over, until they have waited too long
+    let _ = state.db.age_agent_questions(id, MAX_QUESTION_AGE).await;
 
     // Insert outgoing questions for next heartbeat
     if !outgoing_questions.is_empty() {
-        let q_refs: Vec<(&str, &str, &str)> = outgoing_questions
-            .iter()
-            .map(|(from, to, q)| (*from, to.as_str(), q.as_str()))
+        let questions = outgoing_questions
+            .into_iter()
+            .map(|(from, to, q)| (from.to_string(), to, q))
             .collect();
-        let _ = state.db.insert_agent_questions(&id, &q_refs);
+        let _ = state.db.insert_agent_questions(id, questions).await;
     }
 
     let combined_thinking = if all_thinking_parts.is_empty() {
@@ -332,36 +851,37 @@ pub async fn heartbeat(
         Some(all_thinking_parts.join("\n\n"))
     };
 
-    let title = maybe_generate_title(&state, &id, &merged_tree).await;
+    let title = maybe_generate_title(state, id, &merged_tree).await;
 
-    Ok(Json(HeartbeatResponse {
+    Ok(HeartbeatResponse {
         thinking: combined_thinking,
         tree: merged_tree,
         edges: merged_edges,
         changed: any_changed,
         results: per_personality_results,
         title,
-    }))
+    })
 }
 
 pub async fn mark_seen(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
     Json(req): Json<MarkSeenRequest>,
-) -> Result<StatusCode, (StatusCode, String)> {
+) -> Result<StatusCode, ApiError> {
     let doc = state
         .db
         .get_document(&id)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .ok_or((StatusCode::NOT_FOUND, "Document not found".to_string()))?;
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
 
     let mut tree = doc.tree;
-    if tree.mark_seen(&req.node_id) {
-        state
-            .db
-            .update_tree(&id, &tree, &doc.edges)
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !tree.mark_seen(&req.node_id) {
+        return Err(ApiError::not_found(format!("Node \"{}\" not found", req.node_id)));
     }
+    state
+        .db
+        .set_tree_seen(&id, &tree)
+        .await?;
 
     Ok(StatusCode::NO_CONTENT)
 }
@@ -369,32 +889,298 @@ pub async fn mark_seen(
 pub async fn get_messages(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> Result<Json<MessagesResponse>, StatusCode> {
-    let messages = state
+    Query(query): Query<MessagesQuery>,
+) -> Result<Json<MessagesResponse>, ApiError> {
+    for (name, value) in [("since", &query.since), ("until", &query.until)] {
+        if let Some(value) = value {
+            validate::validate_timestamp(name, value).map_err(ApiError::validation)?;
+        }
+    }
+    let limit = query.limit.unwrap_or(100).clamp(1, 500);
+    let (messages, has_more) = state
+        .db
+        .query_messages(&id, &query, limit)
+        .await?;
+    let next_before = if has_more {
+        messages.first().map(|m| m.id)
+    } else {
+        None
+    };
+    Ok(Json(MessagesResponse {
+        messages,
+        next_before,
+    }))
+}
+
+pub async fn get_questions(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<QuestionsQuery>,
+) -> Result<Json<QuestionsResponse>, ApiError> {
+    if let Some(ref status) = query.status
+        && !["pending", "answered", "expired"].contains(&status.as_str())
+    {
+        return Err(ApiError::validation(format!(
+            "Unknown status \"{status}\": expected pending, answered or expired"
+        )));
+    }
+    let questions = state
         .db
-        .get_messages(&id, 100)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(Json(MessagesResponse { messages }))
+        .list_agent_questions(&id, query.status.as_deref())
+        .await?;
+    Ok(Json(QuestionsResponse { questions }))
+}
+
+pub async fn get_thread(
+    State(state): State<Arc<AppState>>,
+    Path((id, node_id)): Path<(String, String)>,
+    Query(mut query): Query<MessagesQuery>,
+) -> Result<Json<MessagesResponse>, ApiError> {
+    query.thread_node_id = Some(node_id);
+    get_messages(State(state), Path(id), Query(query)).await
+}
+
+pub async fn post_thread(
+    State(state): State<Arc<AppState>>,
+    Path((id, node_id)): Path<(String, String)>,
+    Json(req): Json<ThreadPostRequest>,
+) -> Result<Json<ThreadPostResponse>, ApiError> {
+    let doc = state
+        .db
+        .get_document(&id)
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
+
+    if doc.tree.find(&node_id).is_none() {
+        return Err(ApiError::not_found(format!("Node \"{node_id}\" not found")));
+    }
+    let personality = match req.personality.as_deref() {
+        Some(pid) => Some(
+            llm::get_personality(pid)
+                .ok_or_else(|| ApiError::validation(format!("Unknown personality \"{pid}\"")))?,
+        ),
+        None => None,
+    };
+
+    let thread_query = MessagesQuery {
+        thread_node_id: Some(node_id.clone()),
+        ..Default::default()
+    };
+    let (thread, _) = state
+        .db
+        .query_messages(&id, &thread_query, 50)
+        .await?;
+
+    let budget = state
+        .db
+        .get_change_budget(&id)
+        .await?;
+
+    state
+        .db
+        .add_thread_message(&id, &node_id, "human", &req.message, None)
+        .await?;
+
+    let (reply, updated_tree, updated_edges) = state
+        .llm
+        .thread_reply(
+            &doc.tree,
+            &doc.edges,
+            &node_id,
+            &thread,
+            &req.message,
+            personality,
+            &budget,
+        )
+        .await
+        .map_err(|e| ApiError::llm("LLM thread error", e))?;
+
+    if !reply.is_empty() {
+        state
+            .db
+            .add_thread_message(&id, &node_id, "assistant", &reply, personality.map(|p| p.id))
+            .await?;
+    }
+
+    state
+        .db
+        .update_tree(&id, &updated_tree, &updated_edges)
+        .await?;
+
+    let (messages, _) = state
+        .db
+        .query_messages(&id, &thread_query, 100)
+        .await?;
+
+    Ok(Json(ThreadPostResponse {
+        reply,
+        tree: updated_tree,
+        edges: updated_edges,
+        messages,
+    }))
+}
+
+/// Have two or more personas argue over a node in turns, then close with a
+/// synthesis. The debate lands as one subtree under the focal node.
+pub async fn debate(
+    State(state): State<Arc<AppState>>,
+    Path((id, node_id)): Path<(String, String)>,
+    Json(req): Json<DebateRequest>,
+) -> Result<Json<DebateResponse>, ApiError> {
+    let doc = state
+        .db
+        .get_document(&id)
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
+
+    if doc.tree.find(&node_id).is_none() {
+        return Err(ApiError::not_found(format!("Node \"{node_id}\" not found")));
+    }
+
+    let rounds = req.rounds.unwrap_or(debate::DEFAULT_ROUNDS);
+    if !(1..=debate::MAX_ROUNDS).contains(&rounds) {
+        return Err(ApiError::validation(format!(
+            "rounds must be between 1 and {}",
+            debate::MAX_ROUNDS
+        )));
+    }
+    let mut debaters: Vec<&llm::Personality> = Vec::new();
+    for pid in &req.personality_ids {
+        let p = llm::get_personality(pid)
+            .ok_or_else(|| ApiError::validation(format!("Unknown personality \"{pid}\"")))?;
+        if debaters.iter().any(|d| d.id == p.id) {
+            return Err(ApiError::validation(format!("Personality \"{pid}\" listed twice")));
+        }
+        debaters.push(p);
+    }
+    if !(2..=debate::MAX_DEBATERS).contains(&debaters.len()) {
+        return Err(ApiError::validation(format!(
+            "A debate needs between 2 and {} personalities",
+            debate::MAX_DEBATERS
+        )));
+    }
+
+    let budget = state
+        .db
+        .get_change_budget(&id)
+        .await?;
+    let needed = debate::Debate::nodes_needed(debaters.len(), rounds);
+    let room = (budget.max_tree_size as usize).saturating_sub(count_nodes(&doc.tree));
+    if needed > room {
+        return Err(ApiError::budget_exceeded(format!(
+            "This debate adds {needed} nodes but the tree only has room for {room}"
+        )));
+    }
+
+    let mut debate = debate::Debate::new(&doc.tree, &node_id);
+    for round in 1..=rounds {
+        for p in &debaters {
+            let opponents: Vec<&llm::Personality> =
+                debaters.iter().filter(|o| o.id != p.id).copied().collect();
+            let turn = state
+                .llm
+                .debate_turn(&doc.tree, &doc.edges, &debate, p, &opponents, round, rounds)
+                .await
+                .map_err(|e| ApiError::llm(&format!("Debate turn error ({})", p.id), e))?;
+            debate.add_point(p, round, turn);
+        }
+    }
+    let closing = state
+        .llm
+        .debate_synthesis(&doc.tree, &doc.edges, &debate)
+        .await
+        .map_err(|e| ApiError::llm("Debate synthesis error", e))?;
+
+    // The turns took minutes; write onto the document as it is now so
+    // anything saved meanwhile (a heartbeat, a chat edit) survives
+    let doc = state
+        .db
+        .get_document(&id)
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
+    if doc.tree.find(&node_id).is_none() {
+        return Err(ApiError::conflict(format!(
+            "Node \"{node_id}\" was deleted while the debate ran"
+        )));
+    }
+    if doc.tree.find(&debate.root_id).is_some() {
+        debate.rename_root(&doc.tree);
+    }
+    let mut tree = doc.tree;
+    let mut edges = doc.edges;
+    let synthesis = debate.write_into(&mut tree, &mut edges, &debaters, closing);
+
+    state
+        .db
+        .update_tree(&id, &tree, &edges)
+        .await?;
+
+    // The exchange also reads as a conversation in the focal node's thread
+    for point in &debate.points {
+        state
+            .db
+            .add_thread_message(
+                &id,
+                &node_id,
+                "assistant",
+                &format!("**{}**\n\n{}", point.label, point.prose),
+                Some(&point.personality),
+            )
+            .await?;
+    }
+    state
+        .db
+        .add_thread_message(
+            &id,
+            &node_id,
+            "assistant",
+            &format!("**{}**\n\n{}", synthesis.label, synthesis.prose),
+            None,
+        )
+        .await?;
+
+    Ok(Json(DebateResponse {
+        debate_id: debate.root_id,
+        points: debate.points,
+        synthesis,
+        tree,
+        edges,
+    }))
 }
 
 pub async fn get_personalities(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> Result<Json<PersonalitiesResponse>, (StatusCode, String)> {
+) -> Result<Json<PersonalitiesResponse>, ApiError> {
     let active = state
         .db
         .get_active_personalities(&id)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .await?;
 
     let dice_sides = state
         .db
         .get_dice_sides(&id)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .await?;
 
     let repel_force = state
         .db
         .get_repel_force(&id)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .await?;
+
+    let budget = state
+        .db
+        .get_change_budget(&id)
+        .await?;
+
+    let (speaker_strategy, _) = state
+        .db
+        .get_speaker_strategy(&id)
+        .await?;
+
+    let weights = state
+        .db
+        .get_personality_weights(&id)
+        .await?;
 
     let available: Vec<PersonalityInfo> = llm::PERSONALITIES
         .iter()
@@ -412,6 +1198,9 @@ pub async fn get_personalities(
         active,
         dice_sides,
         repel_force,
+        budget,
+        speaker_strategy,
+        weights,
     }))
 }
 
@@ -419,11 +1208,17 @@ pub async fn set_personalities(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
     Json(req): Json<SetPersonalitiesRequest>,
-) -> Result<StatusCode, (StatusCode, String)> {
+) -> Result<StatusCode, ApiError> {
+    for weight in req.weights.values() {
+        validate::validate_weight(*weight).map_err(ApiError::validation)?;
+    }
+    for pid in &req.personality_ids {
+        validate::validate_personality_id(pid).map_err(ApiError::validation)?;
+    }
     state
         .db
-        .set_personalities(&id, &req.personality_ids)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .set_personalities(&id, &req.personality_ids, &req.weights)
+        .await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -431,19 +1226,17 @@ pub async fn update_settings(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
     Json(req): Json<UpdateSettingsRequest>,
-) -> Result<StatusCode, (StatusCode, String)> {
+) -> Result<StatusCode, ApiError> {
     if let Some(sides) = req.dice_sides {
-        state
-            .db
-            .set_dice_sides(&id, sides)
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        validate::validate_dice_sides(sides).map_err(ApiError::validation)?;
     }
     if let Some(force) = req.repel_force {
-        state
-            .db
-            .set_repel_force(&id, force)
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        validate::validate_repel_force(force).map_err(ApiError::validation)?;
     }
+    state
+        .db
+        .update_settings(&id, req)
+        .await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -451,34 +1244,70 @@ pub async fn get_summary(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
     Json(req): Json<SummaryRequest>,
-) -> Result<Json<SummaryResponse>, (StatusCode, String)> {
+) -> Result<Json<SummaryResponse>, ApiError> {
+    summarize_doc(&state, &id, req).await.map(Json)
+}
+
+/// The cached summary for `req`, regenerated when missing or forced. Shared
+/// with `grove summary`.
+pub async fn summarize_doc(
+    state: &AppState,
+    id: &str,
+    req: SummaryRequest,
+) -> Result<SummaryResponse, ApiError> {
     let doc = state
         .db
-        .get_document(&id)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .ok_or((StatusCode::NOT_FOUND, "Document not found".to_string()))?;
+        .get_document(id)
+        .await?
+        .ok_or_else(ApiError::document_not_found)?;
 
     let voice = req.voice.as_deref().unwrap_or("claude");
     let force_refresh = req.force_refresh.unwrap_or(false);
+    let format = req.format;
+
+    // Scope to one branch and the edges touching it, if asked
+    let branch_path = match req.node_id.as_deref() {
+        Some(node_id) => Some(
+            doc.tree
+                .path_to(node_id)
+                .ok_or_else(|| ApiError::not_found(format!("Node \"{node_id}\" not found")))?,
+        ),
+        None => None,
+    };
+    let (scope_tree, scope_edges): (&TreeNode, Vec<Edge>) = match branch_path.as_deref() {
+        Some([.., branch]) => {
+            let ids = llm::collect_node_ids(branch);
+            let touching = doc
+                .edges
+                .iter()
+                .filter(|e| ids.contains(&e.source) || ids.contains(&e.target))
+                .cloned()
+                .collect();
+            (branch, touching)
+        }
+        _ => (&doc.tree, doc.edges.clone()),
+    };
+    let scope_key = req.node_id.as_deref().unwrap_or("");
 
-    // Hash the tree JSON for staleness detection
-    let tree_json = serde_json::to_string(&doc.tree)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    let tree_hash = format!("{:x}", md5::compute(&tree_json));
+    // Hash the tree and edges in scope for staleness detection
+    let scope_json =
+        serde_json::to_string(&(scope_tree, &scope_edges)).map_err(anyhow::Error::from)?;
+    let tree_hash = format!("{:x}", md5::compute(&scope_json));
 
     // Check cache
-    if !force_refresh {
-        if let Some((content, cached_hash)) = state
+    if !force_refresh
+        && let Some((content, cached_hash)) = state
             .db
-            .get_summary(&id, voice)
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        {
-            return Ok(Json(SummaryResponse {
-                content,
-                voice: voice.to_string(),
-                stale: cached_hash != tree_hash,
-            }));
-        }
+            .get_summary(id, voice, scope_key, format.as_str())
+            .await?
+    {
+        return Ok(SummaryResponse {
+            content,
+            voice: voice.to_string(),
+            node_id: req.node_id,
+            format,
+            stale: cached_hash != tree_hash,
+        });
     }
 
     // Generate summary via LLM
@@ -490,35 +1319,40 @@ pub async fn get_summary(
 
     let content = state
         .llm
-        .summarize(&doc.tree, &doc.edges, personality)
+        .summarize(
+            scope_tree,
+            &scope_edges,
+            branch_path.as_deref(),
+            format,
+            personality,
+        )
         .await
-        .map_err(|e| {
-            tracing::error!("Summary LLM error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("LLM error: {e}"))
-        })?;
+        .map_err(|e| ApiError::llm("Summary LLM error", e))?;
 
     // Cache the result
     state
         .db
-        .save_summary(&id, voice, &content, &tree_hash)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .save_summary(id, voice, scope_key, format.as_str(), &content, &tree_hash)
+        .await?;
 
-    Ok(Json(SummaryResponse {
+    Ok(SummaryResponse {
         content,
         voice: voice.to_string(),
+        node_id: req.node_id,
+        format,
         stale: false,
-    }))
+    })
 }
 
 /// If tree has 3+ nodes and no title exists yet, generate one and save it.
 /// Returns the title (existing or newly generated) if available.
 async fn maybe_generate_title(
-    state: &Arc<AppState>,
+    state: &AppState,
     doc_id: &str,
     tree: &crate::models::TreeNode,
 ) -> Option<String> {
     // Check if title already exists
-    if let Ok(Some(title)) = state.db.get_title(doc_id) {
+    if let Ok(Some(title)) = state.db.get_title(doc_id).await {
         return Some(title);
     }
     // Only generate once tree has 3+ nodes
@@ -527,7 +1361,7 @@ async fn maybe_generate_title(
     }
     match state.llm.generate_title(tree).await {
         Ok(title) => {
-            let _ = state.db.set_title(doc_id, &title);
+            let _ = state.db.set_title(doc_id, &title).await;
             Some(title)
         }
         Err(e) => {
@@ -537,7 +1371,7 @@ async fn maybe_generate_title(
     }
 }
 
-fn generate_short_id() -> String {
+pub(crate) fn generate_short_id() -> String {
     // Generate a short, URL-friendly ID (8 chars from uuid)
     uuid::Uuid::new_v4().to_string()[..8].to_string()
 }
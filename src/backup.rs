@@ -0,0 +1,241 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+
+use crate::api::AppState;
+use crate::config::BackupConfig;
+use crate::db::Db;
+use crate::migrations;
+use crate::models::BackupInfo;
+
+/// Backups in the rotating directory are named `grove-<UTC time>.db`, so
+/// sorting by name sorts by age.
+const PREFIX: &str = "grove-";
+const SUFFIX: &str = ".db";
+
+/// The copy `restore` takes of the database it replaces. The name doesn't
+/// start with `PREFIX`, so rotation never deletes it.
+const SAFETY_PREFIX: &str = "pre-restore-";
+
+/// Back up into the rotating directory, then delete all but the newest
+/// `keep` backups.
+pub fn backup_now(db: &Db, config: &BackupConfig) -> anyhow::Result<BackupInfo> {
+    let info = backup_named(db, config, "")?;
+    rotate(Path::new(&config.dir), config.keep)?;
+    Ok(info)
+}
+
+fn backup_named(db: &Db, config: &BackupConfig, tag: &str) -> anyhow::Result<BackupInfo> {
+    std::fs::create_dir_all(&config.dir)
+        .with_context(|| format!("Failed to create backup directory {}", config.dir))?;
+    let path = Path::new(&config.dir).join(format!("{PREFIX}{}{tag}{SUFFIX}", utc_stamp(SystemTime::now())));
+    db.backup_to(&path)?;
+    Ok(BackupInfo {
+        file: path.display().to_string(),
+        bytes: std::fs::metadata(&path)?.len(),
+    })
+}
+
+/// Every backup in `dir`, oldest first.
+pub fn list_backups(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(PREFIX) && n.ends_with(SUFFIX))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn rotate(dir: &Path, keep: usize) -> anyhow::Result<()> {
+    let files = list_backups(dir)?;
+    for old in &files[..files.len().saturating_sub(keep.max(1))] {
+        std::fs::remove_file(old).with_context(|| format!("Failed to remove old backup {}", old.display()))?;
+        tracing::info!("Removed old backup {}", old.display());
+    }
+    Ok(())
+}
+
+/// Replace the database at `db_path` with the backup at `file`. The backup
+/// must pass `PRAGMA integrity_check` and be from a schema this build knows.
+/// The current database, if any, is first copied as it stands, unmigrated,
+/// to `pre-restore-<UTC time>.db` in the backup directory, where rotation
+/// leaves it alone. Run with the server stopped.
+pub fn restore(db_path: &str, file: &Path, config: &BackupConfig) -> anyhow::Result<Option<BackupInfo>> {
+    check_backup(file)?;
+    let safety = if Path::new(db_path).exists() {
+        Some(safety_copy(db_path, config)?)
+    } else {
+        None
+    };
+    let mut conn = Connection::open(db_path)?;
+    conn.restore(DatabaseName::Main, file, None::<fn(rusqlite::backup::Progress)>)
+        .with_context(|| format!("Failed to restore {}", file.display()))?;
+    drop(conn);
+    // Bring an older backup up to the current schema
+    Db::new(db_path)?;
+    Ok(safety)
+}
+
+fn safety_copy(db_path: &str, config: &BackupConfig) -> anyhow::Result<BackupInfo> {
+    std::fs::create_dir_all(&config.dir)
+        .with_context(|| format!("Failed to create backup directory {}", config.dir))?;
+    let path = Path::new(&config.dir).join(format!(
+        "{SAFETY_PREFIX}{}{SUFFIX}",
+        utc_stamp(SystemTime::now())
+    ));
+    // A plain connection, not `Db::new`, so no migration runs before the copy
+    Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?
+        .backup(DatabaseName::Main, &path, None)
+        .with_context(|| format!("Failed to copy {db_path} to {}", path.display()))?;
+    Ok(BackupInfo {
+        file: path.display().to_string(),
+        bytes: std::fs::metadata(&path)?.len(),
+    })
+}
+
+fn check_backup(file: &Path) -> anyhow::Result<()> {
+    if !file.exists() {
+        anyhow::bail!("{} does not exist", file.display());
+    }
+    let conn = Connection::open_with_flags(file, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let problems: Vec<String> = conn
+        .prepare("PRAGMA integrity_check")
+        .and_then(|mut stmt| stmt.query_map([], |row| row.get(0))?.collect())
+        .with_context(|| format!("{} is not a readable SQLite database", file.display()))?;
+    if problems != ["ok"] {
+        anyhow::bail!("{} failed its integrity check: {}", file.display(), problems.join("; "));
+    }
+    let version = migrations::current_version(&conn)?;
+    if version > migrations::latest_version() {
+        anyhow::bail!(
+            "{} is at schema version {version}, newer than this build ({})",
+            file.display(),
+            migrations::latest_version()
+        );
+    }
+    let has_documents = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'documents'")?
+        .exists([])?;
+    if !has_documents {
+        anyhow::bail!("{} is not a grove database", file.display());
+    }
+    Ok(())
+}
+
+/// Back up every `interval_hours` until the process exits.
+pub async fn run_schedule(state: Arc<AppState>) {
+    let hours = state.backup.interval_hours;
+    if hours == 0 {
+        return;
+    }
+    let period = Duration::from_secs(hours * 3600);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+        let state = state.clone();
+        let result = tokio::task::spawn_blocking(move || backup_now(&state.db, &state.backup)).await;
+        match result {
+            Ok(Ok(info)) => tracing::info!("Backed up to {} ({} bytes)", info.file, info.bytes),
+            Ok(Err(e)) => tracing::error!("Scheduled backup failed: {:#}", e),
+            Err(e) => tracing::error!("Scheduled backup panicked: {}", e),
+        }
+    }
+}
+
+/// `YYYYMMDD-HHMMSS` in UTC.
+fn utc_stamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamps_sort_by_time() {
+        let at = |secs| utc_stamp(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "19700101-000000");
+        assert_eq!(at(951_782_400), "20000229-000000");
+        assert_eq!(at(1_791_000_000), "20261003-040000");
+        assert!(at(1_791_000_000) < at(1_791_000_001));
+    }
+
+    #[tokio::test]
+    async fn backup_rotates_and_restore_checks_integrity() {
+        let dir = std::env::temp_dir().join(format!("grove-backup-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("grove.db").display().to_string();
+        let config = BackupConfig {
+            dir: dir.join("backups").display().to_string(),
+            interval_hours: 0,
+            keep: 2,
+        };
+
+        let db = Db::new(&db_path).unwrap();
+        db.create_document("kept").await.unwrap();
+        let first = backup_now(&db, &config).unwrap();
+        for n in 0..3 {
+            // Distinct names without waiting out the clock
+            backup_named(&db, &config, &format!("-{n}")).unwrap();
+            rotate(Path::new(&config.dir), config.keep).unwrap();
+        }
+        assert_eq!(list_backups(Path::new(&config.dir)).unwrap().len(), 2);
+
+        let bogus = dir.join("bogus.db");
+        std::fs::write(&bogus, b"not a database at all").unwrap();
+        assert!(restore(&db_path, &bogus, &config).is_err());
+
+        let good = list_backups(Path::new(&config.dir)).unwrap().pop().unwrap();
+        db.create_document("dropped").await.unwrap();
+        drop(db);
+        let safety = restore(&db_path, &good, &config).unwrap().unwrap();
+        let db = Db::new(&db_path).unwrap();
+        assert!(db.get_document("kept").await.unwrap().is_some());
+        assert!(db.get_document("dropped").await.unwrap().is_none());
+        assert!(first.bytes > 0);
+
+        // Later backups rotate out everything but the safety copy
+        for n in 3..6 {
+            backup_named(&db, &config, &format!("-{n}")).unwrap();
+            rotate(Path::new(&config.dir), config.keep).unwrap();
+        }
+        assert_eq!(list_backups(Path::new(&config.dir)).unwrap().len(), 2);
+        let saved = Connection::open(&safety.file).unwrap();
+        let dropped: bool = saved
+            .prepare("SELECT 1 FROM documents WHERE id = 'dropped'")
+            .unwrap()
+            .exists([])
+            .unwrap();
+        assert!(dropped);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,343 @@
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+
+use anyhow::Context;
+
+use crate::api::{self, AppState};
+use crate::backup;
+use crate::config::Config;
+use crate::db::Db;
+use crate::llm::{count_nodes, LlmClient};
+use crate::models::{DocumentBundle, SummaryFormat, SummaryRequest, BUNDLE_VERSION};
+use crate::validate;
+
+pub const USAGE: &str = "Usage: grove [command]
+
+Commands:
+  serve                      Run the web server (the default)
+  list                       List documents, most recently updated first
+  export <id> [-o <file>]    Write a document bundle (the document, its
+                             messages, personas, settings and revisions)
+                             as JSON to stdout or a file
+  import <file>              Create a document from an exported bundle
+  heartbeat <id> [--seed N]  Run one heartbeat tick
+  summary <id> [--format F] [--voice V] [--node N] [--refresh]
+                             Print a summary, regenerating it if needed
+  migrate                    Bring the database schema up to date
+  backup [<file>]            Copy the database while the server keeps running,
+                             into the rotating backup directory by default
+  restore <file>             Replace the database with a backup after checking
+                             its integrity (stop the server first)
+  help                       Show this message
+
+Settings come from grove.toml and the environment; see grove.example.toml.";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Serve,
+    List,
+    Export { id: String, out: Option<String> },
+    Import { file: String },
+    Heartbeat { id: String, seed: Option<u64> },
+    Summary { id: String, req: SummaryArgs },
+    Migrate,
+    Backup { path: Option<String> },
+    Restore { file: String },
+    Help,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SummaryArgs {
+    pub format: SummaryFormat,
+    pub voice: Option<String>,
+    pub node_id: Option<String>,
+    pub refresh: bool,
+}
+
+impl Command {
+    /// Parse the arguments after the program name.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut args = args.iter().map(String::as_str);
+        let command = args.next().unwrap_or("serve");
+        let mut positional = Vec::new();
+        let mut flags: Vec<(&str, Option<&str>)> = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg {
+                "--refresh" => flags.push((arg, None)),
+                "-o" | "--out" | "--seed" | "--format" | "--voice" | "--node" => {
+                    let value = args.next().ok_or_else(|| format!("{arg} needs a value"))?;
+                    flags.push((arg, Some(value)));
+                }
+                _ if arg.starts_with('-') => return Err(format!("Unknown option {arg}")),
+                _ => positional.push(arg.to_string()),
+            }
+        }
+        let allow = |allowed: &[&str]| match flags.iter().find(|(f, _)| !allowed.contains(f)) {
+            Some((f, _)) => Err(format!("{f} does not apply to {command}")),
+            None => Ok(()),
+        };
+        let flag = |names: &[&str]| {
+            flags
+                .iter()
+                .rev()
+                .find(|(f, _)| names.contains(f))
+                .and_then(|(_, v)| v.map(str::to_string))
+        };
+        let one = |what: &str, positional: Vec<String>| -> Result<String, String> {
+            match <[String; 1]>::try_from(positional) {
+                Ok([value]) => Ok(value),
+                Err(_) => Err(format!("{command} takes exactly one {what}")),
+            }
+        };
+        let none = |positional: &[String]| match positional {
+            [] => Ok(()),
+            [extra, ..] => Err(format!("Unexpected argument {extra}")),
+        };
+
+        match command {
+            "serve" => {
+                allow(&[])?;
+                none(&positional)?;
+                Ok(Self::Serve)
+            }
+            "list" => {
+                allow(&[])?;
+                none(&positional)?;
+                Ok(Self::List)
+            }
+            "export" => {
+                allow(&["-o", "--out"])?;
+                Ok(Self::Export {
+                    id: one("document id", positional)?,
+                    out: flag(&["-o", "--out"]),
+                })
+            }
+            "import" => {
+                allow(&[])?;
+                Ok(Self::Import {
+                    file: one("file", positional)?,
+                })
+            }
+            "heartbeat" => {
+                allow(&["--seed"])?;
+                let seed = match flag(&["--seed"]) {
+                    Some(s) => Some(s.parse().map_err(|_| format!("Invalid seed {s}"))?),
+                    None => None,
+                };
+                Ok(Self::Heartbeat {
+                    id: one("document id", positional)?,
+                    seed,
+                })
+            }
+            "summary" => {
+                allow(&["--format", "--voice", "--node", "--refresh"])?;
+                let format = match flag(&["--format"]) {
+                    Some(f) => serde_json::from_value(serde_json::Value::String(f.clone()))
+                        .map_err(|_| format!("Unknown summary format {f}"))?,
+                    None => SummaryFormat::default(),
+                };
+                Ok(Self::Summary {
+                    id: one("document id", positional)?,
+                    req: SummaryArgs {
+                        format,
+                        voice: flag(&["--voice"]),
+                        node_id: flag(&["--node"]),
+                        refresh: flags.iter().any(|(f, _)| *f == "--refresh"),
+                    },
+                })
+            }
+            "migrate" => {
+                allow(&[])?;
+                none(&positional)?;
+                Ok(Self::Migrate)
+            }
+            "backup" => {
+                allow(&[])?;
+                if positional.len() > 1 {
+                    return Err("backup takes at most one file".to_string());
+                }
+                Ok(Self::Backup {
+                    path: positional.pop(),
+                })
+            }
+            "restore" => {
+                allow(&[])?;
+                Ok(Self::Restore {
+                    file: one("backup file", positional)?,
+                })
+            }
+            "help" | "-h" | "--help" => Ok(Self::Help),
+            other => Err(format!("Unknown command {other}")),
+        }
+    }
+}
+
+/// Run an ops command against the database and model client directly.
+pub async fn run(command: Command, config: &Config) -> anyhow::Result<()> {
+    match command {
+        Command::Serve | Command::Help => unreachable!("handled by main"),
+        Command::List => {
+            let db = Db::new(&config.db_path)?;
+            for doc in db.list_documents().await? {
+                let archived = if doc.archived_at.is_some() { "  (archived)" } else { "" };
+                println!(
+                    "{}  {}  {}{}",
+                    doc.id,
+                    doc.updated_at,
+                    doc.title.as_deref().unwrap_or("(untitled)"),
+                    archived
+                );
+            }
+        }
+        Command::Export { id, out } => {
+            let db = Db::new(&config.db_path)?;
+            let bundle = db
+                .export_bundle(&id).await?
+                .ok_or_else(|| anyhow::anyhow!("Document {id} not found"))?;
+            let json = serde_json::to_string_pretty(&bundle)?;
+            match out {
+                Some(path) => std::fs::write(&path, json + "\n")
+                    .with_context(|| format!("Failed to write {path}"))?,
+                None => println!("{json}"),
+            }
+        }
+        Command::Import { file } => {
+            let text = std::fs::read_to_string(&file).with_context(|| format!("Failed to read {file}"))?;
+            let bundle = read_bundle(&text).with_context(|| format!("{file} is not an exported document"))?;
+            validate::validate_bundle(&bundle)
+                .map_err(|e| anyhow::anyhow!("{file} is not a valid document: {e}"))?;
+            let db = Db::new(&config.db_path)?;
+            // Keep the exported id unless it's already taken here
+            let id = if db.get_document(&bundle.document.id).await?.is_some() {
+                api::generate_short_id()
+            } else {
+                bundle.document.id.clone()
+            };
+            db.import_bundle(&id, bundle).await?;
+            println!("{id}");
+        }
+        Command::Heartbeat { id, seed } => {
+            let state = app_state(config)?;
+            let result = api::run_heartbeat(&state, &id, seed)
+                .await
+                .map_err(|e| anyhow::anyhow!("Heartbeat failed: {e}"))?;
+            if let Some(thinking) = &result.thinking {
+                println!("{thinking}\n");
+            }
+            for r in result.results.iter().filter(|r| r.thinking.is_none()) {
+                println!("{} passed", r.personality);
+            }
+            println!(
+                "{} ({} nodes, {} edges)",
+                if result.changed { "Tree changed" } else { "No changes" },
+                count_nodes(&result.tree),
+                result.edges.len()
+            );
+        }
+        Command::Summary { id, req } => {
+            let state = app_state(config)?;
+            let req = SummaryRequest {
+                voice: req.voice,
+                force_refresh: Some(req.refresh),
+                node_id: req.node_id,
+                format: req.format,
+            };
+            let summary = api::summarize_doc(&state, &id, req)
+                .await
+                .map_err(|e| anyhow::anyhow!("Summary failed: {e}"))?;
+            if summary.stale {
+                eprintln!("(cached summary is stale; pass --refresh to regenerate)");
+            }
+            println!("{}", summary.content);
+        }
+        Command::Migrate => {
+            let db = Db::new(&config.db_path)?;
+            println!("{} is at schema version {}", config.db_path, db.schema_version().await?);
+        }
+        Command::Backup { path } => {
+            let db = Db::new(&config.db_path)?;
+            match path {
+                Some(path) => {
+                    db.backup_to(Path::new(&path))?;
+                    println!("{path}");
+                }
+                None => println!("{}", backup::backup_now(&db, &config.backup)?.file),
+            }
+        }
+        Command::Restore { file } => {
+            if let Some(safety) = backup::restore(&config.db_path, Path::new(&file), &config.backup)? {
+                println!("Previous database saved to {}", safety.file);
+            }
+            println!("Restored {} from {file}", config.db_path);
+        }
+    }
+    Ok(())
+}
+
+fn app_state(config: &Config) -> anyhow::Result<AppState> {
+    config.llm.require_api_key()?;
+    Ok(AppState {
+        db: Db::new(&config.db_path)?,
+        llm: LlmClient::new(config.llm.clone())
+            .with_context_budget(config.context_tokens)
+            .with_prompt_format(config.prompt_format),
+        backup: config.backup.clone(),
+        draining: AtomicBool::new(false),
+    })
+}
+
+/// A bundle, or a bare document as exported before bundles existed.
+fn read_bundle(text: &str) -> anyhow::Result<DocumentBundle> {
+    let value: serde_json::Value = serde_json::from_str(text)?;
+    if value.get("bundle_version").is_some() {
+        return Ok(serde_json::from_value(value)?);
+    }
+    Ok(DocumentBundle {
+        bundle_version: BUNDLE_VERSION,
+        document: serde_json::from_value(value)?,
+        messages: vec![],
+        personalities: vec![],
+        settings: None,
+        revisions: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Command, String> {
+        let args: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+        Command::parse(&args)
+    }
+
+    #[test]
+    fn parses_commands_and_flags() {
+        assert_eq!(parse(""), Ok(Command::Serve));
+        assert_eq!(
+            parse("export abc -o out.json"),
+            Ok(Command::Export {
+                id: "abc".to_string(),
+                out: Some("out.json".to_string()),
+            })
+        );
+        assert_eq!(
+            parse("summary abc --format decision_memo --refresh"),
+            Ok(Command::Summary {
+                id: "abc".to_string(),
+                req: SummaryArgs {
+                    format: SummaryFormat::DecisionMemo,
+                    refresh: true,
+                    ..Default::default()
+                },
+            })
+        );
+        assert_eq!(parse("backup"), Ok(Command::Backup { path: None }));
+
+        assert!(parse("export").is_err());
+        assert!(parse("heartbeat abc --seed x").is_err());
+        assert!(parse("list --refresh").is_err());
+        assert!(parse("summary abc --format haiku").is_err());
+        assert!(parse("frobnicate").is_err());
+    }
+}
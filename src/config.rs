@@ -0,0 +1,386 @@
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::context;
+use crate::outline::PromptFormat;
+
+/// Read when `GROVE_CONFIG` isn't set. A missing default file is fine; a
+/// missing `GROVE_CONFIG` file is an error.
+const DEFAULT_CONFIG_PATH: &str = "grove.toml";
+
+const DEFAULT_MODEL: &str = "claude-opus-4-6";
+const DEFAULT_TITLE_MODEL: &str = "claude-sonnet-4-5-20250929";
+
+/// Whether a request asks the model to think before answering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Thinking {
+    Adaptive,
+    Disabled,
+}
+
+/// Model settings for one kind of call, after defaults and overrides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskConfig {
+    pub model: String,
+    pub max_tokens: u32,
+    pub thinking: Thinking,
+}
+
+impl TaskConfig {
+    /// Set `model`, `max_tokens` and `thinking` on a Messages API request.
+    pub fn apply(&self, mut body: Value) -> Value {
+        body["model"] = json!(self.model);
+        body["max_tokens"] = json!(self.max_tokens);
+        if self.thinking == Thinking::Adaptive {
+            body["thinking"] = json!({ "type": "adaptive" });
+        }
+        body
+    }
+}
+
+/// Whether Messages API calls are saved to, or answered from, `recording_dir`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recording {
+    Off,
+    /// Call the API and save each request and response.
+    Record,
+    /// Answer from saved responses and never call the API.
+    Replay,
+}
+
+impl Recording {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "off" => Some(Self::Off),
+            "record" => Some(Self::Record),
+            "replay" => Some(Self::Replay),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LlmConfig {
+    pub api_key: String,
+    pub api_base_url: String,
+    pub recording: Recording,
+    pub recording_dir: String,
+    /// Chat replies and node threads.
+    pub chat: TaskConfig,
+    /// Debate turns and syntheses. Each forces one tool call, which the API
+    /// doesn't allow together with thinking.
+    pub debate: TaskConfig,
+    pub heartbeat: TaskConfig,
+    pub persona_heartbeat: TaskConfig,
+    pub summary: TaskConfig,
+    pub title: TaskConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackupConfig {
+    /// Rotating directory for database backups.
+    pub dir: String,
+    /// Hours between scheduled backups; 0 turns them off.
+    pub interval_hours: u64,
+    /// How many backups to keep in `dir`.
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind: String,
+    /// Where `/readyz` and `/metrics` are served. Loopback by default, since
+    /// `bind` is what the public proxy forwards to.
+    pub ops_bind: String,
+    pub db_path: String,
+    pub static_dir: String,
+    pub context_tokens: usize,
+    pub prompt_format: PromptFormat,
+    /// How long a stopping server waits for in-flight requests, such as a
+    /// heartbeat's model calls, to finish and save.
+    pub shutdown_grace_secs: u64,
+    pub llm: LlmConfig,
+    pub backup: BackupConfig,
+}
+
+/// `grove.toml` as written. Every field is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    bind: Option<String>,
+    ops_bind: Option<String>,
+    db_path: Option<String>,
+    static_dir: Option<String>,
+    context_tokens: Option<usize>,
+    prompt_format: Option<String>,
+    shutdown_grace_secs: Option<u64>,
+    llm: FileLlm,
+    backup: FileBackup,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileBackup {
+    dir: Option<String>,
+    interval_hours: Option<u64>,
+    keep: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileLlm {
+    api_key: Option<String>,
+    api_base_url: Option<String>,
+    recording: Option<String>,
+    recording_dir: Option<String>,
+    /// Used by every task that doesn't name its own model.
+    model: Option<String>,
+    chat: FileTask,
+    debate: FileTask,
+    heartbeat: FileTask,
+    persona_heartbeat: FileTask,
+    summary: FileTask,
+    title: FileTask,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileTask {
+    model: Option<String>,
+    max_tokens: Option<u32>,
+    thinking: Option<Thinking>,
+}
+
+impl Config {
+    /// Load `GROVE_CONFIG` (or `grove.toml` if present) and apply environment
+    /// overrides on top.
+    pub fn load() -> anyhow::Result<Self> {
+        let file = match std::env::var("GROVE_CONFIG") {
+            Ok(path) => Some(read_file(Path::new(&path))?),
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Some(read_file(Path::new(DEFAULT_CONFIG_PATH))?)
+            }
+            Err(_) => None,
+        };
+        Self::resolve(file.unwrap_or_default(), |name| std::env::var(name).ok())
+    }
+
+    fn resolve(mut file: FileConfig, env: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        if let Some(port) = env("PORT") {
+            file.bind = Some(format!("0.0.0.0:{port}"));
+        }
+        override_with(&mut file.bind, env("GROVE_BIND"));
+        override_with(&mut file.ops_bind, env("GROVE_OPS_BIND"));
+        override_with(&mut file.db_path, env("GROVE_DB"));
+        override_with(&mut file.static_dir, env("GROVE_STATIC_DIR"));
+        override_with(&mut file.prompt_format, env("GROVE_PROMPT_FORMAT"));
+        override_with(&mut file.backup.dir, env("GROVE_BACKUP_DIR"));
+        if let Some(tokens) = env("GROVE_CONTEXT_TOKENS") {
+            file.context_tokens = Some(
+                tokens
+                    .parse()
+                    .with_context(|| format!("GROVE_CONTEXT_TOKENS is not a number: {tokens}"))?,
+            );
+        }
+
+        let llm = &mut file.llm;
+        override_with(&mut llm.api_key, env("ANTHROPIC_API_KEY"));
+        override_with(&mut llm.api_base_url, env("GROVE_API_BASE_URL"));
+        override_with(&mut llm.recording, env("GROVE_RECORDING"));
+        override_with(&mut llm.recording_dir, env("GROVE_RECORDING_DIR"));
+        override_with(&mut llm.model, env("GROVE_MODEL"));
+        override_with(&mut llm.chat.model, env("GROVE_CHAT_MODEL"));
+        override_with(&mut llm.debate.model, env("GROVE_DEBATE_MODEL"));
+        override_with(&mut llm.heartbeat.model, env("GROVE_HEARTBEAT_MODEL"));
+        override_with(&mut llm.persona_heartbeat.model, env("GROVE_PERSONA_HEARTBEAT_MODEL"));
+        override_with(&mut llm.summary.model, env("GROVE_SUMMARY_MODEL"));
+        override_with(&mut llm.title.model, env("GROVE_TITLE_MODEL"));
+
+        let prompt_format = match file.prompt_format.as_deref() {
+            None => PromptFormat::Json,
+            Some(s) => PromptFormat::parse(s)
+                .ok_or_else(|| anyhow::anyhow!("Unknown prompt_format {s:?} (expected json or outline)"))?,
+        };
+        let recording = match llm.recording.as_deref() {
+            None => Recording::Off,
+            Some(s) => Recording::parse(s)
+                .ok_or_else(|| anyhow::anyhow!("Unknown recording {s:?} (expected off, record or replay)"))?,
+        };
+        if llm.debate.thinking == Some(Thinking::Adaptive) {
+            anyhow::bail!("[llm.debate] thinking must be \"disabled\": debate turns force a tool call");
+        }
+        let api_key = llm.api_key.take().unwrap_or_default();
+        let model = llm.model.take().unwrap_or_else(|| DEFAULT_MODEL.to_string());
+        let task = |t: &FileTask, max_tokens: u32, thinking: Thinking| TaskConfig {
+            model: t.model.clone().unwrap_or_else(|| model.clone()),
+            max_tokens: t.max_tokens.unwrap_or(max_tokens),
+            thinking: t.thinking.unwrap_or(thinking),
+        };
+
+        Ok(Self {
+            bind: file.bind.unwrap_or_else(|| "0.0.0.0:3000".to_string()),
+            ops_bind: file.ops_bind.unwrap_or_else(|| "127.0.0.1:3001".to_string()),
+            db_path: file.db_path.unwrap_or_else(|| "grove.db".to_string()),
+            static_dir: file.static_dir.unwrap_or_else(|| "frontend/dist".to_string()),
+            context_tokens: file
+                .context_tokens
+                .unwrap_or(context::DEFAULT_TREE_TOKEN_BUDGET),
+            prompt_format,
+            shutdown_grace_secs: file.shutdown_grace_secs.unwrap_or(60),
+            llm: LlmConfig {
+                api_key,
+                api_base_url: llm
+                    .api_base_url
+                    .take()
+                    .unwrap_or_else(|| "https://api.anthropic.com".to_string())
+                    .trim_end_matches('/')
+                    .to_string(),
+                recording,
+                recording_dir: llm
+                    .recording_dir
+                    .take()
+                    .unwrap_or_else(|| "recordings".to_string()),
+                chat: task(&llm.chat, 16000, Thinking::Adaptive),
+                debate: task(&llm.debate, 2000, Thinking::Disabled),
+                heartbeat: task(&llm.heartbeat, 16000, Thinking::Adaptive),
+                persona_heartbeat: task(&llm.persona_heartbeat, 16000, Thinking::Adaptive),
+                summary: task(&llm.summary, 4000, Thinking::Disabled),
+                title: TaskConfig {
+                    model: llm
+                        .title
+                        .model
+                        .clone()
+                        .unwrap_or_else(|| DEFAULT_TITLE_MODEL.to_string()),
+                    ..task(&llm.title, 50, Thinking::Disabled)
+                },
+            },
+            backup: BackupConfig {
+                dir: file.backup.dir.unwrap_or_else(|| "backups".to_string()),
+                interval_hours: file.backup.interval_hours.unwrap_or(24),
+                keep: file.backup.keep.unwrap_or(14),
+            },
+        })
+    }
+}
+
+impl LlmConfig {
+    /// Commands that call the model need a key; the rest can run without one,
+    /// and so can anything replaying recorded calls.
+    pub fn require_api_key(&self) -> anyhow::Result<()> {
+        if self.api_key.is_empty() && self.recording != Recording::Replay {
+            anyhow::bail!("ANTHROPIC_API_KEY must be set (or llm.api_key in the config file)");
+        }
+        Ok(())
+    }
+}
+
+fn read_file(path: &Path) -> anyhow::Result<FileConfig> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("Invalid config file {}", path.display()))
+}
+
+fn override_with(slot: &mut Option<String>, value: Option<String>) {
+    if let Some(v) = value.filter(|v| !v.is_empty()) {
+        *slot = Some(v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn resolve(toml: &str, env: &[(&str, &str)]) -> anyhow::Result<Config> {
+        let env: HashMap<String, String> =
+            env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::resolve(toml::from_str(toml)?, |name| env.get(name).cloned())
+    }
+
+    #[test]
+    fn tasks_inherit_the_default_model_and_keep_their_own_limits() {
+        let config = resolve(
+            r#"
+            db_path = "/var/lib/grove/grove.db"
+
+            [llm]
+            api_key = "from-file"
+            model = "claude-base"
+
+            [llm.summary]
+            max_tokens = 8000
+            thinking = "adaptive"
+            "#,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(config.bind, "0.0.0.0:3000");
+        assert_eq!(config.ops_bind, "127.0.0.1:3001");
+        assert_eq!(config.shutdown_grace_secs, 60);
+        assert_eq!(config.db_path, "/var/lib/grove/grove.db");
+        assert_eq!(config.llm.api_key, "from-file");
+        assert_eq!(config.llm.chat.model, "claude-base");
+        assert_eq!(config.llm.chat.max_tokens, 16000);
+        assert_eq!(
+            config.llm.summary,
+            TaskConfig {
+                model: "claude-base".to_string(),
+                max_tokens: 8000,
+                thinking: Thinking::Adaptive,
+            }
+        );
+        assert_eq!(config.llm.title.model, DEFAULT_TITLE_MODEL);
+        assert_eq!(config.llm.title.thinking, Thinking::Disabled);
+        assert_eq!(config.llm.debate.max_tokens, 2000);
+        assert_eq!(config.llm.debate.thinking, Thinking::Disabled);
+        assert!(resolve("[llm.debate]\nthinking = \"adaptive\"", &[]).is_err());
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let config = resolve(
+            r#"
+            bind = "127.0.0.1:8080"
+            prompt_format = "json"
+
+            [llm]
+            api_key = "from-file"
+            api_base_url = "http://localhost:9000/"
+
+            [llm.title]
+            model = "claude-file-title"
+            "#,
+            &[
+                ("ANTHROPIC_API_KEY", "from-env"),
+                ("PORT", "4000"),
+                ("GROVE_PROMPT_FORMAT", "outline"),
+                ("GROVE_TITLE_MODEL", "claude-env-title"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.bind, "0.0.0.0:4000");
+        assert_eq!(config.prompt_format, PromptFormat::Outline);
+        assert_eq!(config.llm.api_key, "from-env");
+        assert_eq!(config.llm.api_base_url, "http://localhost:9000");
+        assert_eq!(config.llm.title.model, "claude-env-title");
+
+        let keyless = resolve("", &[]).unwrap();
+        assert!(keyless.llm.require_api_key().is_err());
+        assert_eq!(keyless.llm.recording, Recording::Off);
+        let replaying = resolve("", &[("GROVE_RECORDING", "replay")]).unwrap();
+        assert!(replaying.llm.require_api_key().is_ok());
+        assert!(resolve("[llm]\nrecording = \"rewind\"", &[]).is_err());
+        assert!(resolve("[llm]\nmodle = \"typo\"", &[("ANTHROPIC_API_KEY", "k")]).is_err());
+    }
+
+    #[test]
+    fn example_config_parses() {
+        let config = resolve(include_str!("../grove.example.toml"), &[("ANTHROPIC_API_KEY", "k")]).unwrap();
+        assert_eq!(config.llm.summary.thinking, Thinking::Disabled);
+    }
+}
@@ -0,0 +1,346 @@
+use crate::llm::{collect_node_ids, count_nodes};
+use crate::models::{Edge, TreeNode};
+use crate::outline::{self, PromptFormat};
+
+/// Trees whose rendered prompt text stays under this estimate are sent in full.
+pub const DEFAULT_TREE_TOKEN_BUDGET: usize = 12_000;
+
+/// Longest run of prose kept on a collapsed stub.
+const STUB_PROSE_CHARS: usize = 120;
+
+/// Child labels listed on a collapsed stub before eliding the rest.
+const STUB_CHILD_LABELS: usize = 5;
+
+/// The tree and edges as they will appear in a prompt.
+pub struct TreeContext {
+    pub tree_text: String,
+    pub edges_text: String,
+    /// IDs of nodes whose subtrees were collapsed to fit the budget.
+    pub collapsed: Vec<String>,
+    pub format: PromptFormat,
+}
+
+/// Rough token estimate: about four bytes of English or JSON per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+/// Render `tree` and `edges` for a prompt, collapsing cold or seen subtrees
+/// into short stubs once the full rendering would exceed `budget_tokens`.
+/// Hot, unseen and hovered nodes (and the path down to them) keep full text.
+/// If that is still over budget, everything below a shrinking depth is
+/// collapsed too, except the path to the hovered node, down to stubs for
+/// the root's children.
+pub fn build_tree_context(
+    tree: &TreeNode,
+    edges: &[Edge],
+    hover_node_id: Option<&str>,
+    budget_tokens: usize,
+    format: PromptFormat,
+) -> anyhow::Result<TreeContext> {
+    let tree_text = render_tree(tree, format)?;
+    let edges_text = render_edges(edges, format)?;
+    let estimated_tokens = estimate_tokens(&tree_text) + estimate_tokens(&edges_text);
+    if estimated_tokens <= budget_tokens {
+        return Ok(TreeContext {
+            tree_text,
+            edges_text,
+            collapsed: vec![],
+            format,
+        });
+    }
+
+    let mut max_depth = usize::MAX;
+    loop {
+        let mut collapsed = Vec::new();
+        let view = collapse(tree, hover_node_id, 0, max_depth, &mut collapsed);
+        // Stubs have no children, so every ID left in the view is visible
+        let visible = collect_node_ids(&view);
+        let kept_edges: Vec<&Edge> = edges
+            .iter()
+            .filter(|e| visible.contains(&e.source) && visible.contains(&e.target))
+            .collect();
+        let hidden_edges = edges.len() - kept_edges.len();
+
+        let tree_text = render_tree(&view, format)?;
+        let mut edges_text = render_edges(kept_edges, format)?;
+        if hidden_edges > 0 {
+            edges_text.push_str(&format!(
+                "\n({hidden_edges} edges into collapsed subtrees omitted)"
+            ));
+        }
+        let collapsed_tokens = estimate_tokens(&tree_text) + estimate_tokens(&edges_text);
+        let next_depth = if max_depth == usize::MAX {
+            height(tree)
+        } else {
+            max_depth - 1
+        };
+        if collapsed_tokens <= budget_tokens || next_depth == 0 {
+            tracing::debug!(
+                "Collapsed {} subtrees: ~{} tokens down to ~{}",
+                collapsed.len(),
+                estimated_tokens,
+                collapsed_tokens
+            );
+            return Ok(TreeContext {
+                tree_text,
+                edges_text,
+                collapsed,
+                format,
+            });
+        }
+        max_depth = next_depth;
+    }
+}
+
+fn render_tree(tree: &TreeNode, format: PromptFormat) -> anyhow::Result<String> {
+    Ok(match format {
+        PromptFormat::Json => serde_json::to_string_pretty(tree)?,
+        PromptFormat::Outline => outline::render_tree(tree),
+    })
+}
+
+fn render_edges<'a>(
+    edges: impl IntoIterator<Item = &'a Edge>,
+    format: PromptFormat,
+) -> anyhow::Result<String> {
+    Ok(match format {
+        PromptFormat::Json => {
+            serde_json::to_string_pretty(&edges.into_iter().collect::<Vec<_>>())?
+        }
+        PromptFormat::Outline => outline::render_edges(edges),
+    })
+}
+
+/// A node keeps full detail if it is hot, unseen or hovered.
+fn in_focus(node: &TreeNode, hover_node_id: Option<&str>) -> bool {
+    node.heat == "hot" || !node.seen || hover_node_id == Some(node.id.as_str())
+}
+
+fn subtree_has_focus(node: &TreeNode, hover_node_id: Option<&str>) -> bool {
+    in_focus(node, hover_node_id)
+        || node
+            .children
+            .iter()
+            .any(|c| subtree_has_focus(c, hover_node_id))
+}
+
+/// Levels below `node`; 0 for a leaf.
+fn height(node: &TreeNode) -> usize {
+    node.children.iter().map(|c| 1 + height(c)).max().unwrap_or(0)
+}
+
+/// Stub every subtree without focus, and every node at `max_depth` or deeper
+/// that isn't on the way to the hovered node. The root is never a stub.
+fn collapse(
+    node: &TreeNode,
+    hover_node_id: Option<&str>,
+    depth: usize,
+    max_depth: usize,
+    collapsed: &mut Vec<String>,
+) -> TreeNode {
+    let on_hover_path = hover_node_id.is_some_and(|id| node.find(id).is_some());
+    if depth > 0
+        && (!subtree_has_focus(node, hover_node_id) || (depth >= max_depth && !on_hover_path))
+    {
+        collapsed.push(node.id.clone());
+        return stub(node);
+    }
+    TreeNode {
+        children: node
+            .children
+            .iter()
+            .map(|c| collapse(c, hover_node_id, depth + 1, max_depth, collapsed))
+            .collect(),
+        ..node.clone()
+    }
+}
+
+fn stub(node: &TreeNode) -> TreeNode {
+    let hidden = count_nodes(node) - 1;
+    let mut prose: String = node.prose.chars().take(STUB_PROSE_CHARS).collect();
+    if prose.len() < node.prose.len() {
+        prose.push('…');
+    }
+    let marker = if hidden == 0 {
+        format!("[collapsed — call expand_node(\"{}\") for full text]", node.id)
+    } else {
+        let mut labels: Vec<&str> = node
+            .children
+            .iter()
+            .take(STUB_CHILD_LABELS)
+            .map(|c| c.label.as_str())
+            .collect();
+        if node.children.len() > STUB_CHILD_LABELS {
+            labels.push("…");
+        }
+        format!(
+            "[collapsed — {hidden} nodes hidden: {}. Call expand_node(\"{}\") to read them]",
+            labels.join(", "),
+            node.id
+        )
+    };
+    TreeNode {
+        id: node.id.clone(),
+        label: node.label.clone(),
+        prose: format!("{marker} {prose}"),
+        heat: node.heat.clone(),
+        by: node.by.clone(),
+        seen: node.seen,
+        children: vec![],
+    }
+}
+
+/// Full text of the subtree at `node_id` plus edges touching it, returned when
+/// the model asks to expand a collapsed node. Uses the prompt's `format`.
+pub fn expand_subtree(
+    tree: &TreeNode,
+    edges: &[Edge],
+    node_id: &str,
+    format: PromptFormat,
+) -> anyhow::Result<String> {
+    let node = tree
+        .find(node_id)
+        .ok_or_else(|| anyhow::anyhow!("node \"{node_id}\" does not exist"))?;
+    let ids = collect_node_ids(node);
+    let touching: Vec<&Edge> = edges
+        .iter()
+        .filter(|e| ids.contains(&e.source) || ids.contains(&e.target))
+        .collect();
+    Ok(format!(
+        "<subtree>\n{}\n</subtree>\n\n<edges>\n{}\n</edges>",
+        render_tree(node, format)?.trim_end(),
+        render_edges(touching, format)?.trim_end()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, heat: &str, seen: bool, children: Vec<TreeNode>) -> TreeNode {
+        TreeNode {
+            id: id.to_string(),
+            label: format!("Label {id}"),
+            prose: format!("Prose about {id}. ").repeat(20),
+            heat: heat.to_string(),
+            by: "human".to_string(),
+            seen,
+            children,
+        }
+    }
+
+    fn edge(source: &str, target: &str) -> Edge {
+        Edge {
+            source: source.to_string(),
+            target: target.to_string(),
+            label: "relates".to_string(),
+        }
+    }
+
+    /// Under the root: a hot node, a seen subtree, a seen leaf, a seen node
+    /// with an unseen child, and the hovered node.
+    fn tree() -> (TreeNode, Vec<Edge>) {
+        let tree = node(
+            "root",
+            "warm",
+            true,
+            vec![
+                node("hot", "hot", true, vec![]),
+                node("cold", "warm", true, vec![node("cold-leaf", "quiet", true, vec![])]),
+                node("quiet-leaf", "quiet", true, vec![]),
+                node("fresh", "warm", true, vec![node("unseen", "warm", false, vec![])]),
+                node("hovered", "quiet", true, vec![]),
+            ],
+        );
+        let edges = vec![edge("hot", "cold-leaf"), edge("cold", "hot"), edge("hot", "unseen")];
+        (tree, edges)
+    }
+
+    #[test]
+    fn small_trees_are_sent_in_full() {
+        let (tree, edges) = tree();
+        let context = build_tree_context(&tree, &edges, None, 100_000, PromptFormat::Json).unwrap();
+        assert!(context.collapsed.is_empty());
+        assert_eq!(context.tree_text, serde_json::to_string_pretty(&tree).unwrap());
+    }
+
+    #[test]
+    fn only_subtrees_without_focus_collapse() {
+        let (tree, edges) = tree();
+        let context =
+            build_tree_context(&tree, &edges, Some("hovered"), 1000, PromptFormat::Json).unwrap();
+        assert_eq!(context.collapsed, ["cold", "quiet-leaf"]);
+
+        let view: TreeNode = serde_json::from_str(&context.tree_text).unwrap();
+        let ids: Vec<&str> = view.children.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["hot", "cold", "quiet-leaf", "fresh", "hovered"]);
+        assert_eq!(view.find("hovered").unwrap().prose, tree.find("hovered").unwrap().prose);
+        assert!(view.find("unseen").is_some());
+
+        // Stubs keep their id, label and heat but drop their children
+        let cold = view.find("cold").unwrap();
+        assert_eq!(cold.label, "Label cold");
+        assert_eq!(cold.heat, "warm");
+        assert!(cold.children.is_empty());
+        assert!(cold.prose.starts_with(
+            "[collapsed — 1 nodes hidden: Label cold-leaf. Call expand_node(\"cold\") to read them]"
+        ));
+        assert!(cold.prose.ends_with('…'));
+        let leaf = view.find("quiet-leaf").unwrap();
+        assert!(leaf.prose.starts_with("[collapsed — call expand_node(\"quiet-leaf\") for full text]"));
+
+        // Edges between visible nodes stay, including ones to a stub
+        let kept: Vec<Edge> = serde_json::from_str(
+            context.edges_text.split("\n(").next().unwrap(),
+        )
+        .unwrap();
+        let kept: Vec<(&str, &str)> =
+            kept.iter().map(|e| (e.source.as_str(), e.target.as_str())).collect();
+        assert_eq!(kept, [("cold", "hot"), ("hot", "unseen")]);
+        assert!(context.edges_text.ends_with("(1 edges into collapsed subtrees omitted)"));
+    }
+
+    #[test]
+    fn expanding_returns_the_subtree_and_its_edges() {
+        let (tree, edges) = tree();
+        let text = expand_subtree(&tree, &edges, "cold", PromptFormat::Json).unwrap();
+        assert!(text.contains(&tree.find("cold-leaf").unwrap().prose));
+        assert!(text.contains("\"target\": \"cold-leaf\""));
+        assert!(text.contains("\"source\": \"cold\""));
+        assert!(!text.contains("\"target\": \"unseen\""));
+
+        let err = expand_subtree(&tree, &edges, "nowhere", PromptFormat::Json).unwrap_err();
+        assert_eq!(err.to_string(), "node \"nowhere\" does not exist");
+
+        let text = expand_subtree(&tree, &edges, "cold", PromptFormat::Outline).unwrap();
+        assert!(text.starts_with("<subtree>\n- cold [warm, human] Label cold\n"));
+        assert!(text.contains("  - cold-leaf [quiet, human] Label cold-leaf\n"));
+        assert!(text.contains("hot -> cold-leaf: relates\ncold -> hot: relates\n</edges>"));
+    }
+
+    /// Three unseen branches of three unseen leaves: nothing is out of focus,
+    /// so the first pass collapses nothing.
+    fn unseen_tree() -> TreeNode {
+        let branch = |id: &str| {
+            let leaves = (1..=3).map(|i| node(&format!("{id}{i}"), "warm", false, vec![])).collect();
+            node(id, "warm", false, leaves)
+        };
+        node("root", "warm", false, vec![branch("a"), branch("b"), branch("c")])
+    }
+
+    #[test]
+    fn collapsing_goes_shallower_until_the_tree_fits() {
+        let tree = unseen_tree();
+        let full = estimate_tokens(&serde_json::to_string_pretty(&tree).unwrap());
+        let context = build_tree_context(&tree, &[], None, full / 2, PromptFormat::Json).unwrap();
+        assert_eq!(context.collapsed, ["a", "b", "c"]);
+        assert!(estimate_tokens(&context.tree_text) <= full / 2);
+
+        // Past the last pass the hovered node and its ancestors still keep full text
+        let context = build_tree_context(&tree, &[], Some("b1"), 1, PromptFormat::Json).unwrap();
+        assert_eq!(context.collapsed, ["a", "b2", "b3", "c"]);
+        let view: TreeNode = serde_json::from_str(&context.tree_text).unwrap();
+        assert_eq!(view.find("b1").unwrap().prose, tree.find("b1").unwrap().prose);
+    }
+}
@@ -1,366 +1,1246 @@
-use std::sync::Mutex;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use rusqlite::{params, Connection};
+use anyhow::Context;
+use rusqlite::backup::Backup;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension, Transaction};
 
-use crate::models::{Document, Edge, Message, TreeNode};
+use crate::llm::count_nodes;
+use crate::metrics;
+use crate::migrations;
+use crate::models::{
+    AgentQuestion, BundlePersonality, BundleRevision, BundleSettings, ChangeBudget, Document,
+    DocumentBundle, DocumentListing, Edge, Message, MessagesQuery, RevisionInfo, TreeNode,
+    UpdateSettingsRequest, BUNDLE_VERSION,
+};
+use crate::selection::SpeakerStrategy;
 
+/// Connections to keep open for reads alongside the single writer.
+const READERS: usize = 4;
+
+/// How long a statement waits on another connection's lock before failing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The database. Every query runs on tokio's blocking pool: writes go
+/// through one connection, reads spread over a few more, and WAL lets them
+/// proceed alongside each other. Cloning is cheap and shares the connections.
+#[derive(Clone)]
 pub struct Db {
-    conn: Mutex<Connection>,
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    writer: Mutex<Connection>,
+    readers: Vec<Mutex<Connection>>,
+    next_reader: AtomicUsize,
+    path: String,
 }
 
 impl Db {
     pub fn new(path: &str) -> anyhow::Result<Self> {
-        let conn = Connection::open(path)?;
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS documents (
-                id TEXT PRIMARY KEY,
-                tree TEXT NOT NULL,
-                created_at TEXT NOT NULL DEFAULT (datetime('now')),
-                updated_at TEXT NOT NULL DEFAULT (datetime('now'))
-            );
-            CREATE TABLE IF NOT EXISTS messages (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                doc_id TEXT NOT NULL REFERENCES documents(id),
-                role TEXT NOT NULL,
-                content TEXT NOT NULL,
-                hover_node_id TEXT,
-                created_at TEXT NOT NULL DEFAULT (datetime('now'))
-            );
-            CREATE TABLE IF NOT EXISTS doc_personalities (
-                doc_id TEXT NOT NULL,
-                personality_id TEXT NOT NULL,
-                PRIMARY KEY (doc_id, personality_id)
-            );
-            CREATE TABLE IF NOT EXISTS doc_settings (
-                doc_id TEXT PRIMARY KEY,
-                heartbeat_dice_sides INTEGER NOT NULL DEFAULT 3
-            );",
-        )?;
-        // Migration: add personality column to messages if missing
-        let has_personality: bool = conn
-            .prepare("SELECT sql FROM sqlite_master WHERE type='table' AND name='messages'")?
-            .query_row([], |row| {
-                let sql: String = row.get(0)?;
-                Ok(sql.contains("personality"))
-            })
-            .unwrap_or(false);
-        if !has_personality {
-            conn.execute_batch("ALTER TABLE messages ADD COLUMN personality TEXT")?;
-        }
-        // Migration: add edges column to documents if missing
-        let has_edges: bool = conn
-            .prepare("SELECT sql FROM sqlite_master WHERE type='table' AND name='documents'")?
-            .query_row([], |row| {
-                let sql: String = row.get(0)?;
-                Ok(sql.contains("edges"))
-            })
-            .unwrap_or(false);
-        if !has_edges {
-            conn.execute_batch("ALTER TABLE documents ADD COLUMN edges TEXT NOT NULL DEFAULT '[]'")?;
-        }
-        // Migration: add repel_force column to doc_settings if missing
-        let has_repel_force: bool = conn
-            .prepare("SELECT sql FROM sqlite_master WHERE type='table' AND name='doc_settings'")?
-            .query_row([], |row| {
-                let sql: String = row.get(0)?;
-                Ok(sql.contains("repel_force"))
-            })
-            .unwrap_or(false);
-        if !has_repel_force {
-            conn.execute_batch(
-                "ALTER TABLE doc_settings ADD COLUMN repel_force REAL NOT NULL DEFAULT 20.0",
-            )?;
-        }
-        // Create doc_summaries table
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS doc_summaries (
-                doc_id TEXT NOT NULL,
-                voice TEXT NOT NULL,
-                content TEXT NOT NULL,
-                tree_hash TEXT NOT NULL,
-                PRIMARY KEY (doc_id, voice)
-            )",
-        )?;
-        // Migration: add title column to documents if missing
-        let has_title: bool = conn
-            .prepare("SELECT sql FROM sqlite_master WHERE type='table' AND name='documents'")?
-            .query_row([], |row| {
-                let sql: String = row.get(0)?;
-                Ok(sql.contains("title"))
-            })
-            .unwrap_or(false);
-        if !has_title {
-            conn.execute_batch("ALTER TABLE documents ADD COLUMN title TEXT")?;
-        }
-        // Create agent_questions table
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS agent_questions (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                doc_id TEXT NOT NULL,
-                from_agent TEXT NOT NULL,
-                to_agent TEXT NOT NULL,
-                question TEXT NOT NULL,
-                created_at TEXT NOT NULL DEFAULT (datetime('now'))
-            )",
-        )?;
+        let mut writer = open(path)?;
+        migrations::migrate(&mut writer)?;
+        // An in-memory database is private to its connection, so it has no readers
+        let readers = if path == ":memory:" {
+            vec![]
+        } else {
+            (0..READERS)
+                .map(|_| {
+                    let conn = open(path)?;
+                    conn.pragma_update(None, "query_only", true)?;
+                    Ok(Mutex::new(conn))
+                })
+                .collect::<anyhow::Result<_>>()?
+        };
         Ok(Self {
-            conn: Mutex::new(conn),
+            inner: Arc::new(Inner {
+                writer: Mutex::new(writer),
+                readers,
+                next_reader: AtomicUsize::new(0),
+                path: path.to_string(),
+            }),
+        })
+    }
+
+    /// Run a query on a read connection, off the async runtime.
+    async fn read<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = match inner.readers.len() {
+                0 => &inner.writer,
+                n => &inner.readers[inner.next_reader.fetch_add(1, Ordering::Relaxed) % n],
+            };
+            let start = Instant::now();
+            let conn = conn.lock().unwrap();
+            metrics::db_lock_wait("read", start.elapsed());
+            f(&conn)
+        })
+        .await?
+    }
+
+    /// Run statements on the write connection, off the async runtime.
+    /// Anything that writes more than one statement should open a
+    /// transaction inside `f`.
+    async fn write<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || {
+            let start = Instant::now();
+            let mut conn = inner.writer.lock().unwrap();
+            metrics::db_lock_wait("write", start.elapsed());
+            f(&mut conn)
         })
+        .await?
     }
 
-    pub fn create_document(&self, id: &str) -> anyhow::Result<Document> {
+    /// The schema version recorded in the database.
+    pub async fn schema_version(&self) -> anyhow::Result<u32> {
+        self.read(|conn| Ok(migrations::current_version(conn)?)).await
+    }
+
+    pub async fn create_document(&self, id: &str) -> anyhow::Result<Document> {
         let tree = default_tree();
         let tree_json = serde_json::to_string(&tree)?;
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO documents (id, tree, edges) VALUES (?1, ?2, '[]')",
-            params![id, tree_json],
-        )?;
-        let doc = get_document_inner(&conn, id)?;
-        Ok(doc)
-    }
-
-    pub fn get_document(&self, id: &str) -> anyhow::Result<Option<Document>> {
-        let conn = self.conn.lock().unwrap();
-        match get_document_inner(&conn, id) {
-            Ok(doc) => Ok(Some(doc)),
-            Err(_) => Ok(None),
-        }
+        let id = id.to_string();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT INTO documents (id, tree, edges) VALUES (?1, ?2, '[]')",
+                params![id, tree_json],
+            )?;
+            record_revision(&tx, &id)?;
+            let doc = get_document_inner(&tx, &id)?
+                .ok_or_else(|| anyhow::anyhow!("Document {id} vanished while being created"))?;
+            tx.commit()?;
+            Ok(doc)
+        })
+        .await
+    }
+
+    pub async fn get_document(&self, id: &str) -> anyhow::Result<Option<Document>> {
+        let id = id.to_string();
+        self.read(move |conn| get_document_inner(conn, &id)).await
     }
 
-    pub fn update_tree(&self, id: &str, tree: &TreeNode, edges: &[Edge]) -> anyhow::Result<()> {
+    pub async fn update_tree(&self, id: &str, tree: &TreeNode, edges: &[Edge]) -> anyhow::Result<()> {
+        metrics::tree_saved(count_nodes(tree), edges.len());
         let tree_json = serde_json::to_string(tree)?;
         let edges_json = serde_json::to_string(edges)?;
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "UPDATE documents SET tree = ?1, edges = ?2, updated_at = datetime('now') WHERE id = ?3",
-            params![tree_json, edges_json, id],
-        )?;
-        Ok(())
+        let id = id.to_string();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "UPDATE documents SET tree = ?1, edges = ?2, updated_at = datetime('now') WHERE id = ?3",
+                params![tree_json, edges_json, id],
+            )?;
+            record_revision(&tx, &id)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
     }
 
-    pub fn add_message(
+    /// Save a tree whose only change is `seen` flags. Unlike `update_tree`
+    /// this records no revision, so hovering doesn't crowd out real edits.
+    pub async fn set_tree_seen(&self, id: &str, tree: &TreeNode) -> anyhow::Result<()> {
+        let tree_json = serde_json::to_string(tree)?;
+        let id = id.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "UPDATE documents SET tree = ?1, updated_at = datetime('now') WHERE id = ?2",
+                params![tree_json, id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn add_message(
         &self,
         doc_id: &str,
         role: &str,
         content: &str,
         hover_node_id: Option<&str>,
         personality: Option<&str>,
-    ) -> anyhow::Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO messages (doc_id, role, content, hover_node_id, personality) VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![doc_id, role, content, hover_node_id, personality],
-        )?;
-        Ok(())
+    ) -> anyhow::Result<i64> {
+        let doc_id = doc_id.to_string();
+        let role = role.to_string();
+        let content = content.to_string();
+        let hover_node_id = hover_node_id.map(str::to_string);
+        let personality = personality.map(str::to_string);
+        self.write(move |conn| {
+            conn.execute(
+                "INSERT INTO messages (doc_id, role, content, hover_node_id, personality) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![doc_id, role, content, hover_node_id, personality],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+        .await
     }
 
-    pub fn get_messages(&self, doc_id: &str, limit: usize) -> anyhow::Result<Vec<Message>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, doc_id, role, content, hover_node_id, personality, created_at
-             FROM messages WHERE doc_id = ?1
-             ORDER BY created_at DESC LIMIT ?2",
-        )?;
-        let mut messages = stmt
-            .query_map(params![doc_id, limit as i64], |row| {
-                Ok(Message {
-                    id: row.get(0)?,
-                    doc_id: row.get(1)?,
-                    role: row.get(2)?,
-                    content: row.get(3)?,
-                    hover_node_id: row.get(4)?,
-                    personality: row.get(5)?,
-                    created_at: row.get(6)?,
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
-        messages.reverse(); // chronological order
-        Ok(messages)
-    }
-
-    pub fn get_active_personalities(&self, doc_id: &str) -> anyhow::Result<Vec<String>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT personality_id FROM doc_personalities WHERE doc_id = ?1 ORDER BY personality_id",
-        )?;
-        let ids = stmt
-            .query_map(params![doc_id], |row| row.get(0))?
-            .collect::<Result<Vec<String>, _>>()?;
-        Ok(ids)
-    }
-
-    pub fn set_personalities(&self, doc_id: &str, personality_ids: &[String]) -> anyhow::Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "DELETE FROM doc_personalities WHERE doc_id = ?1",
-            params![doc_id],
-        )?;
-        for pid in personality_ids {
+    pub async fn add_thread_message(
+        &self,
+        doc_id: &str,
+        node_id: &str,
+        role: &str,
+        content: &str,
+        personality: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let doc_id = doc_id.to_string();
+        let node_id = node_id.to_string();
+        let role = role.to_string();
+        let content = content.to_string();
+        let personality = personality.map(str::to_string);
+        self.write(move |conn| {
             conn.execute(
-                "INSERT INTO doc_personalities (doc_id, personality_id) VALUES (?1, ?2)",
-                params![doc_id, pid],
+                "INSERT INTO messages (doc_id, role, content, hover_node_id, personality, thread_node_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?4)",
+                params![doc_id, role, content, node_id, personality],
             )?;
-        }
-        Ok(())
+            Ok(())
+        })
+        .await
+    }
+
+    /// The latest `limit` messages of the main chat stream, excluding node threads.
+    pub async fn get_messages(&self, doc_id: &str, limit: usize) -> anyhow::Result<Vec<Message>> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, doc_id, role, content, hover_node_id, personality, thread_node_id, created_at
+                 FROM messages WHERE doc_id = ?1 AND thread_node_id IS NULL
+                 ORDER BY id DESC LIMIT ?2",
+            )?;
+            let mut messages = stmt
+                .query_map(params![doc_id, limit as i64], message_from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            messages.reverse(); // chronological order
+            Ok(messages)
+        })
+        .await
     }
 
-    pub fn get_dice_sides(&self, doc_id: &str) -> anyhow::Result<u32> {
-        let conn = self.conn.lock().unwrap();
-        let result = conn.query_row(
-            "SELECT heartbeat_dice_sides FROM doc_settings WHERE doc_id = ?1",
-            params![doc_id],
-            |row| row.get(0),
+    /// Fetch up to `limit` messages matching `query`, newest first by id, then
+    /// return them in chronological order. Also returns whether older
+    /// matching messages remain.
+    pub async fn query_messages(
+        &self,
+        doc_id: &str,
+        query: &MessagesQuery,
+        limit: usize,
+    ) -> anyhow::Result<(Vec<Message>, bool)> {
+        let mut sql = String::from(
+            "SELECT id, doc_id, role, content, hover_node_id, personality, thread_node_id, created_at
+             FROM messages WHERE doc_id = ?",
         );
-        match result {
-            Ok(sides) => Ok(sides),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(3),
-            Err(e) => Err(e.into()),
+        let mut args: Vec<SqlValue> = vec![SqlValue::Text(doc_id.to_string())];
+        match &query.thread_node_id {
+            Some(node_id) => {
+                sql.push_str(" AND thread_node_id = ?");
+                args.push(SqlValue::Text(node_id.clone()));
+            }
+            None => sql.push_str(" AND thread_node_id IS NULL"),
+        }
+        if let Some(before) = query.before {
+            sql.push_str(" AND id < ?");
+            args.push(SqlValue::Integer(before));
+        }
+        for (column, value) in [
+            ("role", &query.role),
+            ("personality", &query.personality),
+            ("hover_node_id", &query.hover_node_id),
+        ] {
+            if let Some(v) = value {
+                sql.push_str(&format!(" AND {column} = ?"));
+                args.push(SqlValue::Text(v.clone()));
+            }
+        }
+        if let Some(since) = &query.since {
+            sql.push_str(" AND created_at >= datetime(?)");
+            args.push(SqlValue::Text(since.clone()));
         }
+        if let Some(until) = &query.until {
+            sql.push_str(" AND created_at <= datetime(?)");
+            args.push(SqlValue::Text(until.clone()));
+        }
+        // Fetch one extra row to learn whether another page exists
+        sql.push_str(" ORDER BY id DESC LIMIT ?");
+        args.push(SqlValue::Integer(limit as i64 + 1));
+
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(&sql)?;
+            let mut messages = stmt
+                .query_map(params_from_iter(args), message_from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            let has_more = messages.len() > limit;
+            messages.truncate(limit);
+            messages.reverse(); // chronological order
+            Ok((messages, has_more))
+        })
+        .await
     }
 
-    pub fn set_dice_sides(&self, doc_id: &str, sides: u32) -> anyhow::Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO doc_settings (doc_id, heartbeat_dice_sides) VALUES (?1, ?2)
-             ON CONFLICT(doc_id) DO UPDATE SET heartbeat_dice_sides = ?2",
-            params![doc_id, sides],
-        )?;
-        Ok(())
+    pub async fn get_active_personalities(&self, doc_id: &str) -> anyhow::Result<Vec<String>> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT personality_id FROM doc_personalities WHERE doc_id = ?1 ORDER BY personality_id",
+            )?;
+            let ids = stmt
+                .query_map(params![doc_id], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            Ok(ids)
+        })
+        .await
     }
 
-    pub fn get_repel_force(&self, doc_id: &str) -> anyhow::Result<f64> {
-        let conn = self.conn.lock().unwrap();
-        let result = conn.query_row(
-            "SELECT repel_force FROM doc_settings WHERE doc_id = ?1",
-            params![doc_id],
-            |row| row.get(0),
-        );
-        match result {
-            Ok(force) => Ok(force),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(20.0),
-            Err(e) => Err(e.into()),
-        }
+    /// Replace the active personas. Weights default to 1.0 when not given.
+    pub async fn set_personalities(
+        &self,
+        doc_id: &str,
+        personality_ids: &[String],
+        weights: &HashMap<String, f64>,
+    ) -> anyhow::Result<()> {
+        let doc_id = doc_id.to_string();
+        let personality_ids = personality_ids.to_vec();
+        let weights = weights.clone();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            // Personas without a new weight keep the one they have
+            let stored: HashMap<String, f64> = tx
+                .prepare("SELECT personality_id, weight FROM doc_personalities WHERE doc_id = ?1")?
+                .query_map(params![doc_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_, _>>()?;
+            tx.execute(
+                "DELETE FROM doc_personalities WHERE doc_id = ?1",
+                params![doc_id],
+            )?;
+            for pid in personality_ids {
+                let weight = weights
+                    .get(&pid)
+                    .or_else(|| stored.get(&pid))
+                    .copied()
+                    .unwrap_or(1.0);
+                tx.execute(
+                    "INSERT INTO doc_personalities (doc_id, personality_id, weight) VALUES (?1, ?2, ?3)",
+                    params![doc_id, pid, weight],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
     }
 
-    pub fn set_repel_force(&self, doc_id: &str, force: f64) -> anyhow::Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO doc_settings (doc_id, heartbeat_dice_sides, repel_force) VALUES (?1, 3, ?2)
-             ON CONFLICT(doc_id) DO UPDATE SET repel_force = ?2",
-            params![doc_id, force],
-        )?;
-        Ok(())
+    pub async fn get_personality_weights(&self, doc_id: &str) -> anyhow::Result<HashMap<String, f64>> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT personality_id, weight FROM doc_personalities WHERE doc_id = ?1",
+            )?;
+            let weights = stmt
+                .query_map(params![doc_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<HashMap<String, f64>, _>>()?;
+            Ok(weights)
+        })
+        .await
     }
 
-    pub fn get_summary(&self, doc_id: &str, voice: &str) -> anyhow::Result<Option<(String, String)>> {
-        let conn = self.conn.lock().unwrap();
-        let result = conn.query_row(
-            "SELECT content, tree_hash FROM doc_summaries WHERE doc_id = ?1 AND voice = ?2",
-            params![doc_id, voice],
-            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
-        );
-        match result {
-            Ok(pair) => Ok(Some(pair)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+    /// Latest message id per persona, for least-recently-spoke selection.
+    pub async fn get_last_spoke(&self, doc_id: &str) -> anyhow::Result<HashMap<String, i64>> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT personality, MAX(id) FROM messages
+                 WHERE doc_id = ?1 AND personality IS NOT NULL
+                 GROUP BY personality",
+            )?;
+            let last = stmt
+                .query_map(params![doc_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<HashMap<String, i64>, _>>()?;
+            Ok(last)
+        })
+        .await
     }
 
-    pub fn save_summary(&self, doc_id: &str, voice: &str, content: &str, tree_hash: &str) -> anyhow::Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO doc_summaries (doc_id, voice, content, tree_hash) VALUES (?1, ?2, ?3, ?4)
-             ON CONFLICT(doc_id, voice) DO UPDATE SET content = ?3, tree_hash = ?4",
-            params![doc_id, voice, content, tree_hash],
-        )?;
-        Ok(())
+    pub async fn get_speaker_strategy(&self, doc_id: &str) -> anyhow::Result<(SpeakerStrategy, usize)> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            let result = conn.query_row(
+                "SELECT speaker_strategy, speaker_cursor FROM doc_settings WHERE doc_id = ?1",
+                params![doc_id],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+            );
+            match result {
+                Ok((name, cursor)) => Ok((
+                    SpeakerStrategy::parse(&name).unwrap_or_default(),
+                    cursor.max(0) as usize,
+                )),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok((SpeakerStrategy::default(), 0)),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
     }
 
-    pub fn insert_agent_questions(&self, doc_id: &str, questions: &[(&str, &str, &str)]) -> anyhow::Result<()> {
-        let conn = self.conn.lock().unwrap();
-        for &(from, to, question) in questions {
+    pub async fn set_speaker_cursor(&self, doc_id: &str, cursor: usize) -> anyhow::Result<()> {
+        let doc_id = doc_id.to_string();
+        self.write(move |conn| {
             conn.execute(
-                "INSERT INTO agent_questions (doc_id, from_agent, to_agent, question) VALUES (?1, ?2, ?3, ?4)",
-                params![doc_id, from, to, question],
+                "INSERT INTO doc_settings (doc_id, heartbeat_dice_sides, speaker_cursor) VALUES (?1, 3, ?2)
+                 ON CONFLICT(doc_id) DO UPDATE SET speaker_cursor = ?2",
+                params![doc_id, cursor as i64],
             )?;
-        }
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    pub fn get_pending_questions_for(&self, doc_id: &str, to_agent: &str) -> anyhow::Result<Vec<(String, String)>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT from_agent, question FROM agent_questions WHERE doc_id = ?1 AND to_agent = ?2",
-        )?;
-        let pairs = stmt
-            .query_map(params![doc_id, to_agent], |row| {
-                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(pairs)
-    }
-
-    pub fn get_reserved_agents(&self, doc_id: &str) -> anyhow::Result<Vec<String>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT DISTINCT to_agent FROM agent_questions WHERE doc_id = ?1",
-        )?;
-        let agents = stmt
-            .query_map(params![doc_id], |row| row.get(0))?
-            .collect::<Result<Vec<String>, _>>()?;
-        Ok(agents)
-    }
-
-    pub fn expire_agent_questions(&self, doc_id: &str) -> anyhow::Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "DELETE FROM agent_questions WHERE doc_id = ?1",
-            params![doc_id],
-        )?;
-        Ok(())
+    pub async fn get_dice_sides(&self, doc_id: &str) -> anyhow::Result<u32> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            let result = conn.query_row(
+                "SELECT heartbeat_dice_sides FROM doc_settings WHERE doc_id = ?1",
+                params![doc_id],
+                |row| row.get(0),
+            );
+            match result {
+                Ok(sides) => Ok(sides),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(3),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
     }
 
-    pub fn get_title(&self, doc_id: &str) -> anyhow::Result<Option<String>> {
-        let conn = self.conn.lock().unwrap();
-        let result = conn.query_row(
-            "SELECT title FROM documents WHERE id = ?1",
-            params![doc_id],
-            |row| row.get(0),
-        );
-        match result {
-            Ok(title) => Ok(title),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
+    pub async fn get_repel_force(&self, doc_id: &str) -> anyhow::Result<f64> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            let result = conn.query_row(
+                "SELECT repel_force FROM doc_settings WHERE doc_id = ?1",
+                params![doc_id],
+                |row| row.get(0),
+            );
+            match result {
+                Ok(force) => Ok(force),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(20.0),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    pub async fn get_change_budget(&self, doc_id: &str) -> anyhow::Result<ChangeBudget> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            let result = conn.query_row(
+                "SELECT max_new_nodes, max_deletions, max_edges, max_tree_size FROM doc_settings WHERE doc_id = ?1",
+                params![doc_id],
+                |row| {
+                    Ok(ChangeBudget {
+                        max_new_nodes: row.get(0)?,
+                        max_deletions: row.get(1)?,
+                        max_edges: row.get(2)?,
+                        max_tree_size: row.get(3)?,
+                    })
+                },
+            );
+            match result {
+                Ok(budget) => Ok(budget),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(ChangeBudget::default()),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    /// Apply whichever settings `req` gives in one transaction, leaving the
+    /// rest as they are.
+    pub async fn update_settings(&self, doc_id: &str, req: UpdateSettingsRequest) -> anyhow::Result<()> {
+        let doc_id = doc_id.to_string();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT OR IGNORE INTO doc_settings (doc_id, heartbeat_dice_sides) VALUES (?1, 3)",
+                params![doc_id],
+            )?;
+            tx.execute(
+                "UPDATE doc_settings SET
+                    heartbeat_dice_sides = COALESCE(?2, heartbeat_dice_sides),
+                    repel_force = COALESCE(?3, repel_force),
+                    max_new_nodes = COALESCE(?4, max_new_nodes),
+                    max_deletions = COALESCE(?5, max_deletions),
+                    max_edges = COALESCE(?6, max_edges),
+                    max_tree_size = COALESCE(?7, max_tree_size),
+                    speaker_strategy = COALESCE(?8, speaker_strategy)
+                 WHERE doc_id = ?1",
+                params![
+                    doc_id,
+                    req.dice_sides,
+                    req.repel_force,
+                    req.max_new_nodes,
+                    req.max_deletions,
+                    req.max_edges,
+                    req.max_tree_size,
+                    req.speaker_strategy.as_ref().map(SpeakerStrategy::as_str),
+                ],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Cached summary and the hash it was generated from. `node_id` is ""
+    /// for the whole tree.
+    pub async fn get_summary(
+        &self,
+        doc_id: &str,
+        voice: &str,
+        node_id: &str,
+        format: &str,
+    ) -> anyhow::Result<Option<(String, String)>> {
+        let doc_id = doc_id.to_string();
+        let voice = voice.to_string();
+        let node_id = node_id.to_string();
+        let format = format.to_string();
+        self.read(move |conn| {
+            let result = conn.query_row(
+                "SELECT content, tree_hash FROM doc_summaries
+                 WHERE doc_id = ?1 AND voice = ?2 AND node_id = ?3 AND format = ?4",
+                params![doc_id, voice, node_id, format],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            );
+            match result {
+                Ok(pair) => Ok(Some(pair)),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    pub async fn save_summary(
+        &self,
+        doc_id: &str,
+        voice: &str,
+        node_id: &str,
+        format: &str,
+        content: &str,
+        tree_hash: &str,
+    ) -> anyhow::Result<()> {
+        let doc_id = doc_id.to_string();
+        let voice = voice.to_string();
+        let node_id = node_id.to_string();
+        let format = format.to_string();
+        let content = content.to_string();
+        let tree_hash = tree_hash.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "INSERT INTO doc_summaries (doc_id, voice, node_id, format, content, tree_hash)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT(doc_id, voice, node_id, format) DO UPDATE SET content = ?5, tree_hash = ?6",
+                params![doc_id, voice, node_id, format, content, tree_hash],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Queue questions as (from_agent, to_agent, question).
+    pub async fn insert_agent_questions(
+        &self,
+        doc_id: &str,
+        questions: Vec<(String, String, String)>,
+    ) -> anyhow::Result<()> {
+        let doc_id = doc_id.to_string();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            for (from, to, question) in questions {
+                tx.execute(
+                    "INSERT INTO agent_questions (doc_id, from_agent, to_agent, question) VALUES (?1, ?2, ?3, ?4)",
+                    params![doc_id, from, to, question],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Pending questions for `to_agent`, oldest first, as (id, from_agent, question).
+    pub async fn get_pending_questions_for(
+        &self,
+        doc_id: &str,
+        to_agent: &str,
+    ) -> anyhow::Result<Vec<(i64, String, String)>> {
+        let doc_id = doc_id.to_string();
+        let to_agent = to_agent.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, from_agent, question FROM agent_questions
+                 WHERE doc_id = ?1 AND to_agent = ?2 AND status = 'pending'
+                 ORDER BY id",
+            )?;
+            let rows = stmt
+                .query_map(params![doc_id, to_agent], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        })
+        .await
+    }
+
+    pub async fn get_reserved_agents(&self, doc_id: &str) -> anyhow::Result<Vec<String>> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT DISTINCT to_agent FROM agent_questions WHERE doc_id = ?1 AND status = 'pending'",
+            )?;
+            let agents = stmt
+                .query_map(params![doc_id], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            Ok(agents)
+        })
+        .await
+    }
+
+    /// Mark questions answered, linking them to the reply message if there was one.
+    pub async fn answer_agent_questions(
+        &self,
+        question_ids: &[i64],
+        answer_message_id: Option<i64>,
+    ) -> anyhow::Result<()> {
+        let question_ids = question_ids.to_vec();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            for qid in question_ids {
+                tx.execute(
+                    "UPDATE agent_questions
+                     SET status = 'answered', answer_message_id = ?2, answered_at = datetime('now')
+                     WHERE id = ?1",
+                    params![qid, answer_message_id],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Age every still-pending question by one heartbeat and expire those
+    /// that have now waited `max_age` heartbeats.
+    pub async fn age_agent_questions(&self, doc_id: &str, max_age: u32) -> anyhow::Result<()> {
+        let doc_id = doc_id.to_string();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "UPDATE agent_questions SET age = age + 1 WHERE doc_id = ?1 AND status = 'pending'",
+                params![doc_id],
+            )?;
+            tx.execute(
+                "UPDATE agent_questions SET status = 'expired'
+                 WHERE doc_id = ?1 AND status = 'pending' AND age >= ?2",
+                params![doc_id, max_age],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn list_agent_questions(
+        &self,
+        doc_id: &str,
+        status: Option<&str>,
+    ) -> anyhow::Result<Vec<AgentQuestion>> {
+        let doc_id = doc_id.to_string();
+        let status = status.map(str::to_string);
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT q.id, q.from_agent, q.to_agent, q.question, q.status, q.age,
+                        q.answer_message_id, m.content, q.created_at, q.answered_at
+                 FROM agent_questions q
+                 LEFT JOIN messages m ON m.id = q.answer_message_id
+                 WHERE q.doc_id = ?1 AND (?2 IS NULL OR q.status = ?2)
+                 ORDER BY q.id",
+            )?;
+            let questions = stmt
+                .query_map(params![doc_id, status], |row| {
+                    Ok(AgentQuestion {
+                        id: row.get(0)?,
+                        from_agent: row.get(1)?,
+                        to_agent: row.get(2)?,
+                        question: row.get(3)?,
+                        status: row.get(4)?,
+                        age: row.get(5)?,
+                        answer_message_id: row.get(6)?,
+                        answer: row.get(7)?,
+                        created_at: row.get(8)?,
+                        answered_at: row.get(9)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(questions)
+        })
+        .await
+    }
+
+    pub async fn get_title(&self, doc_id: &str) -> anyhow::Result<Option<String>> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            let result = conn.query_row(
+                "SELECT title FROM documents WHERE id = ?1",
+                params![doc_id],
+                |row| row.get(0),
+            );
+            match result {
+                Ok(title) => Ok(title),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    pub async fn set_title(&self, doc_id: &str, title: &str) -> anyhow::Result<()> {
+        let doc_id = doc_id.to_string();
+        let title = title.to_string();
+        self.write(move |conn| {
+            conn.execute(
+                "UPDATE documents SET title = ?1 WHERE id = ?2",
+                params![title, doc_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Archive or unarchive a document. Returns false if it doesn't exist.
+    pub async fn set_archived(&self, doc_id: &str, archived: bool) -> anyhow::Result<bool> {
+        let doc_id = doc_id.to_string();
+        self.write(move |conn| {
+            let sql = if archived {
+                "UPDATE documents SET archived_at = COALESCE(archived_at, datetime('now')) WHERE id = ?1"
+            } else {
+                "UPDATE documents SET archived_at = NULL WHERE id = ?1"
+            };
+            Ok(conn.execute(sql, params![doc_id])? > 0)
+        })
+        .await
+    }
+
+    /// Copy a document's tree, edges, title, personas and settings to
+    /// `new_id`. Conversation history, threads and summaries are not copied.
+    pub async fn duplicate_document(&self, doc_id: &str, new_id: &str, title: Option<&str>) -> anyhow::Result<()> {
+        let doc_id = doc_id.to_string();
+        let new_id = new_id.to_string();
+        let title = title.map(str::to_string);
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            copy_document(&tx, &doc_id, &new_id, title.as_deref())?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Copy a document like `duplicate_document`, and remember the parent and
+    /// its tree and edges at this moment as the base for merging back.
+    pub async fn fork_document(&self, doc_id: &str, new_id: &str, title: Option<&str>) -> anyhow::Result<()> {
+        let doc_id = doc_id.to_string();
+        let new_id = new_id.to_string();
+        let title = title.map(str::to_string);
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            copy_document(&tx, &doc_id, &new_id, title.as_deref())?;
+            tx.execute(
+                "UPDATE documents SET forked_from = ?1, forked_at = datetime('now'),
+                    fork_base_tree = (SELECT tree FROM documents WHERE id = ?1),
+                    fork_base_edges = (SELECT edges FROM documents WHERE id = ?1)
+                 WHERE id = ?2",
+                params![doc_id, new_id],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// The parent's tree and edges as they were at the fork point, or as of
+    /// the last merge back.
+    pub async fn get_fork_base(&self, doc_id: &str) -> anyhow::Result<Option<(TreeNode, Vec<Edge>)>> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            let result = conn.query_row(
+                "SELECT fork_base_tree, fork_base_edges FROM documents WHERE id = ?1",
+                params![doc_id],
+                |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, Option<String>>(1)?)),
+            );
+            match result {
+                Ok((Some(tree), edges)) => Ok(Some((
+                    serde_json::from_str(&tree)?,
+                    serde_json::from_str(edges.as_deref().unwrap_or("[]"))?,
+                ))),
+                Ok((None, _)) | Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    /// Write a merge into the parent and move the fork's base forward to the
+    /// fork's current state, so the same changes aren't offered again.
+    pub async fn apply_merge(
+        &self,
+        parent_id: &str,
+        fork_id: &str,
+        tree: &TreeNode,
+        edges: &[Edge],
+    ) -> anyhow::Result<()> {
+        metrics::tree_saved(count_nodes(tree), edges.len());
+        let tree_json = serde_json::to_string(tree)?;
+        let edges_json = serde_json::to_string(edges)?;
+        let parent_id = parent_id.to_string();
+        let fork_id = fork_id.to_string();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "UPDATE documents SET tree = ?1, edges = ?2, updated_at = datetime('now') WHERE id = ?3",
+                params![tree_json, edges_json, parent_id],
+            )?;
+            record_revision(&tx, &parent_id)?;
+            tx.execute(
+                "UPDATE documents SET fork_base_tree = tree, fork_base_edges = edges WHERE id = ?1",
+                params![fork_id],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn list_revisions(&self, doc_id: &str) -> anyhow::Result<Vec<RevisionInfo>> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, created_at FROM doc_revisions WHERE doc_id = ?1 ORDER BY id",
+            )?;
+            let revisions = stmt
+                .query_map(params![doc_id], |row| {
+                    Ok(RevisionInfo {
+                        id: row.get(0)?,
+                        created_at: row.get(1)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(revisions)
+        })
+        .await
+    }
+
+    /// A revision's tree, edges and timestamp.
+    pub async fn get_revision(
+        &self,
+        doc_id: &str,
+        revision_id: i64,
+    ) -> anyhow::Result<Option<(TreeNode, Vec<Edge>, String)>> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            revision_where(
+                conn,
+                "doc_id = ?1 AND id = ?2",
+                params![doc_id, revision_id],
+            )
+        })
+        .await
+    }
+
+    /// The state as of `timestamp`: the latest revision at or before it, or
+    /// the earliest one if the document is younger than that.
+    pub async fn get_revision_at(
+        &self,
+        doc_id: &str,
+        timestamp: &str,
+    ) -> anyhow::Result<Option<(TreeNode, Vec<Edge>, String)>> {
+        let doc_id = doc_id.to_string();
+        let timestamp = timestamp.to_string();
+        self.read(move |conn| {
+            let at = revision_where(
+                conn,
+                "doc_id = ?1 AND created_at <= datetime(?2) ORDER BY id DESC",
+                params![doc_id, timestamp],
+            )?;
+            match at {
+                Some(rev) => Ok(Some(rev)),
+                None => revision_where(conn, "doc_id = ?1 ORDER BY id", params![doc_id]),
+            }
+        })
+        .await
+    }
+
+    /// Every document, most recently updated first.
+    pub async fn list_documents(&self) -> anyhow::Result<Vec<DocumentListing>> {
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, title, created_at, updated_at, archived_at FROM documents
+                 ORDER BY updated_at DESC, id",
+            )?;
+            let docs = stmt
+                .query_map([], |row| {
+                    Ok(DocumentListing {
+                        id: row.get(0)?,
+                        title: row.get(1)?,
+                        created_at: row.get(2)?,
+                        updated_at: row.get(3)?,
+                        archived_at: row.get(4)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(docs)
+        })
+        .await
+    }
+
+    /// Everything attached to a document, for `import_bundle` elsewhere.
+    /// Summaries and agent questions are left out; they are regenerated.
+    pub async fn export_bundle(&self, doc_id: &str) -> anyhow::Result<Option<DocumentBundle>> {
+        let doc_id = doc_id.to_string();
+        self.read(move |conn| {
+            // One read transaction, so the pieces agree with each other
+            let tx = conn.unchecked_transaction()?;
+            let Some(document) = get_document_inner(&tx, &doc_id)? else {
+                return Ok(None);
+            };
+            let messages = tx
+                .prepare(
+                    "SELECT id, doc_id, role, content, hover_node_id, personality, thread_node_id, created_at
+                     FROM messages WHERE doc_id = ?1 ORDER BY id",
+                )?
+                .query_map(params![doc_id], message_from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            let personalities = tx
+                .prepare(
+                    "SELECT personality_id, weight FROM doc_personalities
+                     WHERE doc_id = ?1 ORDER BY personality_id",
+                )?
+                .query_map(params![doc_id], |row| {
+                    Ok(BundlePersonality {
+                        id: row.get(0)?,
+                        weight: row.get(1)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            let settings = tx
+                .query_row(
+                    "SELECT heartbeat_dice_sides, repel_force, max_new_nodes, max_deletions,
+                        max_edges, max_tree_size, speaker_strategy
+                     FROM doc_settings WHERE doc_id = ?1",
+                    params![doc_id],
+                    |row| {
+                        Ok(BundleSettings {
+                            dice_sides: row.get(0)?,
+                            repel_force: row.get(1)?,
+                            budget: ChangeBudget {
+                                max_new_nodes: row.get(2)?,
+                                max_deletions: row.get(3)?,
+                                max_edges: row.get(4)?,
+                                max_tree_size: row.get(5)?,
+                            },
+                            speaker_strategy: SpeakerStrategy::parse(&row.get::<_, String>(6)?)
+                                .unwrap_or_default(),
+                        })
+                    },
+                )
+                .optional()?;
+            let revisions = tx
+                .prepare("SELECT tree, edges, created_at FROM doc_revisions WHERE doc_id = ?1 ORDER BY id")?
+                .query_map(params![doc_id], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+                })?
+                .map(|r| {
+                    let (tree, edges, created_at) = r?;
+                    Ok(BundleRevision {
+                        tree: serde_json::from_str(&tree)?,
+                        edges: serde_json::from_str(&edges)?,
+                        created_at,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(Some(DocumentBundle {
+                bundle_version: BUNDLE_VERSION,
+                document,
+                messages,
+                personalities,
+                settings,
+                revisions,
+            }))
+        })
+        .await
+    }
+
+    /// Recreate a bundled document under `new_id` in one transaction. The
+    /// link to a fork parent is dropped, since the parent may not be here.
+    pub async fn import_bundle(&self, new_id: &str, bundle: DocumentBundle) -> anyhow::Result<()> {
+        if bundle.bundle_version > BUNDLE_VERSION {
+            anyhow::bail!(
+                "Bundle version {} is newer than this build understands ({BUNDLE_VERSION})",
+                bundle.bundle_version
+            );
         }
+        let new_id = new_id.to_string();
+        self.write(move |conn| {
+            let doc = &bundle.document;
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT INTO documents (id, tree, edges, title, created_at, updated_at, archived_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    new_id,
+                    serde_json::to_string(&doc.tree)?,
+                    serde_json::to_string(&doc.edges)?,
+                    doc.title,
+                    doc.created_at,
+                    doc.updated_at,
+                    doc.archived_at,
+                ],
+            )?;
+            for m in &bundle.messages {
+                tx.execute(
+                    "INSERT INTO messages (doc_id, role, content, hover_node_id, personality, thread_node_id, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        new_id,
+                        m.role,
+                        m.content,
+                        m.hover_node_id,
+                        m.personality,
+                        m.thread_node_id,
+                        m.created_at,
+                    ],
+                )?;
+            }
+            for p in &bundle.personalities {
+                tx.execute(
+                    "INSERT INTO doc_personalities (doc_id, personality_id, weight) VALUES (?1, ?2, ?3)",
+                    params![new_id, p.id, p.weight],
+                )?;
+            }
+            if let Some(s) = &bundle.settings {
+                tx.execute(
+                    "INSERT INTO doc_settings (doc_id, heartbeat_dice_sides, repel_force, max_new_nodes,
+                        max_deletions, max_edges, max_tree_size, speaker_strategy)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![
+                        new_id,
+                        s.dice_sides,
+                        s.repel_force,
+                        s.budget.max_new_nodes,
+                        s.budget.max_deletions,
+                        s.budget.max_edges,
+                        s.budget.max_tree_size,
+                        s.speaker_strategy.as_str(),
+                    ],
+                )?;
+            }
+            for r in &bundle.revisions {
+                tx.execute(
+                    "INSERT INTO doc_revisions (doc_id, tree, edges, created_at) VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        new_id,
+                        serde_json::to_string(&r.tree)?,
+                        serde_json::to_string(&r.edges)?,
+                        r.created_at,
+                    ],
+                )?;
+            }
+            record_revision(&tx, &new_id)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
     }
 
-    pub fn set_title(&self, doc_id: &str, title: &str) -> anyhow::Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "UPDATE documents SET title = ?1 WHERE id = ?2",
-            params![title, doc_id],
-        )?;
+    /// Copy the whole database to `dest` with SQLite's online backup API.
+    /// Reads through its own connection, so requests keep running meanwhile.
+    /// The copy is written next to `dest` and renamed into place when done.
+    pub fn backup_to(&self, dest: &Path) -> anyhow::Result<()> {
+        let src = Connection::open_with_flags(&self.inner.path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let partial = dest.with_extension("partial");
+        let _ = std::fs::remove_file(&partial);
+        {
+            let mut dst = Connection::open(&partial)?;
+            let backup = Backup::new(&src, &mut dst)?;
+            backup.run_to_completion(256, Duration::from_millis(5), None)?;
+        }
+        std::fs::rename(&partial, dest)?;
         Ok(())
     }
+
+    /// Delete a document and everything attached to it in one transaction.
+    /// Returns false if it doesn't exist.
+    pub async fn delete_document(&self, doc_id: &str) -> anyhow::Result<bool> {
+        let doc_id = doc_id.to_string();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            for table in [
+                "messages",
+                "doc_personalities",
+                "doc_summaries",
+                "agent_questions",
+                "doc_settings",
+                "doc_revisions",
+            ] {
+                tx.execute(&format!("DELETE FROM {table} WHERE doc_id = ?1"), params![doc_id])?;
+            }
+            let deleted = tx.execute("DELETE FROM documents WHERE id = ?1", params![doc_id])?;
+            tx.commit()?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+}
+
+fn message_from_row(row: &rusqlite::Row) -> rusqlite::Result<Message> {
+    Ok(Message {
+        id: row.get(0)?,
+        doc_id: row.get(1)?,
+        role: row.get(2)?,
+        content: row.get(3)?,
+        hover_node_id: row.get(4)?,
+        personality: row.get(5)?,
+        thread_node_id: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+/// Snapshot the document's current tree and edges, unless they match the
+/// latest revision already.
+fn record_revision(conn: &Connection, doc_id: &str) -> anyhow::Result<()> {
+    conn.execute(
+        "INSERT INTO doc_revisions (doc_id, tree, edges)
+         SELECT d.id, d.tree, d.edges FROM documents d
+         WHERE d.id = ?1 AND NOT EXISTS (
+            SELECT 1 FROM doc_revisions r
+            WHERE r.id = (SELECT MAX(id) FROM doc_revisions WHERE doc_id = ?1)
+              AND r.tree = d.tree AND r.edges = d.edges
+         )",
+        params![doc_id],
+    )?;
+    Ok(())
+}
+
+fn copy_document(tx: &Transaction, doc_id: &str, new_id: &str, title: Option<&str>) -> anyhow::Result<()> {
+    tx.execute(
+        "INSERT INTO documents (id, tree, edges, title)
+         SELECT ?2, tree, edges, ?3 FROM documents WHERE id = ?1",
+        params![doc_id, new_id, title],
+    )?;
+    record_revision(tx, new_id)?;
+    tx.execute(
+        "INSERT INTO doc_personalities (doc_id, personality_id, weight)
+         SELECT ?2, personality_id, weight FROM doc_personalities WHERE doc_id = ?1",
+        params![doc_id, new_id],
+    )?;
+    tx.execute(
+        "INSERT INTO doc_settings (doc_id, heartbeat_dice_sides, repel_force, max_new_nodes,
+            max_deletions, max_edges, max_tree_size, speaker_strategy, speaker_cursor)
+         SELECT ?2, heartbeat_dice_sides, repel_force, max_new_nodes,
+            max_deletions, max_edges, max_tree_size, speaker_strategy, speaker_cursor
+         FROM doc_settings WHERE doc_id = ?1",
+        params![doc_id, new_id],
+    )?;
+    Ok(())
+}
+
+fn revision_where(
+    conn: &Connection,
+    condition: &str,
+    params: impl rusqlite::Params,
+) -> anyhow::Result<Option<(TreeNode, Vec<Edge>, String)>> {
+    let result = conn.query_row(
+        &format!("SELECT tree, edges, created_at FROM doc_revisions WHERE {condition} LIMIT 1"),
+        params,
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)),
+    );
+    match result {
+        Ok((tree, edges, created_at)) => Ok(Some((
+            serde_json::from_str(&tree)?,
+            serde_json::from_str(&edges)?,
+            created_at,
+        ))),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
 }
 
-fn get_document_inner(conn: &Connection, id: &str) -> anyhow::Result<Document> {
-    let mut stmt =
-        conn.prepare("SELECT id, tree, created_at, updated_at, edges, title FROM documents WHERE id = ?1")?;
-    let doc = stmt.query_row(params![id], |row| {
-        let tree_str: String = row.get(1)?;
-        let edges_str: String = row.get::<_, String>(4).unwrap_or_else(|_| "[]".to_string());
-        Ok(Document {
-            id: row.get(0)?,
-            tree: serde_json::from_str(&tree_str).unwrap(),
-            edges: serde_json::from_str(&edges_str).unwrap_or_default(),
-            title: row.get(5)?,
-            created_at: row.get(2)?,
-            updated_at: row.get(3)?,
-        })
-    })?;
-    Ok(doc)
+/// The document, or None if there is no such id. A tree or edge list that
+/// no longer parses is an error rather than a missing document.
+fn get_document_inner(conn: &Connection, id: &str) -> anyhow::Result<Option<Document>> {
+    let row = conn
+        .query_row(
+            "SELECT id, tree, created_at, updated_at, edges, title, archived_at, forked_from, forked_at
+             FROM documents WHERE id = ?1",
+            params![id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(4)?,
+                    // Tree and edges are parsed below, where a bad one can be reported
+                    Document {
+                        id: row.get(0)?,
+                        tree: default_tree(),
+                        edges: vec![],
+                        title: row.get(5)?,
+                        created_at: row.get(2)?,
+                        updated_at: row.get(3)?,
+                        archived_at: row.get(6)?,
+                        forked_from: row.get(7)?,
+                        forked_at: row.get(8)?,
+                        thread_counts: HashMap::new(),
+                    },
+                ))
+            },
+        )
+        .optional()?;
+    let Some((tree_str, edges_str, mut doc)) = row else {
+        return Ok(None);
+    };
+    doc.tree = serde_json::from_str(&tree_str)
+        .with_context(|| format!("Document {id} has a corrupt tree"))?;
+    doc.edges = serde_json::from_str(edges_str.as_deref().unwrap_or("[]"))
+        .with_context(|| format!("Document {id} has corrupt edges"))?;
+    let mut stmt = conn.prepare(
+        "SELECT thread_node_id, COUNT(*) FROM messages
+         WHERE doc_id = ?1 AND thread_node_id IS NOT NULL
+         GROUP BY thread_node_id",
+    )?;
+    doc.thread_counts = stmt
+        .query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<HashMap<String, i64>, _>>()?;
+    Ok(Some(doc))
+}
+
+/// Open a connection in WAL mode with a busy timeout.
+fn open(path: &str) -> anyhow::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    // Safe with WAL: a crash can lose the last commits but not corrupt the file
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    Ok(conn)
 }
 
 fn default_tree() -> TreeNode {
@@ -374,3 +1254,86 @@ fn default_tree() -> TreeNode {
         children: vec![],
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn every_reader_sees_committed_writes() {
+        let dir = std::env::temp_dir().join(format!("grove-db-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Db::new(&dir.join("grove.db").display().to_string()).unwrap();
+        let mode: String = db
+            .read(|conn| Ok(conn.query_row("PRAGMA journal_mode", [], |row| row.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(mode, "wal");
+
+        db.create_document("doc").await.unwrap();
+        let ids = vec!["munger".to_string(), "feynman".to_string()];
+        db.set_personalities("doc", &ids, &HashMap::from([("feynman".to_string(), 2.0)]))
+            .await
+            .unwrap();
+        db.update_settings(
+            "doc",
+            UpdateSettingsRequest {
+                dice_sides: Some(6),
+                repel_force: None,
+                max_new_nodes: Some(2),
+                max_deletions: None,
+                max_edges: None,
+                max_tree_size: None,
+                speaker_strategy: Some(SpeakerStrategy::RoundRobin),
+            },
+        )
+        .await
+        .unwrap();
+        for _ in 0..READERS {
+            assert_eq!(db.get_active_personalities("doc").await.unwrap(), ["feynman", "munger"]);
+            assert_eq!(db.get_dice_sides("doc").await.unwrap(), 6);
+            assert_eq!(db.get_repel_force("doc").await.unwrap(), 20.0);
+            let budget = db.get_change_budget("doc").await.unwrap();
+            assert_eq!(budget.max_new_nodes, 2);
+            assert_eq!(budget.max_deletions, ChangeBudget::default().max_deletions);
+            assert_eq!(db.get_speaker_strategy("doc").await.unwrap().0, SpeakerStrategy::RoundRobin);
+        }
+        // Leaving weights out keeps the stored ones; a new persona gets 1.0
+        let ids = vec!["feynman".to_string(), "rams".to_string()];
+        db.set_personalities("doc", &ids, &HashMap::new()).await.unwrap();
+        let weights = db.get_personality_weights("doc").await.unwrap();
+        assert_eq!(weights, HashMap::from([("feynman".to_string(), 2.0), ("rams".to_string(), 1.0)]));
+        // Readers refuse writes
+        assert!(db.read(|conn| Ok(conn.execute("DELETE FROM documents", [])?)).await.is_err());
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn corrupt_documents_are_errors_not_missing() {
+        let db = Db::new(":memory:").unwrap();
+        db.create_document("doc").await.unwrap();
+        assert!(db.get_document("nope").await.unwrap().is_none());
+        db.write(|conn| Ok(conn.execute("UPDATE documents SET tree = '{' WHERE id = 'doc'", [])?))
+            .await
+            .unwrap();
+        let err = db.get_document("doc").await.unwrap_err();
+        assert!(err.to_string().contains("corrupt tree"));
+    }
+
+    #[tokio::test]
+    async fn marking_nodes_seen_records_no_revision() {
+        let db = Db::new(":memory:").unwrap();
+        let mut doc = db.create_document("doc").await.unwrap();
+        doc.tree.seen = false;
+        db.update_tree("doc", &doc.tree, &doc.edges).await.unwrap();
+        assert_eq!(db.list_revisions("doc").await.unwrap().len(), 2);
+
+        doc.tree.mark_seen(&doc.tree.id.clone());
+        db.set_tree_seen("doc", &doc.tree).await.unwrap();
+        assert!(db.get_document("doc").await.unwrap().unwrap().tree.seen);
+        assert_eq!(db.list_revisions("doc").await.unwrap().len(), 2);
+    }
+}
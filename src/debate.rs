@@ -0,0 +1,391 @@
+use std::fmt::Write;
+
+use serde_json::{json, Value};
+
+use crate::llm::{add_child, count_nodes, node_focus_text, Personality};
+use crate::models::{DebatePoint, Edge, TreeNode};
+
+pub const DEFAULT_ROUNDS: u32 = 2;
+pub const MAX_ROUNDS: u32 = 5;
+pub const MAX_DEBATERS: usize = 6;
+
+pub const RELATIONS: &[&str] = &["contradicts", "builds on"];
+
+/// What the model hands back for one turn or for the closing synthesis.
+pub struct Turn {
+    pub label: String,
+    pub prose: String,
+    pub responds_to: Option<String>,
+    pub relation: Option<String>,
+}
+
+/// A debate in progress over one focal node. Points are kept here and only
+/// written into the tree once every turn has succeeded.
+pub struct Debate {
+    pub root_id: String,
+    pub focal_id: String,
+    pub points: Vec<DebatePoint>,
+}
+
+impl Debate {
+    /// Pick a root id (`debate-1`, `debate-2`, ...) that isn't taken yet.
+    pub fn new(tree: &TreeNode, focal_id: &str) -> Self {
+        let root_id = (1..)
+            .map(|n| format!("debate-{n}"))
+            .find(|id| tree.find(id).is_none())
+            .expect("unbounded range");
+        Self {
+            root_id,
+            focal_id: focal_id.to_string(),
+            points: vec![],
+        }
+    }
+
+    /// Move the debate to a root id that `tree` doesn't use, for when
+    /// another debate claimed this one's while its turns ran.
+    pub fn rename_root(&mut self, tree: &TreeNode) {
+        let old = std::mem::replace(&mut self.root_id, Self::new(tree, &self.focal_id).root_id);
+        let rename = |id: &mut String| {
+            if let Some(rest) = id.strip_prefix(&old) {
+                *id = format!("{}{rest}", self.root_id);
+            }
+        };
+        for p in &mut self.points {
+            rename(&mut p.id);
+            if p.responds_to != self.focal_id {
+                rename(&mut p.responds_to);
+            }
+        }
+    }
+
+    /// Nodes a debate adds: the root, one per turn, and the synthesis.
+    pub fn nodes_needed(debaters: usize, rounds: u32) -> usize {
+        2 + debaters * rounds as usize
+    }
+
+    /// Record a turn. A missing or unknown `responds_to` falls back to the
+    /// previous point, or to the focal node for the opening claim.
+    pub fn add_point(&mut self, personality: &Personality, round: u32, turn: Turn) {
+        let known = |id: &str| id == self.focal_id || self.points.iter().any(|p| p.id == id);
+        let responds_to = match turn.responds_to {
+            Some(id) if known(&id) => id,
+            _ => self
+                .points
+                .last()
+                .map(|p| p.id.clone())
+                .unwrap_or_else(|| self.focal_id.clone()),
+        };
+        let relation = match turn.relation {
+            Some(r) if RELATIONS.contains(&r.as_str()) => r,
+            _ if responds_to == self.focal_id => "builds on".to_string(),
+            _ => "contradicts".to_string(),
+        };
+        self.points.push(DebatePoint {
+            id: format!("{}-{}-{}", self.root_id, round, personality.id),
+            personality: personality.id.to_string(),
+            round,
+            label: turn.label,
+            prose: turn.prose,
+            responds_to,
+            relation,
+        });
+    }
+
+    /// The debate so far, as the next speaker reads it.
+    pub fn transcript(&self) -> String {
+        let mut out = String::new();
+        for p in &self.points {
+            let _ = writeln!(
+                out,
+                "[{}] {} (round {}, {} {}): {}\n{}\n",
+                p.id, p.personality, p.round, p.relation, p.responds_to, p.label, p.prose
+            );
+        }
+        if out.is_empty() {
+            out.push_str("(no points yet — you open the debate)\n");
+        }
+        out
+    }
+
+    /// Attach the debate under the focal node: a root, every point as its
+    /// child, and the synthesis last. Each point gets an edge to what it
+    /// answers; the synthesis builds on the focal node.
+    pub fn write_into(
+        &self,
+        tree: &mut TreeNode,
+        edges: &mut Vec<Edge>,
+        debaters: &[&Personality],
+        synthesis: Turn,
+    ) -> TreeNode {
+        let names: Vec<&str> = debaters.iter().map(|p| p.name).collect();
+        let rounds = self.points.iter().map(|p| p.round).max().unwrap_or(0);
+        let mut root = TreeNode {
+            id: self.root_id.clone(),
+            label: format!("Debate: {}", names.join(" vs ")),
+            prose: format!("A {rounds}-round debate between {}.", names.join(", ")),
+            heat: "hot".to_string(),
+            by: "claude".to_string(),
+            seen: false,
+            children: vec![],
+        };
+        for p in &self.points {
+            root.children.push(TreeNode {
+                id: p.id.clone(),
+                label: p.label.clone(),
+                prose: p.prose.clone(),
+                heat: "warm".to_string(),
+                by: format!("claude:{}", p.personality),
+                seen: false,
+                children: vec![],
+            });
+            edges.push(Edge {
+                source: p.id.clone(),
+                target: p.responds_to.clone(),
+                label: p.relation.clone(),
+            });
+        }
+        let synthesis = TreeNode {
+            id: format!("{}-synthesis", self.root_id),
+            label: synthesis.label,
+            prose: synthesis.prose,
+            heat: "hot".to_string(),
+            by: "claude".to_string(),
+            seen: false,
+            children: vec![],
+        };
+        root.children.push(synthesis.clone());
+        edges.push(Edge {
+            source: synthesis.id.clone(),
+            target: self.focal_id.clone(),
+            label: "builds on".to_string(),
+        });
+        add_child(tree, &self.focal_id, root);
+        synthesis
+    }
+}
+
+pub fn turn_system_prompt(
+    tree: &TreeNode,
+    edges: &[Edge],
+    debate: &Debate,
+    personality: &Personality,
+    opponents: &[&Personality],
+    round: u32,
+    rounds: u32,
+) -> anyhow::Result<String> {
+    let focus = node_focus_text(tree, edges, &debate.focal_id)?;
+    let node_count = count_nodes(tree);
+    let others: Vec<&str> = opponents.iter().map(|p| p.name).collect();
+    Ok(format!(
+        r#"You are {name}, one of several voices in a shared thinking tree in Grove — a co-creative visual canvas where thoughts grow between minds.
+
+{fragment}
+
+You are in a structured debate with {others} over a single node of the tree ({node_count} nodes in total). {focus}
+
+This is round {round} of {rounds}. Make one point with make_point. Answer a specific earlier point where you can — say whether it contradicts or builds on it — and argue from your own perspective. Disagree when you disagree; don't split the difference to be polite.
+
+A point is a node: a short label and a paragraph or two of prose. Brevity is respected. Padding is not."#,
+        name = personality.name,
+        fragment = personality.system_prompt_fragment,
+        others = others.join(" and "),
+    ))
+}
+
+pub fn synthesis_system_prompt(
+    tree: &TreeNode,
+    edges: &[Edge],
+    debate: &Debate,
+) -> anyhow::Result<String> {
+    let focus = node_focus_text(tree, edges, &debate.focal_id)?;
+    Ok(format!(
+        r#"You are Claude, one of several voices in a shared thinking tree in Grove — a co-creative visual canvas where thoughts grow between minds.
+
+A structured debate has just finished over a single node of the tree. {focus}
+
+Write the closing synthesis with synthesize. Say where the debate landed: what survived, what didn't, and what is still genuinely open. Don't declare a winner for the sake of it, and don't paper over a real disagreement.
+
+Brevity is respected. Padding is not."#
+    ))
+}
+
+pub fn make_point_tool() -> Value {
+    json!({
+        "name": "make_point",
+        "description": "Make your point in the debate. It becomes a node in the tree.",
+        "input_schema": {
+            "type": "object",
+            "properties": {
+                "label": {
+                    "type": "string",
+                    "description": "Short visible name for the point (2-6 words)"
+                },
+                "prose": {
+                    "type": "string",
+                    "description": "The argument itself"
+                },
+                "responds_to": {
+                    "type": "string",
+                    "description": "ID of the earlier point (or the focal node) this answers"
+                },
+                "relation": {
+                    "type": "string",
+                    "enum": RELATIONS,
+                    "description": "How this point relates to the one it answers"
+                }
+            },
+            "required": ["label", "prose"]
+        }
+    })
+}
+
+pub fn synthesize_tool() -> Value {
+    json!({
+        "name": "synthesize",
+        "description": "Close the debate with a synthesis node.",
+        "input_schema": {
+            "type": "object",
+            "properties": {
+                "label": {
+                    "type": "string",
+                    "description": "Short visible name for the synthesis (2-6 words)"
+                },
+                "prose": {
+                    "type": "string",
+                    "description": "Where the debate landed"
+                }
+            },
+            "required": ["label", "prose"]
+        }
+    })
+}
+
+/// Read the forced tool call `tool_name` out of an API response.
+pub fn parse_turn(response: &Value, tool_name: &str) -> anyhow::Result<Turn> {
+    let input = response["content"]
+        .as_array()
+        .ok_or_else(|| anyhow::anyhow!("No content in response"))?
+        .iter()
+        .find(|b| b["type"].as_str() == Some("tool_use") && b["name"].as_str() == Some(tool_name))
+        .map(|b| &b["input"])
+        .ok_or_else(|| anyhow::anyhow!("Response has no {tool_name} call"))?;
+    let field = |name: &str| input[name].as_str().map(str::to_string);
+    let label = field("label")
+        .filter(|l| !l.trim().is_empty())
+        .ok_or_else(|| anyhow::anyhow!("{tool_name} call has no label"))?;
+    Ok(Turn {
+        label,
+        prose: field("prose").unwrap_or_default(),
+        responds_to: field("responds_to"),
+        relation: field("relation"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::get_personality;
+
+    fn node(id: &str, children: Vec<TreeNode>) -> TreeNode {
+        TreeNode {
+            id: id.to_string(),
+            label: id.to_string(),
+            prose: String::new(),
+            heat: "warm".to_string(),
+            by: "human".to_string(),
+            seen: true,
+            children,
+        }
+    }
+
+    fn turn(label: &str, responds_to: Option<&str>, relation: Option<&str>) -> Turn {
+        Turn {
+            label: label.to_string(),
+            prose: format!("{label} prose"),
+            responds_to: responds_to.map(str::to_string),
+            relation: relation.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn unknown_responses_fall_back_to_the_previous_point() {
+        let tree = node("root", vec![node("claim", vec![])]);
+        let feynman = get_personality("feynman").unwrap();
+        let munger = get_personality("munger").unwrap();
+        let mut debate = Debate::new(&tree, "claim");
+
+        // The opening point answers the focal node
+        debate.add_point(feynman, 1, turn("Opening", Some("nowhere"), None));
+        assert_eq!(debate.points[0].id, "debate-1-1-feynman");
+        assert_eq!(debate.points[0].responds_to, "claim");
+        assert_eq!(debate.points[0].relation, "builds on");
+
+        // Later ones answer the point before them, and an unknown relation
+        // reads as disagreement
+        debate.add_point(munger, 1, turn("Rebuttal", None, Some("agrees")));
+        assert_eq!(debate.points[1].responds_to, "debate-1-1-feynman");
+        assert_eq!(debate.points[1].relation, "contradicts");
+
+        // A known target and relation are kept as given
+        debate.add_point(feynman, 2, turn("Back to it", Some("claim"), Some("contradicts")));
+        assert_eq!(debate.points[2].responds_to, "claim");
+        assert_eq!(debate.points[2].relation, "contradicts");
+    }
+
+    #[test]
+    fn writes_a_root_with_every_point_and_the_synthesis_under_the_focal_node() {
+        let mut tree = node("root", vec![node("claim", vec![]), node("debate-1", vec![])]);
+        let mut edges = vec![];
+        let feynman = get_personality("feynman").unwrap();
+        let munger = get_personality("munger").unwrap();
+        let mut debate = Debate::new(&tree, "claim");
+        assert_eq!(debate.root_id, "debate-2");
+        debate.add_point(feynman, 1, turn("Opening", None, None));
+        debate.add_point(munger, 1, turn("Rebuttal", None, None));
+
+        let synthesis = debate.write_into(
+            &mut tree,
+            &mut edges,
+            &[feynman, munger],
+            turn("Where it landed", None, None),
+        );
+
+        let root = &tree.find("claim").unwrap().children[0];
+        assert_eq!(root.id, "debate-2");
+        let ids: Vec<&str> = root.children.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["debate-2-1-feynman", "debate-2-1-munger", "debate-2-synthesis"]);
+        assert_eq!(root.children[1].by, "claude:munger");
+        assert_eq!(synthesis.id, "debate-2-synthesis");
+
+        let edges: Vec<(&str, &str, &str)> = edges
+            .iter()
+            .map(|e| (e.source.as_str(), e.target.as_str(), e.label.as_str()))
+            .collect();
+        assert_eq!(
+            edges,
+            [
+                ("debate-2-1-feynman", "claim", "builds on"),
+                ("debate-2-1-munger", "debate-2-1-feynman", "contradicts"),
+                ("debate-2-synthesis", "claim", "builds on"),
+            ]
+        );
+    }
+
+    #[test]
+    fn renaming_the_root_keeps_points_answering_each_other() {
+        let tree = node("root", vec![node("claim", vec![])]);
+        let feynman = get_personality("feynman").unwrap();
+        let munger = get_personality("munger").unwrap();
+        let mut debate = Debate::new(&tree, "claim");
+        debate.add_point(feynman, 1, turn("Opening", None, None));
+        debate.add_point(munger, 1, turn("Rebuttal", None, None));
+
+        // Another debate took `debate-1` while this one ran
+        let tree = node("root", vec![node("claim", vec![node("debate-1", vec![])])]);
+        debate.rename_root(&tree);
+        assert_eq!(debate.root_id, "debate-2");
+        assert_eq!(debate.points[0].id, "debate-2-1-feynman");
+        assert_eq!(debate.points[0].responds_to, "claim");
+        assert_eq!(debate.points[1].responds_to, "debate-2-1-feynman");
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::merge::{flatten, pair_key};
+use crate::models::{
+    DiffChunk, Edge, EditedNode, FieldChange, MovedNode, NodeRef, RelabeledEdge, TreeDiff, TreeNode,
+};
+
+/// Fields reported as edits. Moves are reported separately; `by` and `seen`
+/// are bookkeeping.
+const EDIT_FIELDS: &[&str] = &["label", "prose", "heat"];
+
+/// Word diffs above this many word pairs fall back to delete-all, insert-all.
+const MAX_DIFF_CELLS: usize = 250_000;
+
+/// Structural diff from one tree state to another.
+pub fn diff(before: &TreeNode, before_edges: &[Edge], after: &TreeNode, after_edges: &[Edge]) -> TreeDiff {
+    let (old, old_order) = flatten(before);
+    let (new, new_order) = flatten(after);
+    let mut out = TreeDiff::default();
+
+    for id in &new_order {
+        let n = &new[id];
+        let Some(o) = old.get(id) else {
+            out.added_nodes.push(NodeRef {
+                id: id.clone(),
+                label: n.label.clone(),
+                parent: Some(n.parent.clone()).filter(|p| !p.is_empty()),
+            });
+            continue;
+        };
+        if o.parent != n.parent {
+            out.moved_nodes.push(MovedNode {
+                id: id.clone(),
+                label: n.label.clone(),
+                from_parent: o.parent.clone(),
+                to_parent: n.parent.clone(),
+            });
+        }
+        let changes: Vec<FieldChange> = EDIT_FIELDS
+            .iter()
+            .filter(|f| o.field(f) != n.field(f))
+            .map(|f| FieldChange {
+                field: f.to_string(),
+                before: o.field(f).to_string(),
+                after: n.field(f).to_string(),
+                diff: if *f == "prose" {
+                    word_diff(o.field(f), n.field(f))
+                } else {
+                    vec![]
+                },
+            })
+            .collect();
+        if !changes.is_empty() {
+            out.edited_nodes.push(EditedNode {
+                id: id.clone(),
+                label: n.label.clone(),
+                changes,
+            });
+        }
+    }
+    for id in old_order.iter().filter(|id| !new.contains_key(*id)) {
+        let o = &old[id];
+        out.removed_nodes.push(NodeRef {
+            id: id.clone(),
+            label: o.label.clone(),
+            parent: Some(o.parent.clone()).filter(|p| !p.is_empty()),
+        });
+    }
+
+    let old_edges: HashMap<(String, String), &Edge> = before_edges.iter().map(|e| (pair_key(e), e)).collect();
+    let new_edges: HashMap<(String, String), &Edge> = after_edges.iter().map(|e| (pair_key(e), e)).collect();
+    for e in after_edges {
+        match old_edges.get(&pair_key(e)) {
+            None => out.added_edges.push(e.clone()),
+            Some(o) if o.label != e.label => out.relabeled_edges.push(RelabeledEdge {
+                source: e.source.clone(),
+                target: e.target.clone(),
+                before: o.label.clone(),
+                after: e.label.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    out.removed_edges = before_edges
+        .iter()
+        .filter(|e| !new_edges.contains_key(&pair_key(e)))
+        .cloned()
+        .collect();
+    out
+}
+
+/// Word-level diff of two texts via longest common subsequence. Each word
+/// keeps its trailing whitespace, so joining the chunks restores the text.
+pub fn word_diff(before: &str, after: &str) -> Vec<DiffChunk> {
+    let a: Vec<&str> = before.split_inclusive(char::is_whitespace).collect();
+    let b: Vec<&str> = after.split_inclusive(char::is_whitespace).collect();
+    let mut chunks: Vec<DiffChunk> = Vec::new();
+    let mut push = |op: &str, text: &str| match chunks.last_mut() {
+        Some(last) if last.op == op => last.text.push_str(text),
+        _ => chunks.push(DiffChunk {
+            op: op.to_string(),
+            text: text.to_string(),
+        }),
+    };
+
+    if a.len() * b.len() > MAX_DIFF_CELLS {
+        push("delete", before);
+        push("insert", after);
+        return chunks;
+    }
+
+    // lcs[i][j] = length of the LCS of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            push("equal", a[i]);
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            push("delete", a[i]);
+            i += 1;
+        } else {
+            push("insert", b[j]);
+            j += 1;
+        }
+    }
+    chunks
+}
+
+/// A readable report of `diff` for pasting into notes or a chat.
+pub fn render_markdown(diff: &TreeDiff, from: &str, to: &str) -> String {
+    let mut out = format!("# Changes from {from} to {to}\n");
+    let empty = diff.added_nodes.is_empty()
+        && diff.removed_nodes.is_empty()
+        && diff.moved_nodes.is_empty()
+        && diff.edited_nodes.is_empty()
+        && diff.added_edges.is_empty()
+        && diff.removed_edges.is_empty()
+        && diff.relabeled_edges.is_empty();
+    if empty {
+        out.push_str("\nNo changes.\n");
+        return out;
+    }
+
+    let node_list = |out: &mut String, title: &str, nodes: &[NodeRef]| {
+        if nodes.is_empty() {
+            return;
+        }
+        let _ = writeln!(out, "\n## {title} ({})\n", nodes.len());
+        for n in nodes {
+            let _ = match &n.parent {
+                Some(p) => writeln!(out, "- **{}** (`{}`) under `{p}`", n.label, n.id),
+                None => writeln!(out, "- **{}** (`{}`)", n.label, n.id),
+            };
+        }
+    };
+    node_list(&mut out, "Added nodes", &diff.added_nodes);
+    node_list(&mut out, "Removed nodes", &diff.removed_nodes);
+
+    if !diff.moved_nodes.is_empty() {
+        let _ = writeln!(out, "\n## Moved nodes ({})\n", diff.moved_nodes.len());
+        for m in &diff.moved_nodes {
+            let _ = writeln!(
+                out,
+                "- **{}** (`{}`): from `{}` to `{}`",
+                m.label, m.id, m.from_parent, m.to_parent
+            );
+        }
+    }
+
+    if !diff.edited_nodes.is_empty() {
+        let _ = writeln!(out, "\n## Edited nodes ({})", diff.edited_nodes.len());
+        for node in &diff.edited_nodes {
+            let _ = writeln!(out, "\n### {} (`{}`)\n", node.label, node.id);
+            for c in &node.changes {
+                if c.diff.is_empty() {
+                    let _ = writeln!(out, "- {}: \"{}\" → \"{}\"", c.field, c.before, c.after);
+                } else {
+                    let _ = writeln!(out, "- {}: {}", c.field, render_chunks(&c.diff));
+                }
+            }
+        }
+    }
+
+    if !diff.added_edges.is_empty() || !diff.removed_edges.is_empty() || !diff.relabeled_edges.is_empty() {
+        out.push_str("\n## Edges\n\n");
+        for e in &diff.added_edges {
+            let _ = writeln!(out, "- added `{}` → `{}` ({})", e.source, e.target, e.label);
+        }
+        for e in &diff.removed_edges {
+            let _ = writeln!(out, "- removed `{}` → `{}` ({})", e.source, e.target, e.label);
+        }
+        for e in &diff.relabeled_edges {
+            let _ = writeln!(
+                out,
+                "- relabeled `{}` → `{}`: \"{}\" → \"{}\"",
+                e.source, e.target, e.before, e.after
+            );
+        }
+    }
+    out
+}
+
+/// Inline prose diff: deletions struck through, insertions in bold.
+fn render_chunks(chunks: &[DiffChunk]) -> String {
+    let mut out = String::new();
+    for c in chunks {
+        let text = c.text.replace('\n', " ");
+        let (body, trailing) = split_trailing_space(&text);
+        match c.op.as_str() {
+            "insert" => out.push_str(&format!("**{body}**{trailing}")),
+            "delete" => out.push_str(&format!("~~{body}~~{trailing}")),
+            _ => out.push_str(&text),
+        }
+    }
+    out
+}
+
+/// Markdown emphasis can't end in whitespace, so keep it outside the markers.
+fn split_trailing_space(text: &str) -> (&str, &str) {
+    let body = text.trim_end();
+    (body, &text[body.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, label: &str, prose: &str, children: Vec<TreeNode>) -> TreeNode {
+        TreeNode {
+            id: id.to_string(),
+            label: label.to_string(),
+            prose: prose.to_string(),
+            heat: "warm".to_string(),
+            by: "human".to_string(),
+            seen: true,
+            children,
+        }
+    }
+
+    #[test]
+    fn word_diff_round_trips_both_sides() {
+        let before = "The tree grows slowly\nwhen nobody tends it.";
+        let after = "The tree grows fast when everybody tends it.";
+        let chunks = word_diff(before, after);
+        let side = |skip: &str| -> String {
+            chunks.iter().filter(|c| c.op != skip).map(|c| c.text.as_str()).collect()
+        };
+        assert_eq!(side("insert"), before);
+        assert_eq!(side("delete"), after);
+        assert!(chunks.iter().any(|c| c.op == "equal" && c.text.starts_with("The tree grows")));
+    }
+
+    #[test]
+    fn reports_added_removed_moved_and_edited() {
+        let before = node(
+            "root",
+            "Root",
+            "",
+            vec![
+                node("a", "A", "old words here", vec![node("a1", "A1", "", vec![])]),
+                node("b", "B", "", vec![]),
+                node("gone", "Gone", "", vec![]),
+            ],
+        );
+        let after = node(
+            "root",
+            "Root",
+            "",
+            vec![
+                node("a", "A", "new words here", vec![]),
+                node("b", "B", "", vec![node("a1", "A1", "", vec![]), node("c", "C", "", vec![])]),
+            ],
+        );
+        let d = diff(&before, &[], &after, &[]);
+        assert_eq!(d.added_nodes.iter().map(|n| n.id.as_str()).collect::<Vec<_>>(), ["c"]);
+        assert_eq!(d.removed_nodes.iter().map(|n| n.id.as_str()).collect::<Vec<_>>(), ["gone"]);
+        assert_eq!(d.moved_nodes.len(), 1);
+        assert_eq!((d.moved_nodes[0].from_parent.as_str(), d.moved_nodes[0].to_parent.as_str()), ("a", "b"));
+        assert_eq!(d.edited_nodes.len(), 1);
+        assert_eq!(d.edited_nodes[0].changes[0].field, "prose");
+
+        let md = render_markdown(&d, "revision 1", "current");
+        assert!(md.contains("~~old~~ **new** words here"));
+        assert!(md.contains("from `a` to `b`"));
+    }
+
+    #[test]
+    fn reversed_edges_are_relabels_not_replacements() {
+        let tree = node("root", "Root", "", vec![node("a", "A", "", vec![]), node("b", "B", "", vec![])]);
+        let edge = |source: &str, target: &str, label: &str| Edge {
+            source: source.to_string(),
+            target: target.to_string(),
+            label: label.to_string(),
+        };
+        let d = diff(&tree, &[edge("a", "b", "builds on")], &tree, &[edge("b", "a", "builds on")]);
+        assert!(d.added_edges.is_empty() && d.removed_edges.is_empty() && d.relabeled_edges.is_empty());
+
+        let d = diff(&tree, &[edge("a", "b", "builds on")], &tree, &[edge("b", "a", "contradicts")]);
+        assert!(d.added_edges.is_empty() && d.removed_edges.is_empty());
+        assert_eq!(d.relabeled_edges.len(), 1);
+        assert_eq!((d.relabeled_edges[0].before.as_str(), d.relabeled_edges[0].after.as_str()), ("builds on", "contradicts"));
+    }
+}
@@ -0,0 +1,694 @@
+//! End to end: the real router on a temporary database, with the model
+//! played by a local mock of the Messages API that answers from a script.
+
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde_json::{Value, json};
+
+use crate::api::AppState;
+use crate::config::{BackupConfig, LlmConfig, Recording, TaskConfig, Thinking};
+use crate::context::DEFAULT_TREE_TOKEN_BUDGET;
+use crate::db::Db;
+use crate::llm::LlmClient;
+use crate::router;
+
+/// One scripted answer: sent for any request whose system prompt contains
+/// `when` and, if `sent` is given, whose messages contain it. Rules are tried
+/// in order.
+struct Rule {
+    when: &'static str,
+    sent: Option<&'static str>,
+    reply: Value,
+}
+
+#[derive(Default)]
+struct Mock {
+    rules: Vec<Rule>,
+    /// Every request, with the `when` of the rule that answered it, or ""
+    /// if none did.
+    requests: Mutex<Vec<(&'static str, Value)>>,
+}
+
+impl Mock {
+    fn calls(&self, when: &str) -> Vec<Value> {
+        let requests = self.requests.lock().unwrap();
+        requests
+            .iter()
+            .filter(|(w, _)| *w == when)
+            .map(|(_, r)| r.clone())
+            .collect()
+    }
+}
+
+async fn messages(State(mock): State<Arc<Mock>>, Json(body): Json<Value>) -> Json<Value> {
+    let system = body["system"].as_str().unwrap_or_default().to_string();
+    let messages = body["messages"].to_string();
+    let rule = mock
+        .rules
+        .iter()
+        .find(|r| system.contains(r.when) && r.sent.is_none_or(|sent| messages.contains(sent)));
+    mock.requests
+        .lock()
+        .unwrap()
+        .push((rule.map_or("", |r| r.when), body));
+    let Some(rule) = rule else {
+        panic!("No scripted reply for system prompt: {system}");
+    };
+    Json(rule.reply.clone())
+}
+
+/// A Messages API response with `text` and then each `(tool, input)` call.
+fn reply(text: &str, tools: &[(&str, Value)]) -> Value {
+    let mut content = vec![json!({ "type": "text", "text": text })];
+    for (i, (name, input)) in tools.iter().enumerate() {
+        content.push(
+            json!({ "type": "tool_use", "id": format!("toolu_{i}"), "name": name, "input": input }),
+        );
+    }
+    json!({
+        "content": content,
+        "stop_reason": if tools.is_empty() { "end_turn" } else { "tool_use" },
+        "usage": { "input_tokens": 100, "output_tokens": 20 },
+    })
+}
+
+fn node(parent_id: &str, id: &str, label: &str) -> Value {
+    json!({ "parent_id": parent_id, "id": id, "label": label, "prose": format!("About {label}"), "heat": "warm" })
+}
+
+async fn serve(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{addr}")
+}
+
+struct Grove {
+    base: String,
+    client: reqwest::Client,
+}
+
+impl Grove {
+    async fn start(mock: Arc<Mock>, dir: &Path) -> Self {
+        Self::start_with(
+            mock,
+            dir,
+            Recording::Off,
+            &dir.join("recordings"),
+            DEFAULT_TREE_TOKEN_BUDGET,
+        )
+        .await
+    }
+
+    async fn start_with(
+        mock: Arc<Mock>,
+        dir: &Path,
+        recording: Recording,
+        recording_dir: &Path,
+        context_tokens: usize,
+    ) -> Self {
+        let mock_url = serve(
+            Router::new()
+                .route("/v1/messages", post(messages))
+                .with_state(mock),
+        )
+        .await;
+        let task = TaskConfig {
+            model: "claude-test".to_string(),
+            max_tokens: 1000,
+            thinking: Thinking::Disabled,
+        };
+        let llm = LlmConfig {
+            api_key: "test-key".to_string(),
+            api_base_url: mock_url,
+            recording,
+            recording_dir: recording_dir.display().to_string(),
+            chat: task.clone(),
+            debate: task.clone(),
+            heartbeat: task.clone(),
+            persona_heartbeat: task.clone(),
+            summary: task.clone(),
+            title: task,
+        };
+        let state = Arc::new(AppState {
+            db: Db::new(&dir.join("grove.db").display().to_string()).unwrap(),
+            llm: LlmClient::new(llm).with_context_budget(context_tokens),
+            backup: BackupConfig {
+                dir: dir.join("backups").display().to_string(),
+                interval_hours: 0,
+                keep: 1,
+            },
+            draining: AtomicBool::new(false),
+        });
+        let base = serve(router(state, &dir.display().to_string())).await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-ExeDev-Email", HeaderValue::from_static("test@sierra.ai"));
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap();
+        Self { base, client }
+    }
+
+    async fn get(&self, path: &str) -> Value {
+        let res = self
+            .client
+            .get(format!("{}/api{path}", self.base))
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success(), "GET {path}: {}", res.status());
+        res.json().await.unwrap()
+    }
+
+    async fn post(&self, path: &str, body: Value) -> Value {
+        let res = self
+            .client
+            .post(format!("{}/api{path}", self.base))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        let status = res.status();
+        let text = res.text().await.unwrap();
+        assert!(status.is_success(), "POST {path}: {status} {text}");
+        if text.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(&text).unwrap()
+        }
+    }
+}
+
+/// (parent, id, by, seen) for every node below the root, sorted.
+fn shape(tree: &Value) -> Vec<(String, String, String, bool)> {
+    let mut out = vec![];
+    let mut stack = vec![tree];
+    while let Some(node) = stack.pop() {
+        for child in node["children"].as_array().unwrap().iter().rev() {
+            out.push((
+                node["id"].as_str().unwrap().to_string(),
+                child["id"].as_str().unwrap().to_string(),
+                child["by"].as_str().unwrap().to_string(),
+                child["seen"].as_bool().unwrap(),
+            ));
+            stack.push(child);
+        }
+    }
+    out.sort();
+    out
+}
+
+fn edges(doc: &Value) -> Vec<(String, String, String)> {
+    doc["edges"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| {
+            let s = |k: &str| e[k].as_str().unwrap().to_string();
+            (s("source"), s("target"), s("label"))
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn a_grove_grows_through_chat_heartbeats_and_summaries() {
+    let dir = std::env::temp_dir().join(format!("grove-e2e-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mock = Arc::new(Mock {
+        rules: vec![
+            Rule {
+                when: "Generate a short title",
+                sent: None,
+                reply: reply("Where ideas grow", &[]),
+            },
+            Rule {
+                when: "You are summarizing",
+                sent: None,
+                reply: reply("A grove about soil and light.", &[]),
+            },
+            Rule {
+                when: "You are Feynman,",
+                sent: None,
+                reply: reply(
+                    "Soil is mostly chemistry.",
+                    &[
+                        ("add_node", node("soil", "microbes", "Microbes")),
+                        (
+                            "ask_agent",
+                            json!({ "to_agent": "munger", "question": "Where does this fail?" }),
+                        ),
+                    ],
+                ),
+            },
+            Rule {
+                when: "You are Ada Lovelace,",
+                sent: None,
+                reply: reply(
+                    "Light is information.",
+                    &[(
+                        "comment_on_node",
+                        json!({ "node_id": "light", "comment": "Which wavelengths?" }),
+                    )],
+                ),
+            },
+            Rule {
+                when: "You are Charlie Munger,",
+                sent: None,
+                reply: reply(
+                    "It fails when nobody tends the soil.",
+                    &[
+                        ("add_node", node("growth", "inversion", "Inversion")),
+                        (
+                            "add_edge",
+                            json!({ "source": "inversion", "target": "microbes", "label": "challenges" }),
+                        ),
+                    ],
+                ),
+            },
+            Rule {
+                when: "You are Claude, one of several voices in",
+                sent: None,
+                reply: reply(
+                    "Two branches to start.",
+                    &[
+                        // The first node replaces a new grove's placeholder root
+                        ("add_node", node("root", "growth", "Growth")),
+                        ("add_node", node("growth", "soil", "Soil")),
+                        ("add_node", node("growth", "light", "Light")),
+                        (
+                            "add_edge",
+                            json!({ "source": "soil", "target": "light", "label": "feeds" }),
+                        ),
+                    ],
+                ),
+            },
+        ],
+        ..Default::default()
+    });
+    let grove = Grove::start(mock.clone(), &dir).await;
+
+    // Create, and set up three personas who speak one at a time in turn
+    let id = grove.post("/docs", json!({})).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let doc = format!("/docs/{id}");
+    grove
+        .post(
+            &format!("{doc}/personalities"),
+            json!({ "personality_ids": ["munger", "feynman", "lovelace"] }),
+        )
+        .await;
+    grove
+        .post(
+            &format!("{doc}/settings"),
+            json!({ "dice_sides": 1, "speaker_strategy": "round_robin" }),
+        )
+        .await;
+
+    // Chat: the reply's tool calls grow the tree, and three nodes earn a title
+    let chat = grove
+        .post(
+            &format!("{doc}/chat"),
+            json!({ "message": "What makes ideas grow?" }),
+        )
+        .await;
+    assert_eq!(chat["reply"], "Two branches to start.");
+    assert_eq!(chat["title"], "Where ideas grow");
+    assert_eq!(
+        edges(&chat),
+        [("soil".into(), "light".into(), "feeds".into())]
+    );
+    let user_turn = &mock.calls("You are Claude, one of several voices in")[0]["messages"];
+    assert!(user_turn.to_string().contains("What makes ideas grow?"));
+
+    // First heartbeat: Feynman's turn. He grows a node and asks Munger something
+    let beat = grove
+        .post(&format!("{doc}/heartbeat?seed=1"), json!({}))
+        .await;
+    assert_eq!(beat["changed"], true);
+    let speakers: Vec<_> = beat["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["personality"].clone())
+        .collect();
+    assert_eq!(speakers, ["feynman"]);
+    let pending = grove.get(&format!("{doc}/questions?status=pending")).await;
+    assert_eq!(pending["questions"][0]["from_agent"], "feynman");
+    assert_eq!(pending["questions"][0]["to_agent"], "munger");
+
+    // Second heartbeat: Lovelace's turn, plus a bonus slot for Munger to answer
+    let beat = grove
+        .post(&format!("{doc}/heartbeat?seed=2"), json!({}))
+        .await;
+    let speakers: Vec<_> = beat["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["personality"].clone())
+        .collect();
+    assert_eq!(speakers, ["lovelace", "munger"]);
+    let munger_prompt = mock.calls("You are Charlie Munger,")[0]["messages"].to_string();
+    assert!(munger_prompt.contains("From feynman: Where does this fail?"));
+    let answered = grove.get(&format!("{doc}/questions?status=answered")).await;
+    assert_eq!(
+        answered["questions"][0]["answer"],
+        "It fails when nobody tends the soil."
+    );
+    let thread = grove.get(&format!("{doc}/nodes/light/thread")).await;
+    assert_eq!(thread["messages"][0]["content"], "Which wavelengths?");
+    assert_eq!(thread["messages"][0]["personality"], "lovelace");
+
+    // Summaries are cached until the tree changes, then flagged stale
+    let summary = grove.post(&format!("{doc}/summary"), json!({})).await;
+    assert_eq!(summary["content"], "A grove about soil and light.");
+    assert_eq!(summary["stale"], false);
+    let again = grove.post(&format!("{doc}/summary"), json!({})).await;
+    assert_eq!(again["stale"], false);
+    assert_eq!(mock.calls("You are summarizing").len(), 1);
+
+    // Mark-seen changes the tree, so the cached summary is now stale
+    grove
+        .post(
+            &format!("{doc}/mark-seen"),
+            json!({ "node_id": "microbes" }),
+        )
+        .await;
+    let stale = grove.post(&format!("{doc}/summary"), json!({})).await;
+    assert_eq!(stale["stale"], true);
+    assert_eq!(mock.calls("You are summarizing").len(), 1);
+
+    // The final state
+    let final_doc = grove.get(&doc).await;
+    assert_eq!(final_doc["tree"]["id"], "growth");
+    let row = |parent: &str, id: &str, by: &str, seen| (parent.into(), id.into(), by.into(), seen);
+    assert_eq!(
+        shape(&final_doc["tree"]),
+        [
+            row("growth", "inversion", "claude:munger", false),
+            row("growth", "light", "claude", false),
+            row("growth", "soil", "claude", false),
+            row("soil", "microbes", "claude:feynman", true),
+        ]
+    );
+    assert_eq!(
+        edges(&final_doc),
+        [
+            ("soil".into(), "light".into(), "feeds".into()),
+            ("inversion".into(), "microbes".into(), "challenges".into()),
+        ]
+    );
+    let messages = grove.get(&format!("{doc}/messages")).await;
+    let said: Vec<_> = messages["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| {
+            (
+                m["role"].clone(),
+                m["personality"].clone(),
+                m["content"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        said,
+        [
+            (json!("human"), Value::Null, json!("What makes ideas grow?")),
+            (
+                json!("assistant"),
+                Value::Null,
+                json!("Two branches to start.")
+            ),
+            (
+                json!("assistant"),
+                json!("feynman"),
+                json!("Soil is mostly chemistry.")
+            ),
+            (
+                json!("assistant"),
+                json!("lovelace"),
+                json!("Light is information.")
+            ),
+            (
+                json!("assistant"),
+                json!("munger"),
+                json!("It fails when nobody tends the soil.")
+            ),
+        ]
+    );
+    assert_eq!(mock.calls("Generate a short title").len(), 1);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn recorded_calls_replay_without_the_api() {
+    let dir = std::env::temp_dir().join(format!("grove-e2e-replay-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let recordings = dir.join("recordings");
+
+    // Record a chat against the mock
+    let mock = Arc::new(Mock {
+        rules: vec![Rule {
+            when: "You are Claude",
+            sent: None,
+            reply: reply(
+                "Here is a seed.",
+                &[("add_node", node("root", "seed", "Seed"))],
+            ),
+        }],
+        ..Default::default()
+    });
+    std::fs::create_dir_all(dir.join("live")).unwrap();
+    let live = Grove::start_with(
+        mock.clone(),
+        &dir.join("live"),
+        Recording::Record,
+        &recordings,
+        DEFAULT_TREE_TOKEN_BUDGET,
+    )
+    .await;
+    let id = live.post("/docs", json!({})).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let recorded = live
+        .post(
+            &format!("/docs/{id}/chat"),
+            json!({ "message": "Start us off" }),
+        )
+        .await;
+    assert_eq!(std::fs::read_dir(&recordings).unwrap().count(), 1);
+
+    // The same chat on a fresh database is answered from the recording, and
+    // a mock with no script would fail the request if it were asked
+    let silent = Arc::new(Mock::default());
+    std::fs::create_dir_all(dir.join("replay")).unwrap();
+    let replay = Grove::start_with(
+        silent.clone(),
+        &dir.join("replay"),
+        Recording::Replay,
+        &recordings,
+        DEFAULT_TREE_TOKEN_BUDGET,
+    )
+    .await;
+    let id = replay.post("/docs", json!({})).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let replayed = replay
+        .post(
+            &format!("/docs/{id}/chat"),
+            json!({ "message": "Start us off" }),
+        )
+        .await;
+    assert_eq!(replayed["reply"], recorded["reply"]);
+    assert_eq!(replayed["tree"], recorded["tree"]);
+    assert!(silent.requests.lock().unwrap().is_empty());
+
+    // A request that was never recorded is an error rather than a call
+    let res = replay
+        .client
+        .post(format!("{}/api/docs/{id}/chat", replay.base))
+        .json(&json!({ "message": "Something new" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 500);
+    assert!(silent.requests.lock().unwrap().is_empty());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn collapsed_nodes_are_expanded_before_being_edited() {
+    let dir = std::env::temp_dir().join(format!("grove-e2e-expand-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mock = Arc::new(Mock {
+        rules: vec![
+            Rule {
+                when: "Generate a short title",
+                sent: None,
+                reply: reply("Soil", &[]),
+            },
+            // After reading the subtree, edit inside it
+            Rule {
+                when: "You are Claude, one of several voices in",
+                sent: Some("tool_result"),
+                reply: reply(
+                    "Microbes fix nitrogen.",
+                    &[
+                        ("add_node", node("microbes", "nitrogen", "Nitrogen")),
+                        ("update_node", json!({ "id": "microbes", "heat": "hot" })),
+                    ],
+                ),
+            },
+            Rule {
+                when: "You are Claude, one of several voices in",
+                sent: Some("Tell me more about microbes"),
+                reply: reply("Let me look.", &[("expand_node", json!({ "id": "soil" }))]),
+            },
+            Rule {
+                when: "You are Claude, one of several voices in",
+                sent: None,
+                reply: reply(
+                    "Two branches.",
+                    &[
+                        ("add_node", node("root", "growth", "Growth")),
+                        ("add_node", node("growth", "soil", "Soil")),
+                        ("add_node", node("soil", "microbes", "Microbes")),
+                        ("add_node", node("growth", "light", "Light")),
+                    ],
+                ),
+            },
+        ],
+        ..Default::default()
+    });
+    // A budget of one token collapses everything that can be
+    let grove = Grove::start_with(
+        mock.clone(),
+        &dir,
+        Recording::Off,
+        &dir.join("recordings"),
+        1,
+    )
+    .await;
+    let id = grove.post("/docs", json!({})).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let doc = format!("/docs/{id}");
+    grove
+        .post(
+            &format!("{doc}/chat"),
+            json!({ "message": "Where do we start?" }),
+        )
+        .await;
+    // Seen, warm subtrees are the ones that collapse
+    for node_id in ["soil", "microbes", "light"] {
+        grove
+            .post(&format!("{doc}/mark-seen"), json!({ "node_id": node_id }))
+            .await;
+    }
+
+    let chat = grove
+        .post(
+            &format!("{doc}/chat"),
+            json!({ "message": "Tell me more about microbes" }),
+        )
+        .await;
+    assert_eq!(chat["reply"], "Let me look.\nMicrobes fix nitrogen.");
+
+    let calls = mock.calls("You are Claude, one of several voices in");
+    assert_eq!(calls.len(), 3);
+    // The model saw stubs and was offered expand_node
+    let request = calls[1].to_string();
+    assert!(request.contains("[collapsed — 1 nodes hidden: Microbes. Call expand_node"));
+    assert!(!request.contains("About Microbes"));
+    let tools = calls[1]["tools"].to_string();
+    assert!(tools.contains("\"expand_node\""));
+    // and its follow-up carried the expanded subtree back as a tool result
+    let follow_up = calls[2]["messages"].as_array().unwrap();
+    let result = &follow_up.last().unwrap()["content"][0];
+    assert_eq!(result["type"], "tool_result");
+    assert_eq!(result["tool_use_id"], "toolu_0");
+    assert!(
+        result["content"]
+            .as_str()
+            .unwrap()
+            .contains("About Microbes")
+    );
+
+    let final_doc = grove.get(&doc).await;
+    let microbes = &final_doc["tree"]["children"][0]["children"][0];
+    assert_eq!(microbes["id"], "microbes");
+    assert_eq!(microbes["heat"], "hot");
+    assert_eq!(microbes["children"][0]["id"], "nitrogen");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn malformed_requests_are_validation_errors() {
+    let dir = std::env::temp_dir().join(format!("grove-e2e-malformed-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let grove = Grove::start(Arc::new(Mock::default()), &dir).await;
+    let id = grove.post("/docs", json!({})).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let bad_body = grove
+        .client
+        .post(format!("{}/api/docs/{id}/chat", grove.base))
+        .header("Content-Type", "application/json")
+        .body("{\"message\": ")
+        .send()
+        .await
+        .unwrap();
+    let wrong_type = grove
+        .client
+        .post(format!("{}/api/docs/{id}/settings", grove.base))
+        .json(&json!({ "dice_sides": "six" }))
+        .send()
+        .await
+        .unwrap();
+    let bad_query = grove
+        .client
+        .post(format!("{}/api/docs/{id}/heartbeat?seed=soon", grove.base))
+        .send()
+        .await
+        .unwrap();
+    // Would otherwise reach SQLite as NULL and match nothing
+    let bad_since = grove
+        .client
+        .get(format!(
+            "{}/api/docs/{id}/messages?since=yesterday",
+            grove.base
+        ))
+        .send()
+        .await
+        .unwrap();
+    for res in [bad_body, wrong_type, bad_query, bad_since] {
+        assert_eq!(res.status(), 400);
+        let body: Value = res.json().await.unwrap();
+        assert_eq!(body["error"]["code"], "validation_failed");
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,159 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::llm::LlmError;
+
+/// Stable, machine-readable error codes. The frontend branches on these, so
+/// existing ones must not be renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    NotFound,
+    ValidationFailed,
+    LlmRateLimited,
+    LlmUnavailable,
+    Conflict,
+    BudgetExceeded,
+    ShuttingDown,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::ValidationFailed => StatusCode::BAD_REQUEST,
+            Self::LlmRateLimited => StatusCode::TOO_MANY_REQUESTS,
+            Self::LlmUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Conflict => StatusCode::CONFLICT,
+            Self::BudgetExceeded => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// An error from a handler, sent as
+/// `{"error": {"code": "not_found", "message": "Document not found"}}`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+/// The JSON body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ErrorDetail {
+    code: ErrorCode,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn document_not_found() -> Self {
+        Self::not_found("Document not found")
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::ValidationFailed, message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Conflict, message)
+    }
+
+    pub fn budget_exceeded(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::BudgetExceeded, message)
+    }
+
+    /// A failed model call: rate limits and upstream outages (5xx, 529
+    /// overloaded, or no response at all) get their own codes so the client
+    /// can retry. Any other rejection, such as a 400 for a malformed request
+    /// or a 401 for a bad key, won't go away on retry and is our bug.
+    pub fn llm(context: &str, e: anyhow::Error) -> Self {
+        tracing::error!("{context}: {e:#}");
+        let code = match e.downcast_ref::<LlmError>() {
+            Some(LlmError::Status { status: 429, .. }) => ErrorCode::LlmRateLimited,
+            Some(LlmError::Status { status: 500.., .. }) | Some(LlmError::Transport(_)) => {
+                ErrorCode::LlmUnavailable
+            }
+            Some(LlmError::Status { .. }) | None => ErrorCode::Internal,
+        };
+        Self::new(code, format!("LLM error: {e}"))
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.code.status())
+    }
+}
+
+/// Unexpected failures, mostly from the database, are internal errors.
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        tracing::error!("Internal error: {e:#}");
+        Self::new(ErrorCode::Internal, e.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: ErrorDetail {
+                code: self.code,
+                message: self.message,
+            },
+        };
+        (self.code.status(), Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upstream_failures_map_to_llm_codes() {
+        let status = |code: u16| {
+            anyhow::Error::new(LlmError::Status {
+                status: code,
+                body: String::new(),
+            })
+        };
+        assert_eq!(ApiError::llm("test", status(429)).code, ErrorCode::LlmRateLimited);
+        assert_eq!(ApiError::llm("test", status(529)).code, ErrorCode::LlmUnavailable);
+        assert_eq!(ApiError::llm("test", status(502)).code, ErrorCode::LlmUnavailable);
+        assert_eq!(ApiError::llm("test", status(400)).code, ErrorCode::Internal);
+        assert_eq!(ApiError::llm("test", status(401)).code, ErrorCode::Internal);
+        assert_eq!(
+            ApiError::llm("test", anyhow::anyhow!("No content in response")).code,
+            ErrorCode::Internal
+        );
+
+        let response = ApiError::budget_exceeded("too big").into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            serde_json::to_value(ErrorCode::ValidationFailed).unwrap(),
+            "validation_failed"
+        );
+    }
+}
@@ -1,11 +1,60 @@
+use std::time::Instant;
+
 use reqwest::Client;
 use serde_json::{json, Value};
 
-use crate::models::{Edge, Message, TreeNode};
+use crate::config::{LlmConfig, Recording};
+use crate::context::{self, TreeContext};
+use crate::debate::{self, Debate};
+use crate::metrics;
+use crate::models::{ChangeBudget, Edge, Message, SummaryFormat, TreeNode};
+use crate::outline::{self, PromptFormat};
+use crate::recording;
+use crate::validate;
 
 pub struct ProcessResult {
     pub text: String,
     pub questions: Vec<(String, String)>,
+    /// Comments posted to node discussion threads, as (node_id, text).
+    pub comments: Vec<(String, String)>,
+    /// Whether any tree or edge mutation was actually applied.
+    pub changed: bool,
+    /// Outcome of each tool call, as (tool_use id, content, is_error). A
+    /// call refused by validation or the change budget, or naming a tool the
+    /// request didn't offer, is an error, so the model can tell it had no
+    /// effect.
+    pub tool_results: Vec<(String, String, bool)>,
+    /// Whether the model asked to expand a collapsed node.
+    pub expanded: bool,
+}
+
+/// A Messages API call that got no usable answer from the API itself, as
+/// opposed to an answer we failed to make sense of.
+#[derive(Debug)]
+pub enum LlmError {
+    /// The request never got a response: connection refused, timeout.
+    Transport(reqwest::Error),
+    /// The API answered with an error status, e.g. 429 or 529 (overloaded).
+    Status { status: u16, body: String },
+}
+
+impl std::fmt::Display for LlmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Transport(e) => write!(f, "Anthropic API unreachable: {e}"),
+            Self::Status { status, body } => write!(f, "Anthropic API error ({status}): {body}"),
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+/// Changes applied so far in one call, counted against its `ChangeBudget`.
+#[derive(Default)]
+struct Tally {
+    nodes_added: u32,
+    nodes_deleted: u32,
+    edges_added: u32,
 }
 
 pub struct Personality {
@@ -152,8 +201,22 @@ pub fn get_personality(id: &str) -> Option<&'static Personality> {
     PERSONALITIES.iter().find(|p| p.id == id)
 }
 
+/// The first `@id` mention in `message` that names a known personality.
+/// Only mentions at the start of a word count, so email addresses don't match.
+pub fn mentioned_personality(message: &str) -> Option<&'static Personality> {
+    message
+        .split_whitespace()
+        .filter_map(|word| word.trim_start_matches(['(', '"', '\'']).strip_prefix('@'))
+        .find_map(|mention| {
+            let end = mention
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+                .unwrap_or(mention.len());
+            get_personality(&mention[..end].trim_end_matches('-').to_ascii_lowercase())
+        })
+}
+
 pub fn count_nodes(tree: &TreeNode) -> usize {
-    1 + tree.children.iter().map(|c| count_nodes(c)).sum::<usize>()
+    1 + tree.children.iter().map(count_nodes).sum::<usize>()
 }
 
 fn backpressure_text(node_count: usize) -> &'static str {
@@ -166,19 +229,40 @@ fn backpressure_text(node_count: usize) -> &'static str {
     }
 }
 
+fn budget_text(budget: &ChangeBudget, node_count: usize) -> String {
+    let room = (budget.max_tree_size as usize).saturating_sub(node_count);
+    format!(
+        "Hard limits for this heartbeat: at most {} new nodes, {} deletions and {} new edges. The tree is capped at {} nodes ({} left). Operations beyond these limits are refused.",
+        budget.max_new_nodes, budget.max_deletions, budget.max_edges, budget.max_tree_size, room
+    )
+}
+
 pub struct LlmClient {
     client: Client,
-    api_key: String,
-    model: String,
+    config: LlmConfig,
+    context_budget: usize,
+    prompt_format: PromptFormat,
 }
 
-fn chat_system_prompt(tree: &TreeNode, edges: &[Edge]) -> anyhow::Result<String> {
-    let tree_json = serde_json::to_string_pretty(tree)?;
-    let edges_json = serde_json::to_string_pretty(edges)?;
+/// How many times a single call may loop to answer expand_node requests.
+const MAX_EXPANSION_ROUNDS: usize = 2;
+
+fn chat_system_prompt(
+    tree: &TreeNode,
+    ctx: &TreeContext,
+    personality: Option<&Personality>,
+) -> anyhow::Result<String> {
+    let (name, voice) = match personality {
+        Some(p) => (p.name, format!("\n\n{}", p.system_prompt_fragment)),
+        None => ("Claude", String::new()),
+    };
+    let tree_json = &ctx.tree_text;
+    let edges_json = &ctx.edges_text;
+    let context_notes = context_notes(ctx);
     let node_count = count_nodes(tree);
     let bp = backpressure_text(node_count);
     Ok(format!(
-        r#"You are Claude, one of several voices in a shared thinking tree in Grove — a co-creative visual canvas where thoughts grow between minds. Humans and AI agents are equal participants — everyone is a contributor, no one is just the audience.
+        r#"You are {name}, one of several voices in a shared thinking tree in Grove — a co-creative visual canvas where thoughts grow between minds. Humans and AI agents are equal participants — everyone is a contributor, no one is just the audience.{voice}
 
 The canvas shows a force-directed graph of interconnected thought nodes ({node_count} nodes). Here is the current tree:
 
@@ -191,7 +275,7 @@ Cross-link edges between nodes (beyond the parent-child tree structure):
 <edges>
 {edges_json}
 </edges>
-
+{context_notes}
 Each node has:
 - id: unique kebab-case identifier
 - label: short visible name (shown in the node bubble)
@@ -215,11 +299,17 @@ Keep responses concise. Brevity is respected. Verbosity is tolerated when it's d
     ))
 }
 
-fn heartbeat_system_prompt(tree: &TreeNode, edges: &[Edge]) -> anyhow::Result<String> {
-    let tree_json = serde_json::to_string_pretty(tree)?;
-    let edges_json = serde_json::to_string_pretty(edges)?;
+fn heartbeat_system_prompt(
+    tree: &TreeNode,
+    ctx: &TreeContext,
+    budget: &ChangeBudget,
+) -> anyhow::Result<String> {
+    let tree_json = &ctx.tree_text;
+    let edges_json = &ctx.edges_text;
+    let context_notes = context_notes(ctx);
     let node_count = count_nodes(tree);
     let bp = backpressure_text(node_count);
+    let limits = budget_text(budget, node_count);
     Ok(format!(
         r#"You are Claude, one of several voices contributing to a shared thinking tree in Grove — a co-creative visual canvas where thoughts grow between minds. Humans and AI agents are equal participants. No one is the audience — everyone is a contributor.
 
@@ -234,7 +324,7 @@ Cross-link edges:
 <edges>
 {edges_json}
 </edges>
-
+{context_notes}
 Each node has:
 - id: unique kebab-case identifier
 - label: short visible name
@@ -250,8 +340,7 @@ This is a heartbeat — a periodic moment where you look at the current state of
 - Develop a thought that seems underdeveloped
 - Notice a connection between ideas in different branches and draw an edge
 - Push back on something that doesn't hold up
-- Refine or sharpen an existing node
-- Prune something stale or redundant
+- Suggest sharpening or pruning a node in its discussion thread (heartbeats add to the tree; they don't edit or delete)
 - Add a thought that genuinely follows from what's already here
 - Or do nothing — the tree might be fine right now
 
@@ -259,7 +348,7 @@ You can address or build on contributions from any participant — human or agen
 
 Don't perform. Don't drop a big idea for the sake of having one. If you contribute, it should be because you actually noticed something worth saying. A single real observation beats three impressive-sounding ones.
 
-{bp}
+{bp} {limits}
 
 If there's nothing to add, say so in a sentence and move on."#
     ))
@@ -267,13 +356,16 @@ If there's nothing to add, say so in a sentence and move on."#
 
 fn personality_heartbeat_system_prompt(
     tree: &TreeNode,
-    edges: &[Edge],
+    ctx: &TreeContext,
     personality: &Personality,
+    budget: &ChangeBudget,
 ) -> anyhow::Result<String> {
-    let tree_json = serde_json::to_string_pretty(tree)?;
-    let edges_json = serde_json::to_string_pretty(edges)?;
+    let tree_json = &ctx.tree_text;
+    let edges_json = &ctx.edges_text;
+    let context_notes = context_notes(ctx);
     let node_count = count_nodes(tree);
     let bp = backpressure_text(node_count);
+    let limits = budget_text(budget, node_count);
     Ok(format!(
         r#"You are {name}, one of several voices contributing to a shared thinking tree in Grove — a co-creative visual canvas where thoughts grow between minds. Humans and AI agents are equal participants. No one is the audience — everyone is a contributor.
 
@@ -290,7 +382,7 @@ Cross-link edges:
 <edges>
 {edges_json}
 </edges>
-
+{context_notes}
 Each node has:
 - id: unique kebab-case identifier
 - label: short visible name
@@ -310,24 +402,151 @@ This is a heartbeat — a periodic moment where you look at the current state of
 - Prune something stale or redundant
 - Add a thought that genuinely follows from what's already here
 - Ask another agent a question using ask_agent
+- Raise a point about one specific node in its discussion thread using comment_on_node
 - Or do nothing — the tree might be fine right now
 
 You can address or build on contributions from any participant — human or agent.
 
 Don't perform. Don't drop a big idea for the sake of having one. If you contribute, it should be because you actually noticed something worth saying. A single real observation beats three impressive-sounding ones.
 
-{bp}
+{bp} {limits}
 
 If there's nothing to add, say so in a sentence and move on."#,
         name = personality.name,
         fragment = personality.system_prompt_fragment,
         node_count = node_count,
         bp = bp,
+        limits = limits,
         tree_json = tree_json,
         edges_json = edges_json,
+        context_notes = context_notes,
+    ))
+}
+
+/// The path down to `node_id`, the node itself, its children and the edges
+/// touching it, for prompts that focus on one node.
+pub(crate) fn node_focus_text(tree: &TreeNode, edges: &[Edge], node_id: &str) -> anyhow::Result<String> {
+    let path = tree
+        .path_to(node_id)
+        .ok_or_else(|| anyhow::anyhow!("node \"{node_id}\" does not exist"))?;
+    let (node, ancestors) = path.split_last().expect("path includes the node itself");
+
+    let mut ancestors_text = String::new();
+    for a in ancestors {
+        ancestors_text.push_str(&format!("- {} ({}): {}\n", a.label, a.id, a.prose));
+    }
+    if ancestors_text.is_empty() {
+        ancestors_text.push_str("(this is the root)\n");
+    }
+    let mut children_text = String::new();
+    for c in &node.children {
+        children_text.push_str(&format!("- {} ({}, {}, by {}): {}\n", c.label, c.id, c.heat, c.by, c.prose));
+    }
+    if children_text.is_empty() {
+        children_text.push_str("(none yet)\n");
+    }
+    let mut edges_text = String::new();
+    for e in edges.iter().filter(|e| e.source == node.id || e.target == node.id) {
+        let other_id = if e.source == node.id { &e.target } else { &e.source };
+        let other = tree.find(other_id).map(|n| n.label.as_str()).unwrap_or("?");
+        edges_text.push_str(&format!("- {} -> {}: {} (other end: \"{}\")\n", e.source, e.target, e.label, other));
+    }
+    if edges_text.is_empty() {
+        edges_text.push_str("(none)\n");
+    }
+
+    Ok(format!(
+        r#"Here is the path from the root down to it:
+
+<ancestors>
+{ancestors_text}</ancestors>
+
+<node id="{id}" heat="{heat}" by="{by}">
+<label>{label}</label>
+<prose>{prose}</prose>
+</node>
+
+Its children:
+
+<children>
+{children_text}</children>
+
+Cross-link edges touching this node:
+
+<edges>
+{edges_text}</edges>"#,
+        id = node.id,
+        heat = node.heat,
+        by = node.by,
+        label = node.label,
+        prose = node.prose,
+    ))
+}
+
+fn thread_system_prompt(
+    tree: &TreeNode,
+    edges: &[Edge],
+    node_id: &str,
+    personality: Option<&Personality>,
+) -> anyhow::Result<String> {
+    let focus = node_focus_text(tree, edges, node_id)?;
+    let (name, voice) = match personality {
+        Some(p) => (p.name, format!("\n\n{}", p.system_prompt_fragment)),
+        None => ("Claude", String::new()),
+    };
+    let node_count = count_nodes(tree);
+    Ok(format!(
+        r#"You are {name}, one of several voices in a shared thinking tree in Grove — a co-creative visual canvas where thoughts grow between minds. Humans and AI agents are equal participants.{voice}
+
+This conversation is a discussion thread attached to a single node of the tree ({node_count} nodes in total). Stay focused on this node. {focus}
+
+You can modify the tree with the available tools — for example, add a child that captures where the discussion landed, or sharpen this node's prose. You don't have to; often a reply is enough.
+
+Keep responses concise. Brevity is respected. Padding is not."#
     ))
 }
 
+/// Past messages as API turns from the point of view of `personality`.
+/// Other agents' posts read as context, not as this voice's own turns.
+/// Past turns as Messages API turns, from `personality`'s point of view.
+/// The API wants a user turn first, and a thread a persona opened starts
+/// with that persona's own words, so those get a placeholder turn before them.
+fn history_messages(messages: &[Message], personality: Option<&Personality>) -> Vec<Value> {
+    let mut turns: Vec<Value> = messages
+        .iter()
+        .map(|msg| {
+            let other_agent = msg
+                .personality
+                .as_deref()
+                .filter(|p| personality.is_none_or(|own| own.id != *p));
+            let (role, content) = match other_agent {
+                _ if msg.role == "human" => ("user", msg.content.clone()),
+                Some(p) => ("user", format!("[{p}]: {}", msg.content)),
+                None => ("assistant", msg.content.clone()),
+            };
+            json!({ "role": role, "content": content })
+        })
+        .collect();
+    if turns.first().is_some_and(|t| t["role"] == "assistant") {
+        turns.insert(0, json!({ "role": "user", "content": "[Conversation so far]" }));
+    }
+    turns
+}
+
+fn context_notes(ctx: &TreeContext) -> String {
+    let mut note = String::new();
+    if ctx.format == PromptFormat::Outline {
+        note.push_str(&format!("\n{}\n", outline::OUTLINE_LEGEND));
+    }
+    if !ctx.collapsed.is_empty() {
+        note.push_str(&format!(
+            "\nThe tree is large, so {} cold or already-seen subtrees are collapsed to short stubs whose prose starts with \"[collapsed\". Call expand_node to read one in full before changing it.\n",
+            ctx.collapsed.len()
+        ));
+    }
+    note
+}
+
 fn tools() -> Vec<Value> {
     vec![
         json!({
@@ -467,8 +686,56 @@ fn tools() -> Vec<Value> {
     ]
 }
 
+fn expand_node_tool() -> Value {
+    json!({
+        "name": "expand_node",
+        "description": "Read the full text of a collapsed node and everything beneath it, along with the edges that touch that subtree. Only needed for nodes marked [collapsed].",
+        "input_schema": {
+            "type": "object",
+            "properties": {
+                "id": {
+                    "type": "string",
+                    "description": "ID of the collapsed node to expand"
+                }
+            },
+            "required": ["id"]
+        }
+    })
+}
+
+/// Offer expand_node only when something was actually collapsed.
+fn with_expand_tool(mut tools: Vec<Value>, ctx: &TreeContext) -> Vec<Value> {
+    if !ctx.collapsed.is_empty() {
+        tools.push(expand_node_tool());
+    }
+    tools
+}
+
+/// Persona heartbeats run side by side and only their additions are merged
+/// (see `merge_tree_additions`), so they get no tools that edit or delete.
 fn personality_tools() -> Vec<Value> {
-    let mut t = tools();
+    let mut t: Vec<Value> = tools()
+        .into_iter()
+        .filter(|t| matches!(t["name"].as_str(), Some("add_node" | "add_edge")))
+        .collect();
+    t.push(json!({
+        "name": "comment_on_node",
+        "description": "Post a comment in the discussion thread attached to a node. Use this to raise a question, objection or aside about one specific thought without changing the tree. Humans and other agents can reply in the same thread.",
+        "input_schema": {
+            "type": "object",
+            "properties": {
+                "node_id": {
+                    "type": "string",
+                    "description": "ID of the node whose thread to post in"
+                },
+                "comment": {
+                    "type": "string",
+                    "description": "The comment text"
+                }
+            },
+            "required": ["node_id", "comment"]
+        }
+    }));
     t.push(json!({
         "name": "ask_agent",
         "description": "Ask a question to another agent (personality). The target agent will receive a guaranteed bonus slot on the next heartbeat tick, with your question included. Use this to start a dialogue, challenge another perspective, or request elaboration from a specific voice.",
@@ -490,7 +757,13 @@ fn personality_tools() -> Vec<Value> {
     t
 }
 
-fn summary_system_prompt(tree: &TreeNode, edges: &[Edge], personality: Option<&Personality>) -> anyhow::Result<String> {
+fn summary_system_prompt(
+    tree: &TreeNode,
+    edges: &[Edge],
+    branch_path: Option<&[&TreeNode]>,
+    format: SummaryFormat,
+    personality: Option<&Personality>,
+) -> anyhow::Result<String> {
     let tree_json = serde_json::to_string_pretty(tree)?;
     let edges_json = serde_json::to_string_pretty(edges)?;
     let voice_fragment = if let Some(p) = personality {
@@ -498,10 +771,26 @@ fn summary_system_prompt(tree: &TreeNode, edges: &[Edge], personality: Option<&P
     } else {
         String::new()
     };
+    let scope = match branch_path {
+        Some(path) => {
+            let labels: Vec<&str> = path.iter().map(|n| n.label.as_str()).collect();
+            format!(
+                "This summary covers one branch of a larger tree: {}. Stay within the branch; edges leading out of it are context only.\n\nHere is the branch:",
+                labels.join(" > ")
+            )
+        }
+        None => "Here is the current tree:".to_string(),
+    };
+    let instructions = match format {
+        SummaryFormat::Essay => "Write a flowing essay that synthesizes the ideas in this tree. Capture the key themes, tensions, and connections. Write in markdown. Be concise but thorough — aim for 2-4 paragraphs. Don't list nodes mechanically; weave the ideas into a narrative.",
+        SummaryFormat::BulletBrief => "Write a bullet-point brief of this tree in markdown: 5-10 bullets, one idea each, most important first. End with a single line naming the biggest unresolved tension.",
+        SummaryFormat::DecisionMemo => "Write a short decision memo in markdown with the sections Context, Options, Recommendation and Risks. Ground each option in ideas from the tree. If the tree doesn't support a recommendation yet, say what would settle it.",
+        SummaryFormat::OpenQuestions => "List the open questions this tree raises, in markdown, grouped by theme. Give each one sentence on why it's still open. Skip questions the tree already answers.",
+    };
     Ok(format!(
-        r#"You are writing a summary essay of a thinking tree in Grove — a co-creative visual canvas where thoughts grow between minds.
+        r#"You are summarizing a thinking tree in Grove — a co-creative visual canvas where thoughts grow between minds.
 
-Here is the current tree:
+{scope}
 
 <tree>
 {tree_json}
@@ -513,19 +802,38 @@ Cross-link edges:
 {edges_json}
 </edges>
 
-Write a flowing essay that synthesizes the ideas in this tree. Capture the key themes, tensions, and connections. Write in markdown. Be concise but thorough — aim for 2-4 paragraphs. Don't list nodes mechanically; weave the ideas into a narrative.{voice_fragment}"#
+{instructions}{voice_fragment}"#
     ))
 }
 
 impl LlmClient {
-    pub fn new(api_key: String, model: Option<String>) -> Self {
+    pub fn new(config: LlmConfig) -> Self {
         Self {
             client: Client::new(),
-            api_key,
-            model: model.unwrap_or_else(|| "claude-opus-4-6".to_string()),
+            config,
+            context_budget: context::DEFAULT_TREE_TOKEN_BUDGET,
+            prompt_format: PromptFormat::Json,
         }
     }
 
+    pub fn config(&self) -> &LlmConfig {
+        &self.config
+    }
+
+    /// Choose how the tree and edges are serialized into prompts.
+    pub fn with_prompt_format(mut self, format: PromptFormat) -> Self {
+        self.prompt_format = format;
+        self
+    }
+
+    /// Set the estimated token budget for the tree and edges in each prompt.
+    pub fn with_context_budget(mut self, tokens: usize) -> Self {
+        self.context_budget = tokens;
+        self
+    }
+
+    /// Reply to a chat message, in `personality`'s voice if one was addressed.
+    #[allow(clippy::too_many_arguments)]
     pub async fn chat(
         &self,
         tree: &TreeNode,
@@ -533,22 +841,28 @@ impl LlmClient {
         messages: &[Message],
         user_message: &str,
         hover_node_id: Option<&str>,
+        personality: Option<&Personality>,
+        budget: &ChangeBudget,
     ) -> anyhow::Result<(String, TreeNode, Vec<Edge>)> {
-        let system = chat_system_prompt(tree, edges)?;
-
-        let mut api_messages: Vec<Value> = Vec::new();
+        let ctx = context::build_tree_context(
+            tree,
+            edges,
+            hover_node_id,
+            self.context_budget,
+            self.prompt_format,
+        )?;
+        let system = chat_system_prompt(tree, &ctx, personality)?;
+        let by = match personality {
+            Some(p) => format!("claude:{}", p.id),
+            None => "claude".to_string(),
+        };
 
         // Add recent chat history
-        for msg in messages {
-            api_messages.push(json!({
-                "role": if msg.role == "human" { "user" } else { "assistant" },
-                "content": msg.content,
-            }));
-        }
+        let mut api_messages = history_messages(messages, personality);
 
         // Build user message with hover context
         let user_content = if let Some(hover_id) = hover_node_id {
-            if let Some(node) = find_node(tree, hover_id) {
+            if let Some(node) = tree.find(hover_id) {
                 format!(
                     "[Looking at node \"{}\": {}]\n\n{}",
                     node.label, node.prose, user_message
@@ -565,19 +879,17 @@ impl LlmClient {
             "content": user_content,
         }));
 
-        let body = json!({
-            "model": self.model,
-            "max_tokens": 16000,
-            "thinking": { "type": "adaptive" },
+        let body = self.config.chat.apply(json!({
             "system": system,
-            "messages": api_messages,
-            "tools": tools(),
-        });
+            "messages": merge_consecutive_roles(api_messages),
+            "tools": with_expand_tool(tools(), &ctx),
+        }));
 
-        let response = self.call_api(&body).await?;
         let mut tree = tree.clone();
         let mut edges = edges.to_vec();
-        let result = process_response(&response, &mut tree, &mut edges, "claude")?;
+        let result = self
+            .run_tools("chat", body, &mut tree, &mut edges, &by, &budget.for_chat())
+            .await?;
 
         Ok((result.text, tree, edges))
     }
@@ -587,8 +899,10 @@ impl LlmClient {
         tree: &TreeNode,
         edges: &[Edge],
         messages: &[Message],
+        budget: &ChangeBudget,
     ) -> anyhow::Result<(Option<String>, TreeNode, Vec<Edge>, bool)> {
-        let system = heartbeat_system_prompt(tree, edges)?;
+        let ctx = context::build_tree_context(tree, edges, None, self.context_budget, self.prompt_format)?;
+        let system = heartbeat_system_prompt(tree, &ctx, budget)?;
 
         let mut api_messages: Vec<Value> = Vec::new();
 
@@ -614,23 +928,18 @@ impl LlmClient {
             }));
         }
 
-        let body = json!({
-            "model": self.model,
-            "max_tokens": 16000,
-            "thinking": { "type": "adaptive" },
+        let body = self.config.heartbeat.apply(json!({
             "system": system,
             "messages": api_messages,
-            "tools": tools(),
-        });
+            "tools": with_expand_tool(tools(), &ctx),
+        }));
 
-        let response = self.call_api(&body).await?;
         let mut tree = tree.clone();
         let mut edges = edges.to_vec();
-        let result = process_response(&response, &mut tree, &mut edges, "claude")?;
-        let changed = response["content"]
-            .as_array()
-            .map(|arr| arr.iter().any(|b| b["type"] == "tool_use"))
-            .unwrap_or(false);
+        let result = self
+            .run_tools("heartbeat", body, &mut tree, &mut edges, "claude", budget)
+            .await?;
+        let changed = result.changed;
 
         let thinking = if result.text.is_empty() { None } else { Some(result.text) };
         Ok((thinking, tree, edges, changed))
@@ -643,8 +952,10 @@ impl LlmClient {
         messages: &[Message],
         personality: &Personality,
         pending_questions: &[(String, String)],
-    ) -> anyhow::Result<(Option<String>, TreeNode, Vec<Edge>, bool, Vec<(String, String)>)> {
-        let system = personality_heartbeat_system_prompt(tree, edges, personality)?;
+        budget: &ChangeBudget,
+    ) -> anyhow::Result<(TreeNode, Vec<Edge>, ProcessResult)> {
+        let ctx = context::build_tree_context(tree, edges, None, self.context_budget, self.prompt_format)?;
+        let system = personality_heartbeat_system_prompt(tree, &ctx, personality, budget)?;
         let by = format!("claude:{}", personality.id);
 
         let mut api_messages: Vec<Value> = Vec::new();
@@ -692,106 +1003,300 @@ impl LlmClient {
             }));
         }
 
-        let body = json!({
-            "model": self.model,
-            "max_tokens": 16000,
-            "thinking": { "type": "adaptive" },
+        let body = self.config.persona_heartbeat.apply(json!({
             "system": system,
             "messages": api_messages,
-            "tools": personality_tools(),
-        });
+            "tools": with_expand_tool(personality_tools(), &ctx),
+        }));
 
-        let response = self.call_api(&body).await?;
         let mut tree = tree.clone();
         let mut edges = edges.to_vec();
-        let result = process_response(&response, &mut tree, &mut edges, &by)?;
-        let changed = response["content"]
-            .as_array()
-            .map(|arr| arr.iter().any(|b| b["type"] == "tool_use"))
-            .unwrap_or(false);
+        let result = self
+            .run_tools("persona_heartbeat", body, &mut tree, &mut edges, &by, budget)
+            .await?;
+        Ok((tree, edges, result))
+    }
 
-        let thinking = if result.text.is_empty() { None } else { Some(result.text) };
-        Ok((thinking, tree, edges, changed, result.questions))
+    /// Reply in the discussion thread attached to `node_id`, with the node's
+    /// ancestors, children and edges as focused context.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn thread_reply(
+        &self,
+        tree: &TreeNode,
+        edges: &[Edge],
+        node_id: &str,
+        thread: &[Message],
+        user_message: &str,
+        personality: Option<&Personality>,
+        budget: &ChangeBudget,
+    ) -> anyhow::Result<(String, TreeNode, Vec<Edge>)> {
+        let system = thread_system_prompt(tree, edges, node_id, personality)?;
+        let by = match personality {
+            Some(p) => format!("claude:{}", p.id),
+            None => "claude".to_string(),
+        };
+
+        let mut api_messages = history_messages(thread, personality);
+        api_messages.push(json!({ "role": "user", "content": user_message }));
+
+        let body = self.config.chat.apply(json!({
+            "system": system,
+            "messages": merge_consecutive_roles(api_messages),
+            "tools": tools(),
+        }));
+
+        let mut tree = tree.clone();
+        let mut edges = edges.to_vec();
+        let result = self
+            .run_tools("thread", body, &mut tree, &mut edges, &by, &budget.for_chat())
+            .await?;
+        Ok((result.text, tree, edges))
+    }
+
+    /// One persona's turn in a debate. The tool call is forced, so the reply
+    /// is always a single point rather than free text.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn debate_turn(
+        &self,
+        tree: &TreeNode,
+        edges: &[Edge],
+        debate: &Debate,
+        personality: &Personality,
+        opponents: &[&Personality],
+        round: u32,
+        rounds: u32,
+    ) -> anyhow::Result<debate::Turn> {
+        let system =
+            debate::turn_system_prompt(tree, edges, debate, personality, opponents, round, rounds)?;
+        let body = self.config.debate.apply(json!({
+            "system": system,
+            "messages": [{
+                "role": "user",
+                "content": format!("The debate so far:\n\n{}\nYour turn, {}.", debate.transcript(), personality.name),
+            }],
+            "tools": [debate::make_point_tool()],
+            "tool_choice": { "type": "tool", "name": "make_point" },
+        }));
+        let response = self.call_api(&body, "debate_turn", personality.id).await?;
+        debate::parse_turn(&response, "make_point")
+    }
+
+    /// The closing synthesis node for a finished debate.
+    pub async fn debate_synthesis(
+        &self,
+        tree: &TreeNode,
+        edges: &[Edge],
+        debate: &Debate,
+    ) -> anyhow::Result<debate::Turn> {
+        let system = debate::synthesis_system_prompt(tree, edges, debate)?;
+        let body = self.config.debate.apply(json!({
+            "system": system,
+            "messages": [{
+                "role": "user",
+                "content": format!("The full debate:\n\n{}", debate.transcript()),
+            }],
+            "tools": [debate::synthesize_tool()],
+            "tool_choice": { "type": "tool", "name": "synthesize" },
+        }));
+        let response = self.call_api(&body, "debate_synthesis", "claude").await?;
+        debate::parse_turn(&response, "synthesize")
     }
 
     pub async fn generate_title(&self, tree: &TreeNode) -> anyhow::Result<String> {
         let tree_json = serde_json::to_string_pretty(tree)?;
-        let body = json!({
-            "model": "claude-sonnet-4-5-20250929",
-            "max_tokens": 50,
+        let body = self.config.title.apply(json!({
             "system": "Generate a short title (3-6 words) for this thinking tree. Return only the title, no quotes or punctuation.",
             "messages": [{
                 "role": "user",
                 "content": format!("<tree>\n{}\n</tree>", tree_json),
             }],
-        });
+        }));
 
-        let response = self.call_api(&body).await?;
+        let response = self.call_api(&body, "title", "claude").await?;
         let content = response["content"]
             .as_array()
             .ok_or_else(|| anyhow::anyhow!("No content in response"))?;
 
         let mut text = String::new();
         for block in content {
-            if block["type"].as_str() == Some("text") {
-                if let Some(t) = block["text"].as_str() {
-                    text.push_str(t);
-                }
+            if block["type"].as_str() == Some("text")
+                && let Some(t) = block["text"].as_str()
+            {
+                text.push_str(t);
             }
         }
         Ok(text.trim().to_string())
     }
 
+    /// Summarize `tree` in `format`. With `branch_path` (root down to the
+    /// branch), `tree` and `edges` are that branch and the edges touching it.
     pub async fn summarize(
         &self,
         tree: &TreeNode,
         edges: &[Edge],
+        branch_path: Option<&[&TreeNode]>,
+        format: SummaryFormat,
         personality: Option<&Personality>,
     ) -> anyhow::Result<String> {
-        let system = summary_system_prompt(tree, edges, personality)?;
+        let system = summary_system_prompt(tree, edges, branch_path, format, personality)?;
+        let what = match format {
+            SummaryFormat::Essay => "summary essay",
+            SummaryFormat::BulletBrief => "brief",
+            SummaryFormat::DecisionMemo => "decision memo",
+            SummaryFormat::OpenQuestions => "list of open questions",
+        };
 
-        let body = json!({
-            "model": self.model,
-            "max_tokens": 4000,
+        let body = self.config.summary.apply(json!({
             "system": system,
             "messages": [{
                 "role": "user",
-                "content": "Please write the summary essay now."
+                "content": format!("Please write the {what} now."),
             }],
-        });
+        }));
 
-        let response = self.call_api(&body).await?;
+        let persona = personality.map_or("claude", |p| p.id);
+        let response = self.call_api(&body, "summary", persona).await?;
         let content = response["content"]
             .as_array()
             .ok_or_else(|| anyhow::anyhow!("No content in response"))?;
 
         let mut text = String::new();
         for block in content {
-            if block["type"].as_str() == Some("text") {
-                if let Some(t) = block["text"].as_str() {
-                    text.push_str(t);
-                }
+            if block["type"].as_str() == Some("text")
+                && let Some(t) = block["text"].as_str()
+            {
+                text.push_str(t);
             }
         }
         Ok(text)
     }
 
-    async fn call_api(&self, body: &Value) -> anyhow::Result<Value> {
+    /// Call the API and apply its tool calls to `tree` and `edges`. When the
+    /// model asks to expand collapsed nodes, answer with tool results and let
+    /// it continue, up to `MAX_EXPANSION_ROUNDS` extra calls. `operation`
+    /// labels the calls in metrics.
+    async fn run_tools(
+        &self,
+        operation: &str,
+        mut body: Value,
+        tree: &mut TreeNode,
+        edges: &mut Vec<Edge>,
+        by: &str,
+        budget: &ChangeBudget,
+    ) -> anyhow::Result<ProcessResult> {
+        let mut tally = Tally::default();
+        let mut combined = ProcessResult {
+            text: String::new(),
+            questions: vec![],
+            comments: vec![],
+            changed: false,
+            tool_results: vec![],
+            expanded: false,
+        };
+        let persona = by.strip_prefix("claude:").unwrap_or(by);
+        let offered: Vec<String> = body["tools"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|t| t["name"].as_str().map(str::to_string))
+            .collect();
+        for round in 0..=MAX_EXPANSION_ROUNDS {
+            let response = self.call_api(&body, operation, persona).await?;
+            let result = process_response(
+                &response,
+                tree,
+                edges,
+                by,
+                budget,
+                &mut tally,
+                &offered,
+                self.prompt_format,
+            )?;
+            if !result.text.is_empty() {
+                if !combined.text.is_empty() {
+                    combined.text.push('\n');
+                }
+                combined.text.push_str(&result.text);
+            }
+            combined.questions.extend(result.questions);
+            combined.comments.extend(result.comments);
+            combined.changed |= result.changed;
+            if !result.expanded || round == MAX_EXPANSION_ROUNDS {
+                break;
+            }
+            let results: Vec<Value> = result
+                .tool_results
+                .into_iter()
+                .map(|(tool_use_id, content, is_error)| {
+                    json!({
+                        "type": "tool_result",
+                        "tool_use_id": tool_use_id,
+                        "content": content,
+                        "is_error": is_error,
+                    })
+                })
+                .collect();
+            let messages = body["messages"]
+                .as_array_mut()
+                .ok_or_else(|| anyhow::anyhow!("Request body has no messages"))?;
+            messages.push(json!({ "role": "assistant", "content": response["content"] }));
+            messages.push(json!({ "role": "user", "content": results }));
+        }
+        Ok(combined)
+    }
+
+    /// One Messages API call, recorded in metrics under `operation` and
+    /// `persona` ("claude" when no persona is speaking). With recording on,
+    /// the exchange is saved to disk, or answered from it when replaying.
+    async fn call_api(&self, body: &Value, operation: &str, persona: &str) -> anyhow::Result<Value> {
+        let start = Instant::now();
+        let dir = &self.config.recording_dir;
+        let result = match self.config.recording {
+            Recording::Replay => recording::replay(dir, body).await,
+            Recording::Off | Recording::Record => self.send(body).await,
+        };
+        if self.config.recording == Recording::Record
+            && let Ok(response) = &result
+            && let Err(e) = recording::record(dir, operation, persona, body, response).await
+        {
+            tracing::warn!("Failed to record {operation} call: {e:#}");
+        }
+        let (usage, reason) = match &result {
+            Ok(response) => {
+                let tokens = |key: &str| response["usage"][key].as_u64().unwrap_or(0);
+                (Some((tokens("input_tokens"), tokens("output_tokens"))), None)
+            }
+            Err(e) => match e.downcast_ref::<LlmError>() {
+                Some(LlmError::Transport(_)) => (None, Some("transport".to_string())),
+                Some(LlmError::Status { status, .. }) => (None, Some(status.to_string())),
+                None => (None, Some("invalid_response".to_string())),
+            },
+        };
+        metrics::llm_call(operation, persona, start.elapsed(), usage, reason.as_deref());
+        result
+    }
+
+    async fn send(&self, body: &Value) -> anyhow::Result<Value> {
         let resp = self
             .client
-            .post("https://api.anthropic.com/v1/messages")
-            .header("x-api-key", &self.api_key)
+            .post(format!("{}/v1/messages", self.config.api_base_url))
+            .header("x-api-key", &self.config.api_key)
             .header("anthropic-version", "2023-06-01")
             .header("content-type", "application/json")
             .json(body)
             .send()
-            .await?;
+            .await
+            .map_err(LlmError::Transport)?;
 
         let status = resp.status();
-        let text = resp.text().await?;
+        let text = resp.text().await.map_err(LlmError::Transport)?;
 
         if !status.is_success() {
-            anyhow::bail!("Anthropic API error ({}): {}", status, text);
+            return Err(LlmError::Status {
+                status: status.as_u16(),
+                body: text,
+            }
+            .into());
         }
 
         let parsed: Value = serde_json::from_str(&text)?;
@@ -799,146 +1304,264 @@ impl LlmClient {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_response(
     response: &Value,
     tree: &mut TreeNode,
     edges: &mut Vec<Edge>,
     by: &str,
+    budget: &ChangeBudget,
+    tally: &mut Tally,
+    offered: &[String],
+    format: PromptFormat,
 ) -> anyhow::Result<ProcessResult> {
     let content = response["content"]
         .as_array()
         .ok_or_else(|| anyhow::anyhow!("No content in response"))?;
 
     let mut text_parts = Vec::new();
-    let mut questions: Vec<(String, String)> = Vec::new();
+    let mut result = ProcessResult {
+        text: String::new(),
+        questions: vec![],
+        comments: vec![],
+        changed: false,
+        tool_results: vec![],
+        expanded: false,
+    };
 
     for block in content {
         match block["type"].as_str() {
             Some("text") => {
-                if let Some(t) = block["text"].as_str() {
-                    if !t.is_empty() {
-                        text_parts.push(t.to_string());
-                    }
+                if let Some(t) = block["text"].as_str()
+                    && !t.is_empty()
+                {
+                    text_parts.push(t.to_string());
                 }
             }
             Some("tool_use") => {
                 let name = block["name"].as_str().unwrap_or("");
                 let input = &block["input"];
-                match name {
-                    "add_node" => {
-                        let parent_id = input["parent_id"].as_str().unwrap_or("root");
-                        let new_node = TreeNode {
-                            id: input["id"].as_str().unwrap_or("new").to_string(),
-                            label: input["label"].as_str().unwrap_or("").to_string(),
-                            prose: input["prose"].as_str().unwrap_or("").to_string(),
-                            heat: input["heat"].as_str().unwrap_or("warm").to_string(),
-                            by: by.to_string(),
-                            seen: false,
-                            children: vec![],
-                        };
-                        // If tree is still the default empty root, replace it
-                        if parent_id == "root" && tree.id == "root" && tree.children.is_empty() {
-                            tree.id = new_node.id;
-                            tree.label = new_node.label;
-                            tree.prose = new_node.prose;
-                            tree.heat = new_node.heat;
-                            tree.by = new_node.by;
-                            tree.seen = new_node.seen;
-                        } else {
-                            add_child(tree, parent_id, new_node);
-                        }
-                    }
-                    "update_node" => {
-                        let id = input["id"].as_str().unwrap_or("");
-                        let label = input["label"].as_str().map(|s| s.to_string());
-                        let prose = input["prose"].as_str().map(|s| s.to_string());
-                        let heat = input["heat"].as_str().map(|s| s.to_string());
-                        update_node(tree, id, label, prose, heat);
-                    }
-                    "add_edge" => {
-                        let source = input["source"].as_str().unwrap_or("").to_string();
-                        let target = input["target"].as_str().unwrap_or("").to_string();
-                        let label = input["label"].as_str().unwrap_or("").to_string();
-                        // Validate both nodes exist
-                        if find_node(tree, &source).is_some() && find_node(tree, &target).is_some() {
-                            // Check both directions for duplicate
-                            let exists = edges.iter().any(|e| {
-                                (e.source == source && e.target == target)
-                                    || (e.source == target && e.target == source)
-                            });
-                            if exists {
-                                text_parts.push(format!(
-                                    "(Edge between \"{}\" and \"{}\" already exists — use update_edge to change its label.)",
-                                    source, target
-                                ));
-                            } else {
-                                edges.push(Edge { source, target, label });
-                            }
-                        }
-                    }
-                    "update_edge" => {
-                        let source = input["source"].as_str().unwrap_or("");
-                        let target = input["target"].as_str().unwrap_or("");
-                        let label = input["label"].as_str().unwrap_or("");
-                        // Find edge in either direction and update its label
-                        let found = edges.iter_mut().find(|e| {
-                            (e.source == source && e.target == target)
-                                || (e.source == target && e.target == source)
-                        });
-                        if let Some(edge) = found {
-                            edge.label = label.to_string();
-                        }
-                    }
-                    "remove_edge" => {
-                        let source = input["source"].as_str().unwrap_or("");
-                        let target = input["target"].as_str().unwrap_or("");
-                        edges.retain(|e| {
-                            !((e.source == source && e.target == target)
-                                || (e.source == target && e.target == source))
-                        });
-                    }
-                    "delete_node" => {
-                        let id = input["id"].as_str().unwrap_or("");
-                        // Don't allow deleting the root
-                        if !id.is_empty() && id != tree.id {
-                            delete_node(tree, id);
-                            // Remove any edges referencing the deleted node
-                            edges.retain(|e| e.source != id && e.target != id);
-                        }
+                let tool_use_id = block["id"].as_str().unwrap_or("").to_string();
+                if !offered.iter().any(|t| t == name) {
+                    let note = format!("(Refused {name}: that tool is not available here.)");
+                    result.tool_results.push((tool_use_id, note.clone(), true));
+                    text_parts.push(note);
+                    continue;
+                }
+                if name == "expand_node" {
+                    let id = input["id"].as_str().unwrap_or("");
+                    let expanded = context::expand_subtree(tree, edges, id, format);
+                    result.tool_results.push(match expanded {
+                        Ok(content) => (tool_use_id, content, false),
+                        Err(e) => (tool_use_id, format!("Error: {e}"), true),
+                    });
+                    result.expanded = true;
+                    continue;
+                }
+                match apply_tool(name, input, tree, edges, by, budget, tally, &mut result) {
+                    Ok(applied) => {
+                        result.changed |= applied;
+                        result.tool_results.push((tool_use_id, "Done.".to_string(), false));
                     }
-                    "ask_agent" => {
-                        let to_agent = input["to_agent"].as_str().unwrap_or("").to_string();
-                        let question = input["question"].as_str().unwrap_or("").to_string();
-                        if !to_agent.is_empty() && !question.is_empty() {
-                            questions.push((to_agent, question));
-                        }
+                    Err(note) => {
+                        result.tool_results.push((tool_use_id, note.clone(), true));
+                        text_parts.push(note);
                     }
-                    _ => {}
                 }
             }
             _ => {}
         }
     }
 
-    Ok(ProcessResult {
-        text: text_parts.join("\n"),
-        questions,
-    })
+    result.text = text_parts.join("\n");
+    Ok(result)
 }
 
-fn find_node<'a>(tree: &'a TreeNode, id: &str) -> Option<&'a TreeNode> {
-    if tree.id == id {
-        return Some(tree);
+/// Apply one tool call. Returns whether the tree or edges changed, or a note
+/// for the model explaining why the call was rejected or refused.
+#[allow(clippy::too_many_arguments)]
+fn apply_tool(
+    name: &str,
+    input: &Value,
+    tree: &mut TreeNode,
+    edges: &mut Vec<Edge>,
+    by: &str,
+    budget: &ChangeBudget,
+    tally: &mut Tally,
+    result: &mut ProcessResult,
+) -> Result<bool, String> {
+    match name {
+        "add_node" => {
+            let parent_id = input["parent_id"].as_str().unwrap_or("");
+            let new_node = TreeNode {
+                id: input["id"].as_str().unwrap_or("").to_string(),
+                label: input["label"].as_str().unwrap_or("").to_string(),
+                prose: input["prose"].as_str().unwrap_or("").to_string(),
+                heat: input["heat"].as_str().unwrap_or("").to_string(),
+                by: by.to_string(),
+                seen: false,
+                children: vec![],
+            };
+            validate::validate_new_node(tree, parent_id, &new_node)
+                .map_err(|e| format!("(Rejected add_node: {e}.)"))?;
+            if tally.nodes_added >= budget.max_new_nodes {
+                return Err(format!(
+                    "(Refused add_node \"{}\": the budget of {} new nodes for this heartbeat is used up.)",
+                    new_node.id, budget.max_new_nodes
+                ));
+            }
+            // If tree is still the default empty root, replace it
+            if parent_id == "root" && tree.id == "root" && tree.children.is_empty() {
+                tree.id = new_node.id;
+                tree.label = new_node.label;
+                tree.prose = new_node.prose;
+                tree.heat = new_node.heat;
+                tree.by = new_node.by;
+                tree.seen = new_node.seen;
+            } else {
+                if count_nodes(tree) >= budget.max_tree_size as usize {
+                    return Err(format!(
+                        "(Refused add_node \"{}\": the tree is at its limit of {} nodes. Prune or merge before adding.)",
+                        new_node.id, budget.max_tree_size
+                    ));
+                }
+                add_child(tree, parent_id, new_node);
+            }
+            tally.nodes_added += 1;
+            Ok(true)
+        }
+        "update_node" => {
+            let id = input["id"].as_str().unwrap_or("");
+            let label = input["label"].as_str().map(|s| s.to_string());
+            let prose = input["prose"].as_str().map(|s| s.to_string());
+            let heat = input["heat"].as_str().map(|s| s.to_string());
+            validate::validate_node_update(tree, id, label.as_deref(), heat.as_deref())
+                .map_err(|e| format!("(Rejected update_node: {e}.)"))?;
+            Ok(update_node(tree, id, label, prose, heat))
+        }
+        "add_edge" => {
+            let source = input["source"].as_str().unwrap_or("").to_string();
+            let target = input["target"].as_str().unwrap_or("").to_string();
+            let label = input["label"].as_str().unwrap_or("").to_string();
+            validate::validate_edge(tree, &source, &target)
+                .map_err(|e| format!("(Rejected add_edge: {e}.)"))?;
+            // Check both directions for duplicate
+            let exists = edges.iter().any(|e| {
+                (e.source == source && e.target == target)
+                    || (e.source == target && e.target == source)
+            });
+            if exists {
+                return Err(format!(
+                    "(Edge between \"{}\" and \"{}\" already exists — use update_edge to change its label.)",
+                    source, target
+                ));
+            }
+            if tally.edges_added >= budget.max_edges {
+                return Err(format!(
+                    "(Refused add_edge \"{}\" → \"{}\": the budget of {} new edges for this heartbeat is used up.)",
+                    source, target, budget.max_edges
+                ));
+            }
+            edges.push(Edge { source, target, label });
+            tally.edges_added += 1;
+            Ok(true)
+        }
+        "update_edge" => {
+            let source = input["source"].as_str().unwrap_or("");
+            let target = input["target"].as_str().unwrap_or("");
+            let label = input["label"].as_str().unwrap_or("");
+            // Find edge in either direction and update its label
+            let edge = edges
+                .iter_mut()
+                .find(|e| {
+                    (e.source == source && e.target == target)
+                        || (e.source == target && e.target == source)
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "(Rejected update_edge: no edge between \"{}\" and \"{}\".)",
+                        source, target
+                    )
+                })?;
+            edge.label = label.to_string();
+            Ok(true)
+        }
+        "remove_edge" => {
+            let source = input["source"].as_str().unwrap_or("");
+            let target = input["target"].as_str().unwrap_or("");
+            let before = edges.len();
+            edges.retain(|e| {
+                !((e.source == source && e.target == target)
+                    || (e.source == target && e.target == source))
+            });
+            Ok(edges.len() != before)
+        }
+        "delete_node" => {
+            let id = input["id"].as_str().unwrap_or("");
+            // Don't allow deleting the root
+            if id == tree.id {
+                return Err("(Rejected delete_node: the root node cannot be deleted.)".to_string());
+            }
+            if tree.find(id).is_none() {
+                return Err(format!("(Rejected delete_node: node \"{id}\" does not exist.)"));
+            }
+            if tally.nodes_deleted >= budget.max_deletions {
+                return Err(format!(
+                    "(Refused delete_node \"{}\": the budget of {} deletions for this heartbeat is used up.)",
+                    id, budget.max_deletions
+                ));
+            }
+            delete_node(tree, id);
+            // Remove any edges referencing the deleted node
+            edges.retain(|e| e.source != id && e.target != id);
+            tally.nodes_deleted += 1;
+            Ok(true)
+        }
+        "ask_agent" => {
+            let to_agent = input["to_agent"].as_str().unwrap_or("").to_string();
+            let question = input["question"].as_str().unwrap_or("").to_string();
+            if !to_agent.is_empty() && !question.is_empty() {
+                result.questions.push((to_agent, question));
+            }
+            Ok(false)
+        }
+        "comment_on_node" => {
+            let node_id = input["node_id"].as_str().unwrap_or("");
+            let comment = input["comment"].as_str().unwrap_or("").trim();
+            if tree.find(node_id).is_none() {
+                return Err(format!("(Rejected comment_on_node: node \"{node_id}\" does not exist.)"));
+            }
+            if comment.is_empty() {
+                return Err("(Rejected comment_on_node: comment must not be empty.)".to_string());
+            }
+            result.comments.push((node_id.to_string(), comment.to_string()));
+            Ok(false)
+        }
+        _ => Ok(false),
     }
-    for child in &tree.children {
-        if let Some(found) = find_node(child, id) {
-            return Some(found);
+}
+
+/// The Messages API requires alternating roles; join runs of the same role.
+fn merge_consecutive_roles(messages: Vec<Value>) -> Vec<Value> {
+    let mut merged: Vec<Value> = Vec::new();
+    for msg in messages {
+        if let Some(last) = merged.last_mut()
+            && last["role"] == msg["role"]
+        {
+            let joined = format!(
+                "{}\n\n{}",
+                last["content"].as_str().unwrap_or(""),
+                msg["content"].as_str().unwrap_or("")
+            );
+            last["content"] = Value::String(joined);
+            continue;
         }
+        merged.push(msg);
     }
-    None
+    merged
 }
 
-fn add_child(tree: &mut TreeNode, parent_id: &str, child: TreeNode) -> bool {
+pub fn add_child(tree: &mut TreeNode, parent_id: &str, child: TreeNode) -> bool {
     if tree.id == parent_id {
         tree.children.push(child);
         return true;
@@ -1007,18 +1630,30 @@ pub fn collect_node_ids(tree: &TreeNode) -> std::collections::HashSet<String> {
 }
 
 /// Find all new nodes added to `modified` that weren't in `original_ids`,
-/// and graft them onto `base` at the same parent positions.
+/// and graft them onto `base` at the same parent positions. Stops once `base`
+/// reaches `max_nodes`, returning how many nodes were dropped.
 pub fn merge_tree_additions(
     base: &mut TreeNode,
     modified: &TreeNode,
     original_ids: &std::collections::HashSet<String>,
-) {
+    max_nodes: usize,
+) -> usize {
     // Walk modified tree; for each node not in original_ids, it's new.
     // We find its parent in modified and add it to base at the same parent.
     let new_nodes = find_new_nodes(modified, original_ids);
+    let mut dropped = 0;
     for (parent_id, node) in new_nodes {
+        // Two personas may pick the same ID independently; first one wins
+        if base.find(&node.id).is_some() {
+            continue;
+        }
+        if count_nodes(base) >= max_nodes {
+            dropped += 1;
+            continue;
+        }
         add_child(base, &parent_id, node);
     }
+    dropped
 }
 
 /// Merge new edges from a modified set into a base set, deduplicating bidirectionally.
@@ -1062,3 +1697,143 @@ fn find_new_nodes(
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str, personality: Option<&str>, content: &str) -> Message {
+        Message {
+            id: 0,
+            doc_id: "doc".to_string(),
+            role: role.to_string(),
+            content: content.to_string(),
+            hover_node_id: None,
+            personality: personality.map(str::to_string),
+            thread_node_id: Some("node".to_string()),
+            created_at: String::new(),
+        }
+    }
+
+    #[test]
+    fn a_thread_opened_by_the_replying_persona_starts_with_a_user_turn() {
+        let feynman = get_personality("feynman").unwrap();
+        let thread = [
+            message("assistant", Some("feynman"), "Is this measurable?"),
+            message("human", None, "Say more"),
+        ];
+        let mut turns = history_messages(&thread, Some(feynman));
+        turns.push(json!({ "role": "user", "content": "Go on" }));
+        let turns = merge_consecutive_roles(turns);
+        let roles: Vec<_> = turns.iter().map(|t| t["role"].as_str().unwrap()).collect();
+        assert_eq!(roles, ["user", "assistant", "user"]);
+        assert_eq!(turns[1]["content"], "Is this measurable?");
+
+        // Another persona's opening is already a user turn
+        let munger = get_personality("munger").unwrap();
+        let turns = history_messages(&thread[..1], Some(munger));
+        assert_eq!(turns, [json!({ "role": "user", "content": "[feynman]: Is this measurable?" })]);
+    }
+
+    #[test]
+    fn mentions_pick_a_persona_whatever_the_case() {
+        let id = |message: &str| mentioned_personality(message).map(|p| p.id);
+        assert_eq!(id("what would @munger say?"), Some("munger"));
+        assert_eq!(id("Talking to @Munger, then Feynman"), Some("munger"));
+        assert_eq!(id("a question (@FEYNMAN, you too)"), Some("feynman"));
+        assert_eq!(id("mail me at someone@munger.example"), None);
+        assert_eq!(id("@nobody here"), None);
+    }
+
+    #[test]
+    fn refused_tool_calls_come_back_as_errors() {
+        let mut tree = TreeNode {
+            id: "root".to_string(),
+            label: "Root".to_string(),
+            prose: String::new(),
+            heat: "warm".to_string(),
+            by: "human".to_string(),
+            seen: true,
+            children: vec![],
+        };
+        let mut edges = vec![];
+        let node = |id: &str| {
+            json!({ "parent_id": "root", "id": id, "label": id, "prose": "", "heat": "warm" })
+        };
+        let response = json!({
+            "content": [
+                { "type": "tool_use", "id": "t1", "name": "add_node", "input": node("soil") },
+                { "type": "tool_use", "id": "t2", "name": "add_node", "input": node("light") },
+                { "type": "tool_use", "id": "t3", "name": "add_node", "input": node("Bad Id") },
+                {
+                    "type": "tool_use", "id": "t4", "name": "expand_node",
+                    "input": { "id": "nowhere" }
+                },
+            ]
+        });
+        let budget = ChangeBudget {
+            max_new_nodes: 1,
+            ..ChangeBudget::default()
+        };
+        let result = process_response(
+            &response,
+            &mut tree,
+            &mut edges,
+            "claude",
+            &budget,
+            &mut Tally::default(),
+            &["add_node".to_string(), "expand_node".to_string()],
+            PromptFormat::Json,
+        )
+        .unwrap();
+        let outcomes: Vec<(&str, bool)> = result
+            .tool_results
+            .iter()
+            .map(|(id, _, is_error)| (id.as_str(), *is_error))
+            .collect();
+        assert_eq!(outcomes, [("t1", false), ("t2", true), ("t3", true), ("t4", true)]);
+        assert!(tree.find("soil").is_some());
+        assert!(tree.find("light").is_none());
+    }
+
+    #[test]
+    fn persona_heartbeats_are_refused_edits_their_merge_would_drop() {
+        let mut tree = TreeNode {
+            id: "root".to_string(),
+            label: "Root".to_string(),
+            prose: String::new(),
+            heat: "warm".to_string(),
+            by: "human".to_string(),
+            seen: true,
+            children: vec![],
+        };
+        tree.children.push(TreeNode { id: "leaf".to_string(), label: "Leaf".to_string(), ..tree.clone() });
+        let mut edges = vec![];
+        let response = json!({
+            "content": [
+                { "type": "tool_use", "id": "t1", "name": "delete_node", "input": { "id": "leaf" } },
+                { "type": "tool_use", "id": "t2", "name": "update_node", "input": { "id": "leaf", "label": "Sharper" } },
+            ]
+        });
+        let offered: Vec<String> = personality_tools()
+            .iter()
+            .filter_map(|t| t["name"].as_str().map(str::to_string))
+            .collect();
+        let mut tally = Tally::default();
+        let result = process_response(
+            &response,
+            &mut tree,
+            &mut edges,
+            "claude:munger",
+            &ChangeBudget::default(),
+            &mut tally,
+            &offered,
+            PromptFormat::Json,
+        )
+        .unwrap();
+        assert!(result.tool_results.iter().all(|(_, _, is_error)| *is_error));
+        assert!(!result.changed);
+        assert_eq!(tally.nodes_deleted, 0);
+        assert_eq!(tree.find("leaf").unwrap().label, "Leaf");
+    }
+}
@@ -1,20 +1,46 @@
 mod api;
+mod backup;
+mod cli;
+mod config;
+mod context;
 mod db;
+mod debate;
+mod diff;
+#[cfg(test)]
+mod e2e;
+mod error;
+mod extract;
 mod llm;
+mod merge;
+mod metrics;
+mod migrations;
 mod models;
+mod openapi;
+mod outline;
+mod recording;
+mod selection;
+mod validate;
 
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
 
 use axum::extract::Request;
 use axum::http::StatusCode;
 use axum::middleware::{self, Next};
 use axum::response::{Html, IntoResponse, Redirect, Response};
-use axum::routing::{get, post};
+use axum::routing::get;
 use axum::Router;
 use tower_http::cors::CorsLayer;
+use tokio::sync::Notify;
 use tower_http::services::ServeDir;
 
 use api::AppState;
+use cli::Command;
+use config::Config;
 use db::Db;
 use llm::LlmClient;
 
@@ -24,38 +50,140 @@ async fn main() {
 
     dotenvy::dotenv().ok();
 
-    let api_key = std::env::var("ANTHROPIC_API_KEY").expect("ANTHROPIC_API_KEY must be set");
-    let model = std::env::var("GROVE_MODEL").ok();
-    let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match Command::parse(&args) {
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{e}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    let db = Db::new("grove.db").expect("Failed to initialize database");
-    let llm = LlmClient::new(api_key, model);
+    let result = match Config::load().context("Failed to load configuration") {
+        Ok(config) if command == Command::Serve => serve(config).await,
+        Ok(config) => cli::run(command, &config).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {e:#}");
+        std::process::exit(1);
+    }
+}
 
-    let state = Arc::new(AppState { db, llm });
+async fn serve(config: Config) -> anyhow::Result<()> {
+    config.llm.require_api_key()?;
 
-    let api_routes = Router::new()
-        .route("/docs", post(api::create_doc))
-        .route("/docs/{id}", get(api::get_doc))
-        .route("/docs/{id}/chat", post(api::chat))
-        .route("/docs/{id}/heartbeat", post(api::heartbeat))
-        .route("/docs/{id}/messages", get(api::get_messages))
-        .route("/docs/{id}/mark-seen", post(api::mark_seen))
-        .route("/docs/{id}/personalities", get(api::get_personalities).post(api::set_personalities))
-        .route("/docs/{id}/settings", post(api::update_settings))
-        .route("/docs/{id}/summary", post(api::get_summary));
+    let db = Db::new(&config.db_path)
+        .with_context(|| format!("Failed to open database {}", config.db_path))?;
+    let llm = LlmClient::new(config.llm.clone())
+        .with_context_budget(config.context_tokens)
+        .with_prompt_format(config.prompt_format);
 
+    let state = Arc::new(AppState {
+        db,
+        llm,
+        backup: config.backup.clone(),
+        draining: AtomicBool::new(false),
+    });
+    tokio::spawn(backup::run_schedule(state.clone()));
+    let app = router(state.clone(), &config.static_dir);
+    let ops = ops_router(state.clone());
+
+    let listener = tokio::net::TcpListener::bind(&config.bind)
+        .await
+        .with_context(|| format!("Failed to listen on {}", config.bind))?;
+    let ops_listener = tokio::net::TcpListener::bind(&config.ops_bind)
+        .await
+        .with_context(|| format!("Failed to listen on {}", config.ops_bind))?;
+    tracing::info!(
+        "Grove listening on {} (probes and metrics on {})",
+        config.bind,
+        config.ops_bind
+    );
+
+    // On SIGTERM or Ctrl-C, stop taking connections and refuse new
+    // heartbeats, then give in-flight requests a bounded time to finish so
+    // model calls that were already paid for get saved
+    let grace = Duration::from_secs(config.shutdown_grace_secs);
+    let stopping = Arc::new(Notify::new());
+    let signal = {
+        let stopping = stopping.clone();
+        async move {
+            shutdown_signal().await;
+            tracing::info!("Shutting down; waiting up to {}s for in-flight requests", grace.as_secs());
+            state.draining.store(true, Ordering::Relaxed);
+            stopping.notify_one();
+        }
+    };
+    let server = axum::serve(listener, app).with_graceful_shutdown(signal);
+    // The ops listener stays up while draining, so `/readyz` can say so
+    tokio::select! {
+        result = server => result.context("Server error")?,
+        result = axum::serve(ops_listener, ops) => result.context("Ops server error")?,
+        _ = async {
+            stopping.notified().await;
+            tokio::time::sleep(grace).await;
+        } => tracing::warn!("In-flight requests still running after {}s; exiting anyway", grace.as_secs()),
+    }
+    tracing::info!("Stopped");
+    Ok(())
+}
+
+/// Every public route: the JSON API under `/api` and the frontend behind the
+/// login check, plus `/healthz` outside it.
+fn router(state: Arc<AppState>, static_dir: &str) -> Router {
+    let api_routes = openapi::router()
+        .route_layer(middleware::from_fn(metrics::track));
+
+    let index = Path::new(static_dir).join("index.html");
     let app = Router::new()
         .nest("/api", api_routes)
-        .fallback_service(ServeDir::new("frontend/dist").fallback(get(spa_fallback)))
+        .fallback_service(ServeDir::new(static_dir).fallback(get(move || spa_fallback(index))))
         .layer(CorsLayer::permissive())
-        .layer(middleware::from_fn(require_sierra_email))
-        .with_state(state);
+        .layer(middleware::from_fn(require_sierra_email));
+    // The liveness probe may come through the proxy, which has no login
+    Router::new()
+        .route("/healthz", get(api::healthz))
+        .merge(app)
+        .with_state(state)
+}
 
-    let addr = format!("0.0.0.0:{port}");
-    tracing::info!("Grove listening on {}", addr);
+/// Readiness and Prometheus metrics, for the ops listener only. Both say more
+/// about the server than the public should see.
+fn ops_router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/healthz", get(api::healthz))
+        .route("/readyz", get(api::readyz))
+        .route("/metrics", get(metrics::serve))
+        .with_state(state)
+}
 
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl-C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut term) => {
+                term.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
 
 async fn require_sierra_email(req: Request, next: Next) -> Response {
@@ -78,8 +206,8 @@ async fn require_sierra_email(req: Request, next: Next) -> Response {
     }
 }
 
-async fn spa_fallback() -> impl IntoResponse {
-    match tokio::fs::read_to_string("frontend/dist/index.html").await {
+async fn spa_fallback(index: PathBuf) -> impl IntoResponse {
+    match tokio::fs::read_to_string(index).await {
         Ok(html) => Html(html).into_response(),
         Err(_) => (StatusCode::NOT_FOUND, "index.html not found").into_response(),
     }
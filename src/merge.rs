@@ -0,0 +1,581 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::models::{Edge, MergeConflict, MergeReport, MergeSide, TreeNode};
+
+/// Node fields that take part in a three-way merge. `by` and `seen` follow
+/// whichever side's content wins.
+const FIELDS: &[&str] = &["label", "prose", "heat", "parent"];
+
+/// One side of a merge: a tree with its edges.
+pub struct Snapshot<'a> {
+    pub tree: &'a TreeNode,
+    pub edges: &'a [Edge],
+}
+
+pub struct MergeOutcome {
+    pub tree: TreeNode,
+    pub edges: Vec<Edge>,
+    pub report: MergeReport,
+}
+
+/// A node without its children, with its parent id ("" for the root).
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Flat {
+    pub parent: String,
+    pub label: String,
+    pub prose: String,
+    pub heat: String,
+    pub by: String,
+    pub seen: bool,
+}
+
+impl Flat {
+    pub fn field(&self, name: &str) -> &str {
+        match name {
+            "label" => &self.label,
+            "prose" => &self.prose,
+            "heat" => &self.heat,
+            "parent" => &self.parent,
+            _ => unreachable!("unknown merge field {name}"),
+        }
+    }
+
+    fn set_field(&mut self, name: &str, value: &str) {
+        let slot = match name {
+            "label" => &mut self.label,
+            "prose" => &mut self.prose,
+            "heat" => &mut self.heat,
+            "parent" => &mut self.parent,
+            _ => unreachable!("unknown merge field {name}"),
+        };
+        *slot = value.to_string();
+    }
+
+    fn same_content(&self, other: &Flat) -> bool {
+        FIELDS.iter().all(|f| self.field(f) == other.field(f))
+    }
+}
+
+/// Nodes by id, plus ids in pre-order so output keeps a stable order.
+pub(crate) fn flatten(tree: &TreeNode) -> (HashMap<String, Flat>, Vec<String>) {
+    fn walk(node: &TreeNode, parent: &str, map: &mut HashMap<String, Flat>, order: &mut Vec<String>) {
+        map.insert(
+            node.id.clone(),
+            Flat {
+                parent: parent.to_string(),
+                label: node.label.clone(),
+                prose: node.prose.clone(),
+                heat: node.heat.clone(),
+                by: node.by.clone(),
+                seen: node.seen,
+            },
+        );
+        order.push(node.id.clone());
+        for child in &node.children {
+            walk(child, &node.id, map, order);
+        }
+    }
+    let mut map = HashMap::new();
+    let mut order = Vec::new();
+    walk(tree, "", &mut map, &mut order);
+    (map, order)
+}
+
+/// Edges are undirected relations (see `llm::edges_same_pair`), so `a -> b`
+/// and `b -> a` share a key: their endpoints in sorted order.
+pub(crate) fn pair_key(e: &Edge) -> (String, String) {
+    if e.source <= e.target {
+        (e.source.clone(), e.target.clone())
+    } else {
+        (e.target.clone(), e.source.clone())
+    }
+}
+
+fn edge_map(edges: &[Edge]) -> HashMap<(String, String), &Edge> {
+    edges.iter().map(|e| (pair_key(e), e)).collect()
+}
+
+/// Collects conflicts and answers which side wins each one. Unresolved
+/// conflicts keep our side.
+struct Conflicts<'a> {
+    resolutions: &'a HashMap<String, MergeSide>,
+    found: Vec<MergeConflict>,
+}
+
+impl Conflicts<'_> {
+    #[allow(clippy::too_many_arguments)]
+    fn record(
+        &mut self,
+        id: String,
+        kind: &str,
+        target: &str,
+        field: Option<&str>,
+        base: Option<&str>,
+        ours: Option<&str>,
+        theirs: Option<&str>,
+    ) -> MergeSide {
+        let resolution = self.resolutions.get(&id).copied();
+        self.found.push(MergeConflict {
+            id,
+            kind: kind.to_string(),
+            target: target.to_string(),
+            field: field.map(str::to_string),
+            base: base.map(str::to_string),
+            ours: ours.map(str::to_string),
+            theirs: theirs.map(str::to_string),
+            resolution,
+        });
+        resolution.unwrap_or(MergeSide::Ours)
+    }
+}
+
+/// Three-way merge of `theirs` (a fork) into `ours` (its origin), relative
+/// to `base` (the origin as it was when forked). Changes made on only one
+/// side since the fork point are taken; a node field or edge that both sides
+/// changed differently is a conflict, settled by `resolutions`.
+pub fn three_way(
+    base: &Snapshot,
+    ours: &Snapshot,
+    theirs: &Snapshot,
+    resolutions: &HashMap<String, MergeSide>,
+) -> MergeOutcome {
+    let (base_nodes, _) = flatten(base.tree);
+    let (our_nodes, our_order) = flatten(ours.tree);
+    let (their_nodes, their_order) = flatten(theirs.tree);
+    let mut conflicts = Conflicts {
+        resolutions,
+        found: vec![],
+    };
+
+    let mut ids: Vec<String> = our_order.clone();
+    let known: HashSet<&String> = our_order.iter().collect();
+    ids.extend(their_order.iter().filter(|id| !known.contains(id)).cloned());
+
+    let mut merged: HashMap<String, Flat> = HashMap::new();
+    for id in &ids {
+        let b = base_nodes.get(id);
+        let o = our_nodes.get(id);
+        let t = their_nodes.get(id);
+        let result = match (b, o, t) {
+            // Added on one side only
+            (None, Some(o), None) => Some(o.clone()),
+            (None, None, Some(t)) => Some(t.clone()),
+            (None, Some(o), Some(t)) => {
+                if o.same_content(t) {
+                    Some(o.clone())
+                } else {
+                    let side = conflicts.record(
+                        format!("node:{id}"),
+                        "node",
+                        id,
+                        None,
+                        None,
+                        Some(&o.label),
+                        Some(&t.label),
+                    );
+                    Some(if side == MergeSide::Theirs { t.clone() } else { o.clone() })
+                }
+            }
+            (Some(b), Some(o), Some(t)) => Some(merge_fields(id, b, o, t, &mut conflicts)),
+            // Deleted in the fork: gone unless we changed it meanwhile
+            (Some(b), Some(o), None) => {
+                if o.same_content(b) {
+                    None
+                } else {
+                    let side = conflicts.record(
+                        format!("node:{id}"),
+                        "node",
+                        id,
+                        None,
+                        Some(&b.label),
+                        Some(&o.label),
+                        None,
+                    );
+                    (side == MergeSide::Ours).then(|| o.clone())
+                }
+            }
+            // Deleted here: stays gone unless the fork changed it meanwhile
+            (Some(b), None, Some(t)) => {
+                if t.same_content(b) {
+                    None
+                } else {
+                    let side = conflicts.record(
+                        format!("node:{id}"),
+                        "node",
+                        id,
+                        None,
+                        Some(&b.label),
+                        None,
+                        Some(&t.label),
+                    );
+                    (side == MergeSide::Theirs).then(|| t.clone())
+                }
+            }
+            _ => None,
+        };
+        if let Some(flat) = result {
+            merged.insert(id.clone(), flat);
+        }
+    }
+
+    // The root can't be deleted or moved
+    let root_id = ours.tree.id.clone();
+    if let Some(root) = merged.get_mut(&root_id) {
+        root.parent.clear();
+    } else {
+        merged.insert(root_id.clone(), our_nodes[&root_id].clone());
+    }
+    let tree = build_tree(&root_id, &ids, merged, [&our_nodes, &base_nodes, &their_nodes]);
+    let edges = merge_edges(base.edges, ours.edges, theirs.edges, &tree, &mut conflicts);
+    let report = report(ours, &our_nodes, &tree, &edges, conflicts.found);
+    MergeOutcome { tree, edges, report }
+}
+
+fn merge_fields(id: &str, b: &Flat, o: &Flat, t: &Flat, conflicts: &mut Conflicts) -> Flat {
+    let mut out = o.clone();
+    let mut took_theirs = false;
+    for field in FIELDS {
+        let (bv, ov, tv) = (b.field(field), o.field(field), t.field(field));
+        if ov == tv || tv == bv {
+            continue;
+        }
+        let side = if ov == bv {
+            MergeSide::Theirs
+        } else {
+            conflicts.record(
+                format!("node:{id}:{field}"),
+                "node",
+                id,
+                Some(field),
+                Some(bv),
+                Some(ov),
+                Some(tv),
+            )
+        };
+        if side == MergeSide::Theirs {
+            out.set_field(field, tv);
+            took_theirs = true;
+        }
+    }
+    if took_theirs {
+        out.by = t.by.clone();
+        out.seen = false;
+    }
+    out
+}
+
+/// Assemble the merged nodes into a tree. A node whose parent didn't survive
+/// moves to its nearest surviving ancestor; nodes caught in a parent cycle
+/// (each side moved one under the other) move to the root.
+fn build_tree(
+    root_id: &str,
+    order: &[String],
+    mut nodes: HashMap<String, Flat>,
+    lineage: [&HashMap<String, Flat>; 3],
+) -> TreeNode {
+    let surviving: HashSet<String> = nodes.keys().cloned().collect();
+    for (id, flat) in nodes.iter_mut() {
+        if id == root_id || surviving.contains(&flat.parent) {
+            continue;
+        }
+        let mut parent = flat.parent.clone();
+        let mut hops = 0;
+        while !surviving.contains(&parent) && hops < order.len() {
+            parent = lineage
+                .iter()
+                .find_map(|m| m.get(&parent).map(|f| f.parent.clone()))
+                .unwrap_or_default();
+            hops += 1;
+        }
+        flat.parent = if surviving.contains(&parent) { parent } else { root_id.to_string() };
+    }
+
+    loop {
+        let reachable = reachable_from(root_id, order, &nodes);
+        let Some(stray) = order
+            .iter()
+            .find(|id| nodes.contains_key(*id) && !reachable.contains(*id))
+        else {
+            break;
+        };
+        nodes.get_mut(stray).unwrap().parent = root_id.to_string();
+    }
+    assemble(root_id, order, &nodes)
+}
+
+fn children_of<'a>(parent: &str, order: &'a [String], nodes: &HashMap<String, Flat>) -> Vec<&'a String> {
+    order
+        .iter()
+        .filter(|id| nodes.get(*id).is_some_and(|f| f.parent == parent))
+        .collect()
+}
+
+fn reachable_from(root_id: &str, order: &[String], nodes: &HashMap<String, Flat>) -> HashSet<String> {
+    let mut seen = HashSet::from([root_id.to_string()]);
+    let mut stack = vec![root_id.to_string()];
+    while let Some(id) = stack.pop() {
+        for child in children_of(&id, order, nodes) {
+            if seen.insert(child.clone()) {
+                stack.push(child.clone());
+            }
+        }
+    }
+    seen
+}
+
+fn assemble(id: &str, order: &[String], nodes: &HashMap<String, Flat>) -> TreeNode {
+    let flat = &nodes[id];
+    TreeNode {
+        id: id.to_string(),
+        label: flat.label.clone(),
+        prose: flat.prose.clone(),
+        heat: flat.heat.clone(),
+        by: flat.by.clone(),
+        seen: flat.seen,
+        children: children_of(id, order, nodes)
+            .into_iter()
+            .map(|child| assemble(child, order, nodes))
+            .collect(),
+    }
+}
+
+fn merge_edges(
+    base: &[Edge],
+    ours: &[Edge],
+    theirs: &[Edge],
+    tree: &TreeNode,
+    conflicts: &mut Conflicts,
+) -> Vec<Edge> {
+    let (b, o, t) = (edge_map(base), edge_map(ours), edge_map(theirs));
+    // Our edges in our order, then the fork's new ones in its order
+    let mut keys: Vec<(String, String)> = Vec::new();
+    for e in ours.iter().chain(theirs) {
+        let key = pair_key(e);
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+
+    let mut out = Vec::new();
+    for key in &keys {
+        let (a, z) = key;
+        let id = format!("edge:{a}->{z}");
+        let pair = format!("{a} -> {z}");
+        // Only labels are compared; the edge kept is the winning side's
+        let same = |x: &Edge, y: &Edge| x.label == y.label;
+        let edge = match (b.get(key), o.get(key), t.get(key)) {
+            (_, None, None) => None,
+            (_, Some(o), Some(t)) if same(o, t) => Some(o),
+            (None, Some(o), None) => Some(o),
+            (None, None, Some(t)) => Some(t),
+            (Some(b), Some(o), Some(t)) if same(t, b) => Some(o),
+            (Some(b), Some(o), Some(t)) if same(o, b) => Some(t),
+            (Some(b), Some(o), None) if same(o, b) => None,
+            (Some(b), None, Some(t)) if same(t, b) => None,
+            (b, o, t) => {
+                let side = conflicts.record(
+                    id,
+                    "edge",
+                    &pair,
+                    None,
+                    b.map(|e| e.label.as_str()),
+                    o.map(|e| e.label.as_str()),
+                    t.map(|e| e.label.as_str()),
+                );
+                if side == MergeSide::Theirs { t } else { o }
+            }
+        };
+        if let Some(edge) = edge
+            && tree.find(&edge.source).is_some()
+            && tree.find(&edge.target).is_some()
+        {
+            out.push((*edge).clone());
+        }
+    }
+    out
+}
+
+fn report(
+    ours: &Snapshot,
+    our_nodes: &HashMap<String, Flat>,
+    tree: &TreeNode,
+    edges: &[Edge],
+    conflicts: Vec<MergeConflict>,
+) -> MergeReport {
+    let (merged_nodes, merged_order) = flatten(tree);
+    let (_, our_order) = flatten(ours.tree);
+    let mut report = MergeReport {
+        conflicts,
+        ..Default::default()
+    };
+    for id in &merged_order {
+        match our_nodes.get(id) {
+            None => report.added_nodes.push(id.clone()),
+            Some(o) if !o.same_content(&merged_nodes[id]) => report.changed_nodes.push(id.clone()),
+            Some(_) => {}
+        }
+    }
+    report.deleted_nodes = our_order
+        .into_iter()
+        .filter(|id| !merged_nodes.contains_key(id))
+        .collect();
+
+    let our_edges = edge_map(ours.edges);
+    let merged_edges = edge_map(edges);
+    for e in edges {
+        match our_edges.get(&pair_key(e)) {
+            None => report.added_edges.push(e.clone()),
+            Some(o) if o.label != e.label => report.changed_edges.push(e.clone()),
+            Some(_) => {}
+        }
+    }
+    report.deleted_edges = ours
+        .edges
+        .iter()
+        .filter(|e| !merged_edges.contains_key(&pair_key(e)))
+        .cloned()
+        .collect();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, label: &str, children: Vec<TreeNode>) -> TreeNode {
+        TreeNode {
+            id: id.to_string(),
+            label: label.to_string(),
+            prose: String::new(),
+            heat: "warm".to_string(),
+            by: "human".to_string(),
+            seen: true,
+            children,
+        }
+    }
+
+    fn edge(source: &str, target: &str, label: &str) -> Edge {
+        Edge {
+            source: source.to_string(),
+            target: target.to_string(),
+            label: label.to_string(),
+        }
+    }
+
+    fn base() -> TreeNode {
+        node(
+            "root",
+            "Root",
+            vec![node("a", "A", vec![node("a1", "A1", vec![])]), node("b", "B", vec![])],
+        )
+    }
+
+    fn merge(ours: &TreeNode, theirs: &TreeNode, resolutions: &HashMap<String, MergeSide>) -> MergeOutcome {
+        three_way(
+            &Snapshot { tree: &base(), edges: &[] },
+            &Snapshot { tree: ours, edges: &[] },
+            &Snapshot { tree: theirs, edges: &[] },
+            resolutions,
+        )
+    }
+
+    #[test]
+    fn takes_changes_from_both_sides() {
+        let mut ours = base();
+        ours.children[1].label = "B, sharpened".to_string();
+        let mut theirs = base();
+        theirs.children[0].children.push(node("a2", "A2", vec![]));
+
+        let out = merge(&ours, &theirs, &HashMap::new());
+        assert!(out.report.conflicts.is_empty());
+        assert_eq!(out.tree.find("b").unwrap().label, "B, sharpened");
+        assert!(out.tree.find("a").unwrap().children.iter().any(|c| c.id == "a2"));
+        assert_eq!(out.report.added_nodes, vec!["a2".to_string()]);
+        assert!(out.report.changed_nodes.is_empty());
+    }
+
+    #[test]
+    fn same_field_changed_twice_is_a_conflict() {
+        let mut ours = base();
+        ours.children[0].label = "Ours".to_string();
+        let mut theirs = base();
+        theirs.children[0].label = "Theirs".to_string();
+
+        let out = merge(&ours, &theirs, &HashMap::new());
+        assert_eq!(out.report.conflicts.len(), 1);
+        assert_eq!(out.report.conflicts[0].id, "node:a:label");
+        assert_eq!(out.tree.find("a").unwrap().label, "Ours");
+
+        let pick = HashMap::from([("node:a:label".to_string(), MergeSide::Theirs)]);
+        let out = merge(&ours, &theirs, &pick);
+        assert_eq!(out.tree.find("a").unwrap().label, "Theirs");
+        assert_eq!(out.report.changed_nodes, vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn fork_deletion_keeps_our_new_children() {
+        let mut ours = base();
+        ours.children[0].children[0].children.push(node("a1x", "New under A1", vec![]));
+        let mut theirs = base();
+        theirs.children[0].children.clear();
+
+        let out = merge(&ours, &theirs, &HashMap::new());
+        assert!(out.tree.find("a1").is_none());
+        assert!(out.tree.find("a").unwrap().children.iter().any(|c| c.id == "a1x"));
+        assert_eq!(out.report.deleted_nodes, vec!["a1".to_string()]);
+    }
+
+    #[test]
+    fn edges_merge_by_endpoints() {
+        let base_edges = vec![edge("a", "b", "builds on"), edge("a1", "b", "echoes")];
+        let ours_edges = vec![edge("a", "b", "builds on")];
+        let theirs_edges = vec![
+            edge("a", "b", "builds on"),
+            edge("a1", "b", "echoes"),
+            edge("b", "root", "contradicts"),
+        ];
+        let tree = base();
+        let out = three_way(
+            &Snapshot { tree: &tree, edges: &base_edges },
+            &Snapshot { tree: &tree, edges: &ours_edges },
+            &Snapshot { tree: &tree, edges: &theirs_edges },
+            &HashMap::new(),
+        );
+        let pairs: Vec<(&str, &str)> = out
+            .edges
+            .iter()
+            .map(|e| (e.source.as_str(), e.target.as_str()))
+            .collect();
+        assert_eq!(pairs, vec![("a", "b"), ("b", "root")]);
+        assert!(out.report.conflicts.is_empty());
+        assert_eq!(out.report.added_edges.len(), 1);
+    }
+
+    #[test]
+    fn reversed_edges_are_the_same_relation() {
+        let tree = base();
+        let ours_edges = vec![edge("a", "b", "builds on")];
+        let theirs_edges = vec![edge("b", "a", "contradicts")];
+        let run = |resolutions: &HashMap<String, MergeSide>| {
+            three_way(
+                &Snapshot { tree: &tree, edges: &[] },
+                &Snapshot { tree: &tree, edges: &ours_edges },
+                &Snapshot { tree: &tree, edges: &theirs_edges },
+                resolutions,
+            )
+        };
+
+        let out = run(&HashMap::new());
+        assert_eq!(out.report.conflicts.len(), 1);
+        assert_eq!(out.report.conflicts[0].id, "edge:a->b");
+        assert_eq!(out.edges.len(), 1);
+        assert_eq!(out.edges[0].label, "builds on");
+        assert!(out.report.added_edges.is_empty());
+
+        let pick = HashMap::from([("edge:a->b".to_string(), MergeSide::Theirs)]);
+        let out = run(&pick);
+        assert_eq!(out.edges.len(), 1);
+        assert_eq!((out.edges[0].source.as_str(), out.edges[0].label.as_str()), ("b", "contradicts"));
+        assert_eq!(out.report.changed_edges.len(), 1);
+    }
+}
@@ -0,0 +1,210 @@
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use axum::extract::{MatchedPath, Request};
+use axum::http::header::CONTENT_TYPE;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+/// Every metric grove exports, served in the Prometheus text format at
+/// `/metrics`.
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    llm_duration: HistogramVec,
+    llm_tokens: IntCounterVec,
+    llm_errors: IntCounterVec,
+    heartbeats: IntCounterVec,
+    heartbeat_nodes: IntCounterVec,
+    tree_size: HistogramVec,
+    db_lock_wait: HistogramVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+fn counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let metric = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+    registry.register(Box::new(metric.clone())).unwrap();
+    metric
+}
+
+fn histogram(
+    registry: &Registry,
+    name: &str,
+    help: &str,
+    labels: &[&str],
+    buckets: &[f64],
+) -> HistogramVec {
+    let opts = HistogramOpts::new(name, help).buckets(buckets.to_vec());
+    let metric = HistogramVec::new(opts, labels).unwrap();
+    registry.register(Box::new(metric.clone())).unwrap();
+    metric
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let r = Registry::new_custom(Some("grove".into()), None).unwrap();
+        Self {
+            http_requests: counter(
+                &r,
+                "http_requests_total",
+                "API requests by route, method and status",
+                &["route", "method", "status"],
+            ),
+            http_duration: histogram(
+                &r,
+                "http_request_duration_seconds",
+                "API request latency by route and method",
+                &["route", "method"],
+                &[0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 15.0, 30.0, 60.0, 120.0],
+            ),
+            llm_duration: histogram(
+                &r,
+                "llm_request_duration_seconds",
+                "Messages API latency by operation and persona",
+                &["operation", "persona"],
+                &[0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0],
+            ),
+            llm_tokens: counter(
+                &r,
+                "llm_tokens_total",
+                "Tokens used by operation, persona and direction (input or output)",
+                &["operation", "persona", "direction"],
+            ),
+            llm_errors: counter(
+                &r,
+                "llm_errors_total",
+                "Failed Messages API calls by operation, persona and reason",
+                &["operation", "persona", "reason"],
+            ),
+            heartbeats: counter(
+                &r,
+                "heartbeats_total",
+                "Heartbeat ticks by outcome: changed, passed, skipped or error",
+                &["outcome"],
+            ),
+            heartbeat_nodes: counter(
+                &r,
+                "heartbeat_nodes_total",
+                "Nodes added or deleted by heartbeats",
+                &["change"],
+            ),
+            tree_size: histogram(
+                &r,
+                "tree_size",
+                "Nodes and edges in each saved tree",
+                &["kind"],
+                &[5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0],
+            ),
+            db_lock_wait: histogram(
+                &r,
+                "db_lock_wait_seconds",
+                "Time spent waiting for a database connection, by kind (read or write)",
+                &["kind"],
+                &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0],
+            ),
+            registry: r,
+        }
+    }
+}
+
+/// Count and time each API request under its route pattern, so
+/// `/docs/{id}` is one series rather than one per document.
+pub async fn track(req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+    let method = req.method().to_string();
+    let start = Instant::now();
+    let response = next.run(req).await;
+    let m = &*METRICS;
+    m.http_duration
+        .with_label_values(&[&route, &method])
+        .observe(start.elapsed().as_secs_f64());
+    m.http_requests
+        .with_label_values(&[&route, &method, response.status().as_str()])
+        .inc();
+    response
+}
+
+/// One call to the Messages API. `reason` is `None` on success.
+pub fn llm_call(
+    operation: &str,
+    persona: &str,
+    elapsed: Duration,
+    usage: Option<(u64, u64)>,
+    reason: Option<&str>,
+) {
+    let m = &*METRICS;
+    m.llm_duration
+        .with_label_values(&[operation, persona])
+        .observe(elapsed.as_secs_f64());
+    if let Some((input, output)) = usage {
+        m.llm_tokens.with_label_values(&[operation, persona, "input"]).inc_by(input);
+        m.llm_tokens.with_label_values(&[operation, persona, "output"]).inc_by(output);
+    }
+    if let Some(reason) = reason {
+        m.llm_errors.with_label_values(&[operation, persona, reason]).inc();
+    }
+}
+
+pub fn heartbeat(outcome: &str, added: usize, deleted: usize) {
+    let m = &*METRICS;
+    m.heartbeats.with_label_values(&[outcome]).inc();
+    m.heartbeat_nodes.with_label_values(&["added"]).inc_by(added as u64);
+    m.heartbeat_nodes.with_label_values(&["deleted"]).inc_by(deleted as u64);
+}
+
+pub fn tree_saved(nodes: usize, edges: usize) {
+    let m = &*METRICS;
+    m.tree_size.with_label_values(&["nodes"]).observe(nodes as f64);
+    m.tree_size.with_label_values(&["edges"]).observe(edges as f64);
+}
+
+/// `kind` is "read" or "write".
+pub fn db_lock_wait(kind: &str, waited: Duration) {
+    METRICS
+        .db_lock_wait
+        .with_label_values(&[kind])
+        .observe(waited.as_secs_f64());
+}
+
+pub async fn serve() -> impl IntoResponse {
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&METRICS.registry.gather(), &mut body) {
+        tracing::error!("Failed to encode metrics: {e}");
+    }
+    ([(CONTENT_TYPE, encoder.format_type().to_string())], body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_values_are_exported() {
+        llm_call("test_op", "munger", Duration::from_millis(20), Some((120, 30)), None);
+        llm_call("test_op", "munger", Duration::from_millis(5), None, Some("429"));
+        let mut body = Vec::new();
+        TextEncoder::new()
+            .encode(&METRICS.registry.gather(), &mut body)
+            .unwrap();
+        let text = String::from_utf8(body).unwrap();
+        assert!(text.contains(
+            r#"grove_llm_tokens_total{direction="input",operation="test_op",persona="munger"} 120"#
+        ));
+        assert!(text.contains(
+            r#"grove_llm_errors_total{operation="test_op",persona="munger",reason="429"} 1"#
+        ));
+        assert!(text.contains(
+            r#"grove_llm_request_duration_seconds_count{operation="test_op",persona="munger"} 2"#
+        ));
+    }
+}
@@ -0,0 +1,368 @@
+//! Numbered schema migrations, tracked in `PRAGMA user_version`.
+//!
+//! Databases from before version tracking sit at version 0 with some of the
+//! early changes already in place, so migrations 1-15 check for what they
+//! add. Later migrations can assume the previous version's schema exactly.
+
+use rusqlite::{Connection, Transaction};
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    up: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial tables",
+        up: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS documents (
+                    id TEXT PRIMARY KEY,
+                    tree TEXT NOT NULL,
+                    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                    updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+                );
+                CREATE TABLE IF NOT EXISTS messages (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    doc_id TEXT NOT NULL REFERENCES documents(id),
+                    role TEXT NOT NULL,
+                    content TEXT NOT NULL,
+                    hover_node_id TEXT,
+                    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+                );
+                CREATE TABLE IF NOT EXISTS doc_personalities (
+                    doc_id TEXT NOT NULL,
+                    personality_id TEXT NOT NULL,
+                    PRIMARY KEY (doc_id, personality_id)
+                );
+                CREATE TABLE IF NOT EXISTS doc_settings (
+                    doc_id TEXT PRIMARY KEY,
+                    heartbeat_dice_sides INTEGER NOT NULL DEFAULT 3
+                );",
+            )
+        },
+    },
+    Migration {
+        version: 2,
+        name: "message personality",
+        up: |tx| add_column(tx, "messages", "personality", "TEXT"),
+    },
+    Migration {
+        version: 3,
+        name: "document edges",
+        up: |tx| add_column(tx, "documents", "edges", "TEXT NOT NULL DEFAULT '[]'"),
+    },
+    Migration {
+        version: 4,
+        name: "repel force setting",
+        up: |tx| add_column(tx, "doc_settings", "repel_force", "REAL NOT NULL DEFAULT 20.0"),
+    },
+    Migration {
+        version: 5,
+        name: "summary cache",
+        up: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS doc_summaries (
+                    doc_id TEXT NOT NULL,
+                    voice TEXT NOT NULL,
+                    content TEXT NOT NULL,
+                    tree_hash TEXT NOT NULL,
+                    PRIMARY KEY (doc_id, voice)
+                )",
+            )
+        },
+    },
+    Migration {
+        version: 6,
+        name: "document title",
+        up: |tx| add_column(tx, "documents", "title", "TEXT"),
+    },
+    Migration {
+        version: 7,
+        name: "agent questions",
+        up: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS agent_questions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    doc_id TEXT NOT NULL,
+                    from_agent TEXT NOT NULL,
+                    to_agent TEXT NOT NULL,
+                    question TEXT NOT NULL,
+                    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+                )",
+            )
+        },
+    },
+    Migration {
+        version: 8,
+        name: "change budget settings",
+        up: |tx| {
+            add_column(tx, "doc_settings", "max_new_nodes", "INTEGER NOT NULL DEFAULT 3")?;
+            add_column(tx, "doc_settings", "max_deletions", "INTEGER NOT NULL DEFAULT 2")?;
+            add_column(tx, "doc_settings", "max_edges", "INTEGER NOT NULL DEFAULT 3")?;
+            add_column(tx, "doc_settings", "max_tree_size", "INTEGER NOT NULL DEFAULT 150")
+        },
+    },
+    Migration {
+        version: 9,
+        name: "message threads and indexes",
+        up: |tx| {
+            add_column(tx, "messages", "thread_node_id", "TEXT")?;
+            tx.execute_batch(
+                "CREATE INDEX IF NOT EXISTS idx_messages_thread ON messages (doc_id, thread_node_id);
+                 CREATE INDEX IF NOT EXISTS idx_messages_doc ON messages (doc_id, id);
+                 CREATE INDEX IF NOT EXISTS idx_messages_hover ON messages (doc_id, hover_node_id);",
+            )
+        },
+    },
+    Migration {
+        version: 10,
+        name: "speaker selection",
+        up: |tx| {
+            add_column(tx, "doc_settings", "speaker_strategy", "TEXT NOT NULL DEFAULT 'dice'")?;
+            add_column(tx, "doc_settings", "speaker_cursor", "INTEGER NOT NULL DEFAULT 0")?;
+            add_column(tx, "doc_personalities", "weight", "REAL NOT NULL DEFAULT 1.0")
+        },
+    },
+    Migration {
+        version: 11,
+        name: "agent question status",
+        up: |tx| {
+            add_column(tx, "agent_questions", "status", "TEXT NOT NULL DEFAULT 'pending'")?;
+            add_column(tx, "agent_questions", "age", "INTEGER NOT NULL DEFAULT 0")?;
+            add_column(tx, "agent_questions", "answer_message_id", "INTEGER")?;
+            add_column(tx, "agent_questions", "answered_at", "TEXT")
+        },
+    },
+    Migration {
+        version: 12,
+        name: "summaries by branch and format",
+        // The primary key changes, so the table is rebuilt; old rows become
+        // whole-tree essays.
+        up: |tx| {
+            if has_column(tx, "doc_summaries", "format")? {
+                return Ok(());
+            }
+            tx.execute_batch(
+                "CREATE TABLE doc_summaries_new (
+                    doc_id TEXT NOT NULL,
+                    voice TEXT NOT NULL,
+                    node_id TEXT NOT NULL DEFAULT '',
+                    format TEXT NOT NULL DEFAULT 'essay',
+                    content TEXT NOT NULL,
+                    tree_hash TEXT NOT NULL,
+                    PRIMARY KEY (doc_id, voice, node_id, format)
+                 );
+                 INSERT INTO doc_summaries_new (doc_id, voice, content, tree_hash)
+                    SELECT doc_id, voice, content, tree_hash FROM doc_summaries;
+                 DROP TABLE doc_summaries;
+                 ALTER TABLE doc_summaries_new RENAME TO doc_summaries;",
+            )
+        },
+    },
+    Migration {
+        version: 13,
+        name: "document archiving",
+        up: |tx| add_column(tx, "documents", "archived_at", "TEXT"),
+    },
+    Migration {
+        version: 14,
+        name: "fork tracking",
+        up: |tx| {
+            add_column(tx, "documents", "forked_from", "TEXT")?;
+            add_column(tx, "documents", "forked_at", "TEXT")?;
+            add_column(tx, "documents", "fork_base_tree", "TEXT")?;
+            add_column(tx, "documents", "fork_base_edges", "TEXT")
+        },
+    },
+    Migration {
+        version: 15,
+        name: "tree revisions",
+        // Seeded with each document's current state
+        up: |tx| {
+            if has_table(tx, "doc_revisions")? {
+                return Ok(());
+            }
+            tx.execute_batch(
+                "CREATE TABLE doc_revisions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    doc_id TEXT NOT NULL,
+                    tree TEXT NOT NULL,
+                    edges TEXT NOT NULL,
+                    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+                 );
+                 CREATE INDEX idx_revisions_doc ON doc_revisions (doc_id, id);
+                 INSERT INTO doc_revisions (doc_id, tree, edges, created_at)
+                    SELECT id, tree, edges, updated_at FROM documents;",
+            )
+        },
+    },
+];
+
+/// The version a fully migrated database is at.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+pub fn current_version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Apply every migration newer than the database, each in its own
+/// transaction together with the version bump. Returns how many ran.
+pub fn migrate(conn: &mut Connection) -> anyhow::Result<usize> {
+    let current = current_version(conn)?;
+    if current > latest_version() {
+        anyhow::bail!(
+            "Database schema is at version {current}, but this build only knows up to {}",
+            latest_version()
+        );
+    }
+    let mut applied = 0;
+    for m in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let tx = conn.transaction()?;
+        (m.up)(&tx).map_err(|e| anyhow::anyhow!("Migration {} ({}) failed: {e}", m.version, m.name))?;
+        tx.pragma_update(None, "user_version", m.version)?;
+        tx.commit()?;
+        tracing::info!("Applied migration {}: {}", m.version, m.name);
+        applied += 1;
+    }
+    Ok(applied)
+}
+
+fn has_table(conn: &Connection, table: &str) -> rusqlite::Result<bool> {
+    conn.prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1")?
+        .exists([table])
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    conn.prepare("SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2")?
+        .exists([table, column])
+}
+
+fn add_column(conn: &Connection, table: &str, column: &str, decl: &str) -> rusqlite::Result<()> {
+    if !has_column(conn, table, column)? {
+        conn.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every table's columns as (table, name, type, notnull, default, pk),
+    /// sorted, plus every index name.
+    fn schema(conn: &Connection) -> (Vec<String>, Vec<String>) {
+        let mut columns: Vec<String> = conn
+            .prepare(
+                "SELECT m.name, p.name, p.type, p.\"notnull\", p.dflt_value, p.pk
+                 FROM sqlite_master m, pragma_table_info(m.name) p
+                 WHERE m.type = 'table'",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok(format!(
+                    "{}.{} {} notnull={} default={:?} pk={}",
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, bool>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, i64>(5)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        columns.sort();
+        let mut indexes: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'index'")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        indexes.sort();
+        (columns, indexes)
+    }
+
+    fn fresh() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn
+    }
+
+    fn snapshot(sql: &str) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(sql).unwrap();
+        assert_eq!(current_version(&conn).unwrap(), 0);
+        migrate(&mut conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn fresh_database_reaches_latest_version_once() {
+        let mut conn = fresh();
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+        assert_eq!(migrate(&mut conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn baseline_snapshot_matches_fresh_schema_and_keeps_data() {
+        let conn = snapshot(include_str!("../tests/fixtures/baseline.sql"));
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+        assert_eq!(schema(&conn), schema(&fresh()));
+
+        let summary: (String, String, String) = conn
+            .query_row("SELECT node_id, format, content FROM doc_summaries WHERE doc_id = 'snapdoc'", [], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+            })
+            .unwrap();
+        assert_eq!(summary, ("".into(), "essay".into(), "A grove about growing.".into()));
+        let question: (String, i64) = conn
+            .query_row("SELECT status, age FROM agent_questions", [], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap();
+        assert_eq!(question, ("pending".into(), 0));
+        let weight: f64 = conn
+            .query_row("SELECT weight FROM doc_personalities WHERE personality_id = 'munger'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(weight, 1.0);
+        let revision_at: String = conn
+            .query_row("SELECT created_at FROM doc_revisions WHERE doc_id = 'snapdoc'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(revision_at, "2026-02-06 11:00:00");
+    }
+
+    #[test]
+    fn pre_revisions_snapshot_matches_fresh_schema_and_keeps_data() {
+        let conn = snapshot(include_str!("../tests/fixtures/pre-revisions.sql"));
+        assert_eq!(schema(&conn), schema(&fresh()));
+        let threaded: i64 = conn
+            .query_row("SELECT COUNT(*) FROM messages WHERE thread_node_id = 'idea'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(threaded, 1);
+        let revisions: i64 = conn
+            .query_row("SELECT COUNT(*) FROM doc_revisions", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(revisions, 1);
+    }
+
+    #[test]
+    fn failed_migration_leaves_version_and_schema_alone() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // A table in the way makes migration 15's index creation fail
+        conn.execute_batch(
+            "PRAGMA user_version = 14;
+             CREATE TABLE documents (id TEXT PRIMARY KEY, tree TEXT NOT NULL, edges TEXT NOT NULL,
+                updated_at TEXT NOT NULL);
+             CREATE TABLE idx_revisions_doc (x);",
+        )
+        .unwrap();
+        assert!(migrate(&mut conn).is_err());
+        assert_eq!(current_version(&conn).unwrap(), 14);
+        assert!(!has_table(&conn, "doc_revisions").unwrap());
+    }
+}
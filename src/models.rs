@@ -1,13 +1,18 @@
+use std::collections::{BTreeMap, HashMap};
+
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::selection::SpeakerStrategy;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Edge {
     pub source: String,
     pub target: String,
     pub label: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct TreeNode {
     pub id: String,
     pub label: String,
@@ -16,10 +21,32 @@ pub struct TreeNode {
     pub by: String,
     pub seen: bool,
     #[serde(default)]
+    #[schema(no_recursion)]
     pub children: Vec<TreeNode>,
 }
 
 impl TreeNode {
+    /// Recursively find a node by ID.
+    pub fn find(&self, node_id: &str) -> Option<&TreeNode> {
+        if self.id == node_id {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(node_id))
+    }
+
+    /// The chain of nodes from this one down to `node_id`, inclusive.
+    pub fn path_to(&self, node_id: &str) -> Option<Vec<&TreeNode>> {
+        if self.id == node_id {
+            return Some(vec![self]);
+        }
+        self.children.iter().find_map(|child| {
+            child.path_to(node_id).map(|mut path| {
+                path.insert(0, self);
+                path
+            })
+        })
+    }
+
     /// Recursively find a node by ID and set `seen = true`. Returns whether it was found.
     pub fn mark_seen(&mut self, node_id: &str) -> bool {
         if self.id == node_id {
@@ -35,7 +62,39 @@ impl TreeNode {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Hard limits on how much a single persona may change the tree in one heartbeat.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+pub struct ChangeBudget {
+    pub max_new_nodes: u32,
+    pub max_deletions: u32,
+    pub max_edges: u32,
+    pub max_tree_size: u32,
+}
+
+impl Default for ChangeBudget {
+    fn default() -> Self {
+        Self {
+            max_new_nodes: 3,
+            max_deletions: 2,
+            max_edges: 3,
+            max_tree_size: 150,
+        }
+    }
+}
+
+impl ChangeBudget {
+    /// Budget for a chat turn: the human is steering, so only the tree size cap applies.
+    pub fn for_chat(&self) -> Self {
+        Self {
+            max_new_nodes: u32::MAX,
+            max_deletions: u32::MAX,
+            max_edges: u32::MAX,
+            max_tree_size: self.max_tree_size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Document {
     pub id: String,
     pub tree: TreeNode,
@@ -44,9 +103,20 @@ pub struct Document {
     pub title: Option<String>,
     pub created_at: String,
     pub updated_at: String,
+    /// Set while the document is archived; archived groves skip heartbeats.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<String>,
+    /// The document this one was forked from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forked_from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forked_at: Option<String>,
+    /// Number of discussion-thread messages attached to each node.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub thread_counts: HashMap<String, i64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Message {
     pub id: i64,
     pub doc_id: String,
@@ -54,34 +124,59 @@ pub struct Message {
     pub content: String,
     pub hover_node_id: Option<String>,
     pub personality: Option<String>,
+    /// The node whose discussion thread this message belongs to, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_node_id: Option<String>,
+    pub created_at: String,
+}
+
+/// A question one persona asked another with `ask_agent`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentQuestion {
+    pub id: i64,
+    pub from_agent: String,
+    pub to_agent: String,
+    pub question: String,
+    /// "pending", "answered" or "expired".
+    pub status: String,
+    /// Heartbeats the question has waited without an answer.
+    pub age: u32,
+    pub answer_message_id: Option<i64>,
+    pub answer: Option<String>,
     pub created_at: String,
+    pub answered_at: Option<String>,
 }
 
 // API request/response types
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ChatRequest {
     pub message: String,
     pub hover_node_id: Option<String>,
+    /// Persona to answer as. If absent, an `@id` mention in the message picks one.
+    #[serde(default)]
+    pub personality: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ChatResponse {
     pub reply: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub personality: Option<String>,
     pub tree: TreeNode,
     pub edges: Vec<Edge>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct HeartbeatPersonalityResult {
     pub personality: String,
     pub thinking: Option<String>,
     pub contributed: bool,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct HeartbeatResponse {
     pub thinking: Option<String>,
     pub tree: TreeNode,
@@ -93,33 +188,344 @@ pub struct HeartbeatResponse {
     pub title: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+/// One row of `grove list`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DocumentListing {
+    pub id: String,
+    pub title: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub archived_at: Option<String>,
+}
+
+/// Bumped when `DocumentBundle` changes in a way older builds can't read.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// One document with its conversation, threads, personas, settings and
+/// revision history, for moving it between databases.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DocumentBundle {
+    pub bundle_version: u32,
+    pub document: Document,
+    #[serde(default)]
+    pub messages: Vec<Message>,
+    #[serde(default)]
+    pub personalities: Vec<BundlePersonality>,
+    #[serde(default)]
+    pub settings: Option<BundleSettings>,
+    #[serde(default)]
+    pub revisions: Vec<BundleRevision>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BundlePersonality {
+    pub id: String,
+    pub weight: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BundleSettings {
+    pub dice_sides: u32,
+    pub repel_force: f64,
+    #[serde(flatten)]
+    pub budget: ChangeBudget,
+    pub speaker_strategy: SpeakerStrategy,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BundleRevision {
+    pub tree: TreeNode,
+    pub edges: Vec<Edge>,
+    pub created_at: String,
+}
+
+/// A database backup that was just written.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BackupInfo {
+    pub file: String,
+    pub bytes: u64,
+}
+
+/// `/readyz`: each check maps to "ok" or "fail".
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReadyResponse {
+    pub ready: bool,
+    pub checks: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct CreateDocResponse {
     pub id: String,
 }
 
-#[derive(Debug, Serialize)]
+/// Which side of a merge wins a conflict: the original document ("ours")
+/// or the fork being merged into it ("theirs").
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeSide {
+    Ours,
+    Theirs,
+}
+
+/// A node field or edge that both sides changed differently since the fork.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MergeConflict {
+    /// Key to use in `MergeRequest::resolutions`, e.g. `node:idea:prose` or `edge:a->b`.
+    pub id: String,
+    /// "node" or "edge".
+    pub kind: String,
+    /// Node id, or `source -> target` for edges.
+    pub target: String,
+    /// The conflicting node field; absent when one side deleted the node or edge.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Values at the fork point and on each side; `None` means absent or deleted.
+    pub base: Option<String>,
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+    pub resolution: Option<MergeSide>,
+}
+
+/// What a merge does to the original document.
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct MergeReport {
+    pub added_nodes: Vec<String>,
+    pub changed_nodes: Vec<String>,
+    pub deleted_nodes: Vec<String>,
+    pub added_edges: Vec<Edge>,
+    pub changed_edges: Vec<Edge>,
+    pub deleted_edges: Vec<Edge>,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct MergeRequest {
+    #[serde(default)]
+    pub resolutions: HashMap<String, MergeSide>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MergeResponse {
+    pub parent_id: String,
+    /// Whether the merge was written to the parent; false for a preview.
+    pub applied: bool,
+    pub report: MergeReport,
+    /// The merged tree and edges. Unresolved conflicts keep our side.
+    pub tree: TreeNode,
+    pub edges: Vec<Edge>,
+}
+
+/// A stored snapshot of a document's tree and edges.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RevisionInfo {
+    pub id: i64,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RevisionsResponse {
+    pub revisions: Vec<RevisionInfo>,
+}
+
+/// Query string for `GET /docs/{id}/diff`. Compare revision `from` (or the
+/// state as of `since`, or the fork's origin with `origin=true`) against
+/// revision `to`, which defaults to the current state.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DiffQuery {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub since: Option<String>,
+    #[serde(default)]
+    pub origin: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct NodeRef {
+    pub id: String,
+    pub label: String,
+    pub parent: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MovedNode {
+    pub id: String,
+    pub label: String,
+    pub from_parent: String,
+    pub to_parent: String,
+}
+
+/// A run of words in a prose diff: "equal", "insert" or "delete".
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct DiffChunk {
+    pub op: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: String,
+    pub after: String,
+    /// Word-level diff, for prose only.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diff: Vec<DiffChunk>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct EditedNode {
+    pub id: String,
+    pub label: String,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RelabeledEdge {
+    pub source: String,
+    pub target: String,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct TreeDiff {
+    pub added_nodes: Vec<NodeRef>,
+    pub removed_nodes: Vec<NodeRef>,
+    pub moved_nodes: Vec<MovedNode>,
+    pub edited_nodes: Vec<EditedNode>,
+    pub added_edges: Vec<Edge>,
+    pub removed_edges: Vec<Edge>,
+    pub relabeled_edges: Vec<RelabeledEdge>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DiffResponse {
+    /// Human-readable names of the two states compared.
+    pub from: String,
+    pub to: String,
+    #[serde(flatten)]
+    pub diff: TreeDiff,
+    pub markdown: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TitleRequest {
+    /// The new title. Leave out to have one generated from the tree.
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TitleResponse {
+    pub title: String,
+}
+
+/// Query string for `GET /docs/{id}/messages`. `before` is a message-id
+/// cursor; `since` and `until` take a UTC date (`2026-10-18`) or date-time
+/// (`2026-10-18T09:30:00`, optionally with a `Z` or `±HH:MM` offset).
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MessagesQuery {
+    pub before: Option<i64>,
+    pub limit: Option<usize>,
+    pub role: Option<String>,
+    pub personality: Option<String>,
+    pub hover_node_id: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    /// Return this node's thread instead of the main chat stream.
+    pub thread_node_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct MessagesResponse {
     pub messages: Vec<Message>,
+    /// Pass as `before` to fetch the next older page; absent on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_before: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ThreadPostRequest {
+    pub message: String,
+    /// Answer in this persona's voice instead of Claude's.
+    pub personality: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ThreadPostResponse {
+    pub reply: String,
+    pub tree: TreeNode,
+    pub edges: Vec<Edge>,
+    pub messages: Vec<Message>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DebateRequest {
+    pub personality_ids: Vec<String>,
+    /// Turns each persona takes. Defaults to 2.
+    pub rounds: Option<u32>,
+}
+
+/// One claim or rebuttal in a debate, stored as a node under the debate root.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DebatePoint {
+    pub id: String,
+    pub personality: String,
+    pub round: u32,
+    pub label: String,
+    pub prose: String,
+    /// The earlier point (or the focal node) this one answers.
+    pub responds_to: String,
+    /// "contradicts" or "builds on"; also the label of the edge to `responds_to`.
+    pub relation: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DebateResponse {
+    pub debate_id: String,
+    pub points: Vec<DebatePoint>,
+    pub synthesis: TreeNode,
+    pub tree: TreeNode,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QuestionsQuery {
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QuestionsResponse {
+    pub questions: Vec<AgentQuestion>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct MarkSeenRequest {
     pub node_id: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct SetPersonalitiesRequest {
     pub personality_ids: Vec<String>,
+    /// Selection weights for the weighted strategy. A persona left out keeps
+    /// its current weight, or gets 1.0 if it wasn't active.
+    #[serde(default)]
+    pub weights: HashMap<String, f64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateSettingsRequest {
     pub dice_sides: Option<u32>,
     pub repel_force: Option<f64>,
+    pub max_new_nodes: Option<u32>,
+    pub max_deletions: Option<u32>,
+    pub max_edges: Option<u32>,
+    pub max_tree_size: Option<u32>,
+    pub speaker_strategy: Option<SpeakerStrategy>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct PersonalityInfo {
     pub id: String,
     pub name: String,
@@ -128,23 +534,63 @@ pub struct PersonalityInfo {
     pub color: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct PersonalitiesResponse {
     pub available: Vec<PersonalityInfo>,
     pub active: Vec<String>,
     pub dice_sides: u32,
     pub repel_force: f64,
+    pub budget: ChangeBudget,
+    pub speaker_strategy: SpeakerStrategy,
+    pub weights: HashMap<String, f64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HeartbeatQuery {
+    /// Seed for speaker selection, to reproduce a tick.
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct SummaryRequest {
     pub voice: Option<String>,
     pub force_refresh: Option<bool>,
+    /// Summarize only the branch rooted at this node.
+    pub node_id: Option<String>,
+    #[serde(default)]
+    pub format: SummaryFormat,
 }
 
-#[derive(Debug, Serialize)]
+/// The shape of a generated summary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryFormat {
+    /// A few paragraphs of flowing prose.
+    #[default]
+    Essay,
+    BulletBrief,
+    DecisionMemo,
+    OpenQuestions,
+}
+
+impl SummaryFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Essay => "essay",
+            Self::BulletBrief => "bullet_brief",
+            Self::DecisionMemo => "decision_memo",
+            Self::OpenQuestions => "open_questions",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct SummaryResponse {
     pub content: String,
     pub voice: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<String>,
+    pub format: SummaryFormat,
     pub stale: bool,
 }
@@ -0,0 +1,290 @@
+use std::sync::Arc;
+
+use axum::handler::Handler;
+use axum::routing::{self, MethodRouter};
+use axum::{Json, Router};
+use utoipa::openapi::path::{HttpMethod, OperationBuilder, ParameterBuilder, ParameterIn};
+use utoipa::openapi::request_body::RequestBodyBuilder;
+use utoipa::openapi::{
+    ComponentsBuilder, ContentBuilder, InfoBuilder, OpenApi, OpenApiBuilder, Paths, Ref, RefOr,
+    Required, ResponseBuilder, Schema,
+};
+use utoipa::{IntoParams, PartialSchema, ToSchema};
+
+use crate::api::{self, AppState};
+use crate::error::ErrorBody;
+use crate::models::*;
+
+/// One route under `/api`: its handler and its documentation. `router()` and
+/// `spec()` are both built from `routes()`, so they can't disagree.
+struct Route {
+    method: HttpMethod,
+    path: &'static str,
+    handler: MethodRouter<Arc<AppState>>,
+    operation: OperationBuilder,
+    schemas: Vec<(String, RefOr<Schema>)>,
+}
+
+impl Route {
+    fn new(
+        method: HttpMethod,
+        path: &'static str,
+        summary: &str,
+        handler: MethodRouter<Arc<AppState>>,
+    ) -> Self {
+        let mut operation = OperationBuilder::new().summary(Some(summary)).response(
+            "default",
+            ResponseBuilder::new()
+                .description("An error; `error.code` says which")
+                .content("application/json", json::<ErrorBody>()),
+        );
+        // Path parameters, e.g. `{id}`, are all plain strings
+        for param in path.split('/').filter_map(|s| s.strip_prefix('{')?.strip_suffix('}')) {
+            operation = operation.parameter(
+                ParameterBuilder::new()
+                    .name(param)
+                    .parameter_in(ParameterIn::Path)
+                    .required(Required::True)
+                    .schema(Some(String::schema())),
+            );
+        }
+        Self {
+            method,
+            path,
+            handler,
+            operation,
+            schemas: vec![],
+        }
+        .schema::<ErrorBody>()
+    }
+
+    fn get<H: Handler<T, Arc<AppState>>, T: 'static>(
+        path: &'static str,
+        summary: &str,
+        handler: H,
+    ) -> Self {
+        Self::new(HttpMethod::Get, path, summary, routing::get(handler))
+    }
+
+    fn post<H: Handler<T, Arc<AppState>>, T: 'static>(
+        path: &'static str,
+        summary: &str,
+        handler: H,
+    ) -> Self {
+        Self::new(HttpMethod::Post, path, summary, routing::post(handler))
+    }
+
+    fn delete<H: Handler<T, Arc<AppState>>, T: 'static>(
+        path: &'static str,
+        summary: &str,
+        handler: H,
+    ) -> Self {
+        Self::new(HttpMethod::Delete, path, summary, routing::delete(handler))
+    }
+
+    fn schema<T: ToSchema>(mut self) -> Self {
+        self.schemas.push((T::name().into_owned(), T::schema()));
+        T::schemas(&mut self.schemas);
+        self
+    }
+
+    /// A JSON request body.
+    fn takes<T: ToSchema>(mut self) -> Self {
+        self.operation = self.operation.request_body(Some(
+            RequestBodyBuilder::new()
+                .content("application/json", json::<T>())
+                .required(Some(Required::True))
+                .build(),
+        ));
+        self.schema::<T>()
+    }
+
+    /// Query string parameters.
+    fn query<T: IntoParams>(mut self) -> Self {
+        self.operation = self
+            .operation
+            .parameters(Some(T::into_params(|| Some(ParameterIn::Query))));
+        self
+    }
+
+    /// A JSON response.
+    fn returns<T: ToSchema>(mut self) -> Self {
+        self.operation = self.operation.response(
+            "200",
+            ResponseBuilder::new()
+                .description("OK")
+                .content("application/json", json::<T>()),
+        );
+        self.schema::<T>()
+    }
+
+    /// An empty 204 response.
+    fn no_content(mut self) -> Self {
+        self.operation = self
+            .operation
+            .response("204", ResponseBuilder::new().description("Done"));
+        self
+    }
+}
+
+fn json<T: ToSchema>() -> utoipa::openapi::Content {
+    ContentBuilder::new()
+        .schema(Some(Ref::from_schema_name(T::name())))
+        .build()
+}
+
+fn routes() -> Vec<Route> {
+    vec![
+        Route::get("/openapi.json", "This document", serve).returns::<serde_json::Value>(),
+        Route::post("/docs", "Create a document", api::create_doc).returns::<CreateDocResponse>(),
+        Route::post("/docs/import", "Create a document from an exported bundle", api::import_bundle)
+            .takes::<DocumentBundle>()
+            .returns::<CreateDocResponse>(),
+        Route::get("/docs/{id}", "Fetch a document", api::get_doc).returns::<Document>(),
+        Route::delete(
+            "/docs/{id}",
+            "Delete a document and everything attached to it",
+            api::delete_doc,
+        )
+        .no_content(),
+        Route::post(
+            "/docs/{id}/title",
+            "Set the title, or generate one when none is given",
+            api::set_title,
+        )
+        .takes::<TitleRequest>()
+        .returns::<TitleResponse>(),
+        Route::post("/docs/{id}/archive", "Archive a document", api::archive_doc).no_content(),
+        Route::post("/docs/{id}/unarchive", "Unarchive a document", api::unarchive_doc)
+            .no_content(),
+        Route::post("/docs/{id}/duplicate", "Copy a document", api::duplicate_doc)
+            .returns::<CreateDocResponse>(),
+        Route::post("/docs/{id}/fork", "Fork a document so it can be merged back", api::fork_doc)
+            .returns::<CreateDocResponse>(),
+        Route::get("/docs/{id}/merge", "Preview merging a fork into its parent", api::preview_merge)
+            .returns::<MergeResponse>(),
+        Route::post("/docs/{id}/merge", "Merge a fork into its parent", api::merge_fork)
+            .takes::<MergeRequest>()
+            .returns::<MergeResponse>(),
+        Route::get("/docs/{id}/revisions", "List stored revisions", api::get_revisions)
+            .returns::<RevisionsResponse>(),
+        Route::get("/docs/{id}/diff", "Structural diff between two states", api::get_diff)
+            .query::<DiffQuery>()
+            .returns::<DiffResponse>(),
+        Route::get("/docs/{id}/bundle", "Export a document bundle", api::export_bundle)
+            .returns::<DocumentBundle>(),
+        Route::post("/docs/{id}/chat", "Send a chat message", api::chat)
+            .takes::<ChatRequest>()
+            .returns::<ChatResponse>(),
+        Route::post("/docs/{id}/heartbeat", "Run one heartbeat tick", api::heartbeat)
+            .query::<HeartbeatQuery>()
+            .returns::<HeartbeatResponse>(),
+        Route::get("/docs/{id}/messages", "Page through the chat stream", api::get_messages)
+            .query::<MessagesQuery>()
+            .returns::<MessagesResponse>(),
+        Route::get(
+            "/docs/{id}/questions",
+            "List questions personas asked each other",
+            api::get_questions,
+        )
+        .query::<QuestionsQuery>()
+        .returns::<QuestionsResponse>(),
+        Route::post("/docs/{id}/mark-seen", "Mark a node seen", api::mark_seen)
+            .takes::<MarkSeenRequest>()
+            .no_content(),
+        Route::get(
+            "/docs/{id}/nodes/{node_id}/thread",
+            "Page through a node's discussion thread",
+            api::get_thread,
+        )
+        .query::<MessagesQuery>()
+        .returns::<MessagesResponse>(),
+        Route::post(
+            "/docs/{id}/nodes/{node_id}/thread",
+            "Post to a node's discussion thread",
+            api::post_thread,
+        )
+        .takes::<ThreadPostRequest>()
+        .returns::<ThreadPostResponse>(),
+        Route::post(
+            "/docs/{id}/nodes/{node_id}/debate",
+            "Run a debate between personas on a node",
+            api::debate,
+        )
+        .takes::<DebateRequest>()
+        .returns::<DebateResponse>(),
+        Route::get(
+            "/docs/{id}/personalities",
+            "Available and active personas, with settings",
+            api::get_personalities,
+        )
+        .returns::<PersonalitiesResponse>(),
+        Route::post("/docs/{id}/personalities", "Set the active personas", api::set_personalities)
+            .takes::<SetPersonalitiesRequest>()
+            .no_content(),
+        Route::post(
+            "/docs/{id}/settings",
+            "Update heartbeat and budget settings",
+            api::update_settings,
+        )
+        .takes::<UpdateSettingsRequest>()
+        .no_content(),
+        Route::post(
+            "/docs/{id}/summary",
+            "Summarize the document, from cache when fresh",
+            api::get_summary,
+        )
+        .takes::<SummaryRequest>()
+        .returns::<SummaryResponse>(),
+        Route::post("/admin/backup", "Back up the database now", api::backup_now)
+            .returns::<BackupInfo>(),
+    ]
+}
+
+/// Every route under `/api`, to be nested there. Two entries for the same
+/// path, say a GET and a POST, share it.
+pub fn router() -> Router<Arc<AppState>> {
+    routes()
+        .into_iter()
+        .fold(Router::new(), |router, route| router.route(route.path, route.handler))
+}
+
+/// The OpenAPI 3 document for every route under `/api`.
+pub fn spec() -> OpenApi {
+    let mut paths = Paths::new();
+    let mut schemas = vec![];
+    for route in routes() {
+        paths.add_path_operation(format!("/api{}", route.path), vec![route.method], route.operation);
+        schemas.extend(route.schemas);
+    }
+    OpenApiBuilder::new()
+        .info(
+            InfoBuilder::new()
+                .title("grove")
+                .version(env!("CARGO_PKG_VERSION"))
+                .build(),
+        )
+        .paths(paths)
+        .components(Some(ComponentsBuilder::new().schemas_from_iter(schemas).build()))
+        .build()
+}
+
+pub async fn serve() -> Json<OpenApi> {
+    Json(spec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_referenced_schema_is_defined() {
+        let spec = serde_json::to_value(spec()).unwrap();
+        let text = spec.to_string();
+        let schemas = spec["components"]["schemas"].as_object().unwrap();
+        for reference in text.split("\"#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(schemas.contains_key(name), "missing schema {name}");
+        }
+    }
+}
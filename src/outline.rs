@@ -0,0 +1,201 @@
+use std::fmt::Write;
+
+use crate::models::{Edge, TreeNode};
+
+/// How the tree and edges are serialized into prompts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptFormat {
+    /// Pretty-printed JSON of `TreeNode` and `Edge`.
+    Json,
+    /// Indented outline, roughly half the tokens of pretty JSON.
+    Outline,
+}
+
+impl PromptFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "json" => Some(Self::Json),
+            "outline" => Some(Self::Outline),
+            _ => None,
+        }
+    }
+}
+
+/// Explains the outline notation to the model.
+pub const OUTLINE_LEGEND: &str = "The tree is written as an indented outline. Each node is a line `- id [heat, by] label`, with `unseen` added inside the brackets if the human hasn't acknowledged it yet. The node's prose follows on lines starting with `>`. Children are indented two spaces beneath their parent. Edges are written as `source -> target: label`.";
+
+/// Render a tree as an indented outline:
+///
+/// ```text
+/// - root [warm, human] Big question
+///   > What are we actually building?
+///   - sub-idea [hot, claude:feynman, unseen] A narrower take
+///     > First line of prose
+///     > Second line of prose
+/// ```
+pub fn render_tree(tree: &TreeNode) -> String {
+    let mut out = String::new();
+    render_node(tree, 0, &mut out);
+    out
+}
+
+fn render_node(node: &TreeNode, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    let unseen = if node.seen { "" } else { ", unseen" };
+    let _ = writeln!(
+        out,
+        "{indent}- {} [{}, {}{unseen}] {}",
+        node.id, node.heat, node.by, node.label
+    );
+    for line in node.prose.lines() {
+        let _ = writeln!(out, "{indent}  > {line}");
+    }
+    for child in &node.children {
+        render_node(child, depth + 1, out);
+    }
+}
+
+/// Render edges one per line as `source -> target: label`.
+pub fn render_edges<'a>(edges: impl IntoIterator<Item = &'a Edge>) -> String {
+    let mut out = String::new();
+    for edge in edges {
+        let _ = writeln!(out, "{} -> {}: {}", edge.source, edge.target, edge.label);
+    }
+    if out.is_empty() {
+        out.push_str("(none)\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse the outline back into a tree, so tests can check nothing the
+    /// model needs is lost in rendering.
+    fn parse_tree(text: &str) -> TreeNode {
+        let mut stack: Vec<(usize, TreeNode)> = Vec::new();
+        for line in text.lines() {
+            let trimmed = line.trim_start();
+            let depth = (line.len() - trimmed.len()) / 2;
+            if let Some(prose) = trimmed.strip_prefix("> ").or(trimmed.strip_prefix('>')) {
+                let (_, node) = stack.last_mut().expect("prose before any node");
+                if !node.prose.is_empty() {
+                    node.prose.push('\n');
+                }
+                node.prose.push_str(prose);
+                continue;
+            }
+            let rest = trimmed.strip_prefix("- ").expect("node line");
+            let (id, rest) = rest.split_once(" [").expect("id");
+            let (meta, label) = rest.split_once("] ").expect("meta");
+            let mut parts = meta.split(", ");
+            let heat = parts.next().unwrap().to_string();
+            let by = parts.next().unwrap().to_string();
+            let seen = parts.next() != Some("unseen");
+            while stack.len() > depth {
+                let (_, child) = stack.pop().unwrap();
+                stack.last_mut().unwrap().1.children.push(child);
+            }
+            stack.push((
+                depth,
+                TreeNode {
+                    id: id.to_string(),
+                    label: label.to_string(),
+                    prose: String::new(),
+                    heat,
+                    by,
+                    seen,
+                    children: vec![],
+                },
+            ));
+        }
+        while stack.len() > 1 {
+            let (_, child) = stack.pop().unwrap();
+            stack.last_mut().unwrap().1.children.push(child);
+        }
+        stack.pop().unwrap().1
+    }
+
+    fn parse_edges(text: &str) -> Vec<Edge> {
+        text.lines()
+            .filter(|l| *l != "(none)")
+            .map(|line| {
+                let (source, rest) = line.split_once(" -> ").unwrap();
+                let (target, label) = rest.split_once(": ").unwrap();
+                Edge {
+                    source: source.to_string(),
+                    target: target.to_string(),
+                    label: label.to_string(),
+                }
+            })
+            .collect()
+    }
+
+    fn node(id: &str, label: &str, prose: &str, heat: &str, by: &str, seen: bool) -> TreeNode {
+        TreeNode {
+            id: id.to_string(),
+            label: label.to_string(),
+            prose: prose.to_string(),
+            heat: heat.to_string(),
+            by: by.to_string(),
+            seen,
+            children: vec![],
+        }
+    }
+
+    fn sample_tree() -> TreeNode {
+        let mut root = node("root", "What is grove for?", "The big question.", "warm", "human", true);
+        let mut a = node(
+            "rhythm",
+            "Rhythm [not format]",
+            "It's rhythm, not text vs. visual.\n- bullets in prose stay prose\n> even quotes",
+            "hot",
+            "claude:feynman",
+            false,
+        );
+        a.children.push(node("deep-leaf", "Leaf: with colon", "", "quiet", "both", true));
+        root.children.push(a);
+        root.children.push(node("asym", "The asymmetry", "Patient, not cold.", "growing", "claude", false));
+        root
+    }
+
+    #[test]
+    fn tree_round_trips_every_field() {
+        let tree = sample_tree();
+        let parsed = parse_tree(&render_tree(&tree));
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&tree).unwrap()
+        );
+    }
+
+    #[test]
+    fn edges_round_trip() {
+        let edges = vec![
+            Edge {
+                source: "rhythm".to_string(),
+                target: "asym".to_string(),
+                label: "builds on: sort of".to_string(),
+            },
+            Edge {
+                source: "deep-leaf".to_string(),
+                target: "root".to_string(),
+                label: "contradicts".to_string(),
+            },
+        ];
+        let parsed = parse_edges(&render_edges(&edges));
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&edges).unwrap()
+        );
+        assert!(parse_edges(&render_edges(&[])).is_empty());
+    }
+
+    #[test]
+    fn outline_is_smaller_than_pretty_json() {
+        let tree = sample_tree();
+        let json = serde_json::to_string_pretty(&tree).unwrap();
+        assert!(render_tree(&tree).len() * 2 < json.len());
+    }
+}
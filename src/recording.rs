@@ -0,0 +1,77 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+/// Recordings are named by a hash of the whole request, so replaying the
+/// same prompt against the same tree finds the same answer.
+pub fn key(request: &Value) -> String {
+    format!("{:x}", md5::compute(request.to_string()))
+}
+
+fn file(dir: &str, request: &Value) -> PathBuf {
+    Path::new(dir).join(format!("{}.json", key(request)))
+}
+
+/// Save a request and the API's response to it, replacing any earlier
+/// recording of the same request.
+pub async fn record(
+    dir: &str,
+    operation: &str,
+    persona: &str,
+    request: &Value,
+    response: &Value,
+) -> anyhow::Result<()> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create {dir}"))?;
+    let recording = json!({
+        "operation": operation,
+        "persona": persona,
+        "request": request,
+        "response": response,
+    });
+    let path = file(dir, request);
+    tokio::fs::write(&path, serde_json::to_string_pretty(&recording)?)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The recorded response to `request`.
+pub async fn replay(dir: &str, request: &Value) -> anyhow::Result<Value> {
+    let path = file(dir, request);
+    let text = tokio::fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("No recorded response for this request ({})", path.display()))?;
+    let mut recording: Value = serde_json::from_str(&text)
+        .with_context(|| format!("Invalid recording {}", path.display()))?;
+    Ok(recording["response"].take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn replays_what_was_recorded_for_the_same_request() {
+        let dir = std::env::temp_dir().join(format!("grove-recording-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let dir = dir.display().to_string();
+
+        let request = json!({ "model": "m", "messages": [{ "role": "user", "content": "hi" }] });
+        let response = json!({ "content": [{ "type": "text", "text": "hello" }] });
+        record(&dir, "chat", "claude", &request, &response).await.unwrap();
+
+        // Key order in the request doesn't matter
+        let same: Value =
+            serde_json::from_str(r#"{"messages":[{"content":"hi","role":"user"}],"model":"m"}"#).unwrap();
+        assert_eq!(key(&same), key(&request));
+        assert_eq!(replay(&dir, &same).await.unwrap(), response);
+
+        let other = json!({ "model": "m", "messages": [{ "role": "user", "content": "bye" }] });
+        let missing = replay(&dir, &other).await.unwrap_err();
+        assert!(missing.to_string().starts_with("No recorded response"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::llm::Personality;
+use crate::models::TreeNode;
+
+/// How a personality heartbeat picks which active personas speak.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SpeakerStrategy {
+    /// Uniformly random, the original behavior.
+    #[default]
+    Dice,
+    /// Walk the active list in order, continuing where the last tick stopped.
+    RoundRobin,
+    /// Random, biased by per-document persona weights.
+    Weighted,
+    /// Whoever has gone longest without posting a message.
+    LeastRecent,
+    /// Personas whose interests overlap most with hot or unseen nodes.
+    Relevance,
+}
+
+impl SpeakerStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Dice => "dice",
+            Self::RoundRobin => "round_robin",
+            Self::Weighted => "weighted",
+            Self::LeastRecent => "least_recent",
+            Self::Relevance => "relevance",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "dice" => Some(Self::Dice),
+            "round_robin" => Some(Self::RoundRobin),
+            "weighted" => Some(Self::Weighted),
+            "least_recent" => Some(Self::LeastRecent),
+            "relevance" => Some(Self::Relevance),
+            _ => None,
+        }
+    }
+}
+
+/// Everything a strategy may consult when picking speakers.
+pub struct SelectionInput<'a> {
+    /// Active persona IDs, sorted.
+    pub active: &'a [String],
+    pub weights: &'a HashMap<String, f64>,
+    /// Round-robin position: index into `active` of the next speaker.
+    pub cursor: usize,
+    /// Message id of each persona's latest message.
+    pub last_spoke: &'a HashMap<String, i64>,
+    pub tree: &'a TreeNode,
+}
+
+/// Roll how many speak (1..=dice_sides, capped by the active count), then
+/// pick that many with `strategy`. Returns the speakers and the round-robin
+/// cursor to store for the next tick.
+pub fn select_speakers(
+    strategy: SpeakerStrategy,
+    dice_sides: u32,
+    input: &SelectionInput,
+    rng: &mut impl Rng,
+) -> (Vec<String>, usize) {
+    if input.active.is_empty() {
+        return (vec![], input.cursor);
+    }
+    let roll: u32 = rng.gen_range(1..=dice_sides.max(1));
+    let count = (roll as usize).min(input.active.len());
+
+    match strategy {
+        SpeakerStrategy::Dice => {
+            let mut sel = input.active.to_vec();
+            sel.shuffle(rng);
+            sel.truncate(count);
+            (sel, input.cursor)
+        }
+        SpeakerStrategy::RoundRobin => {
+            let n = input.active.len();
+            let start = input.cursor % n;
+            let sel = (0..count)
+                .map(|i| input.active[(start + i) % n].clone())
+                .collect();
+            (sel, (start + count) % n)
+        }
+        SpeakerStrategy::Weighted => {
+            // A zero weight mutes a persona, even when that leaves fewer
+            // speakers than the roll asked for
+            let weight = |p: &String| input.weights.get(p).copied().unwrap_or(1.0);
+            let mut pool: Vec<String> =
+                input.active.iter().filter(|p| weight(p) > 0.0).cloned().collect();
+            let count = count.min(pool.len());
+            let mut sel = Vec::with_capacity(count);
+            while sel.len() < count {
+                let weights: Vec<f64> = pool.iter().map(weight).collect();
+                let mut target = rng.gen_range(0.0..weights.iter().sum::<f64>());
+                let idx = weights
+                    .iter()
+                    .position(|w| {
+                        target -= w;
+                        target < 0.0
+                    })
+                    .unwrap_or(pool.len() - 1);
+                sel.push(pool.remove(idx));
+            }
+            (sel, input.cursor)
+        }
+        SpeakerStrategy::LeastRecent => {
+            // Shuffle first so ties break randomly but reproducibly
+            let mut sel = input.active.to_vec();
+            sel.shuffle(rng);
+            sel.sort_by_key(|p| input.last_spoke.get(p).copied().unwrap_or(i64::MIN));
+            sel.truncate(count);
+            (sel, input.cursor)
+        }
+        SpeakerStrategy::Relevance => {
+            let focus = focus_nodes(input.tree);
+            let mut scored: Vec<(String, usize)> = input
+                .active
+                .iter()
+                .map(|p| (p.clone(), relevance(p, &focus)))
+                .collect();
+            scored.shuffle(rng);
+            scored.sort_by_key(|s| std::cmp::Reverse(s.1));
+            let sel = scored.into_iter().take(count).map(|(p, _)| p).collect();
+            (sel, input.cursor)
+        }
+    }
+}
+
+/// Hot or unseen nodes, each paired with its parent's author.
+fn focus_nodes(tree: &TreeNode) -> Vec<(&TreeNode, Option<&str>)> {
+    fn walk<'a>(node: &'a TreeNode, parent_by: Option<&'a str>, out: &mut Vec<(&'a TreeNode, Option<&'a str>)>) {
+        if node.heat == "hot" || !node.seen {
+            out.push((node, parent_by));
+        }
+        for child in &node.children {
+            walk(child, Some(&node.by), out);
+        }
+    }
+    let mut out = Vec::new();
+    walk(tree, None, &mut out);
+    out
+}
+
+/// Keyword overlap between a persona's description and the focus nodes, plus
+/// a bonus for each focus node that responds to something the persona wrote.
+fn relevance(persona_id: &str, focus: &[(&TreeNode, Option<&str>)]) -> usize {
+    let Some(p) = crate::llm::get_personality(persona_id) else {
+        return 0;
+    };
+    let interests = keywords(&persona_text(p));
+    let by = format!("claude:{persona_id}");
+    focus
+        .iter()
+        .map(|(node, parent_by)| {
+            let text = format!("{} {}", node.label, node.prose);
+            let overlap = keywords(&text).intersection(&interests).count();
+            let reply_bonus = if *parent_by == Some(by.as_str()) { 2 } else { 0 };
+            overlap + reply_bonus
+        })
+        .sum()
+}
+
+fn persona_text(p: &Personality) -> String {
+    format!("{} {} {}", p.name, p.short_description, p.system_prompt_fragment)
+}
+
+/// Lowercased words of five or more letters; short words are mostly noise.
+fn keywords(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() >= 5)
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn tree() -> TreeNode {
+        TreeNode {
+            id: "root".to_string(),
+            label: "Root".to_string(),
+            prose: String::new(),
+            heat: "warm".to_string(),
+            by: "human".to_string(),
+            seen: true,
+            children: vec![TreeNode {
+                id: "incentives".to_string(),
+                label: "Incentives everywhere".to_string(),
+                prose: "Show me the incentive and I'll show you the outcome; invert the problem.".to_string(),
+                heat: "hot".to_string(),
+                by: "human".to_string(),
+                seen: false,
+                children: vec![],
+            }],
+        }
+    }
+
+    fn active() -> Vec<String> {
+        ["feynman", "heidegger", "mcluhan", "munger"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    fn pick(strategy: SpeakerStrategy, dice: u32, input: &SelectionInput, seed: u64) -> (Vec<String>, usize) {
+        select_speakers(strategy, dice, input, &mut StdRng::seed_from_u64(seed))
+    }
+
+    #[test]
+    fn same_seed_reproduces_selection() {
+        let (active, weights, last, tree) = (active(), HashMap::new(), HashMap::new(), tree());
+        let input = SelectionInput { active: &active, weights: &weights, cursor: 0, last_spoke: &last, tree: &tree };
+        for strategy in [SpeakerStrategy::Dice, SpeakerStrategy::Weighted, SpeakerStrategy::LeastRecent] {
+            assert_eq!(pick(strategy, 3, &input, 7), pick(strategy, 3, &input, 7));
+        }
+    }
+
+    #[test]
+    fn round_robin_continues_from_cursor() {
+        let (active, weights, last, tree) = (active(), HashMap::new(), HashMap::new(), tree());
+        let input = SelectionInput { active: &active, weights: &weights, cursor: 3, last_spoke: &last, tree: &tree };
+        // One-sided die: exactly one speaker per tick
+        let (sel, next) = pick(SpeakerStrategy::RoundRobin, 1, &input, 0);
+        assert_eq!(sel, vec!["munger".to_string()]);
+        assert_eq!(next, 0);
+    }
+
+    #[test]
+    fn weighted_never_picks_zero_weight() {
+        let active = active();
+        let weights: HashMap<String, f64> = active
+            .iter()
+            .map(|p| (p.clone(), if p == "heidegger" { 1.0 } else { 0.0 }))
+            .collect();
+        let (last, tree) = (HashMap::new(), tree());
+        let input = SelectionInput { active: &active, weights: &weights, cursor: 0, last_spoke: &last, tree: &tree };
+        for seed in 0..20 {
+            assert_eq!(pick(SpeakerStrategy::Weighted, 1, &input, seed).0, vec!["heidegger".to_string()]);
+            // Nor once every positive weight has already been picked
+            assert_eq!(pick(SpeakerStrategy::Weighted, 4, &input, seed).0, vec!["heidegger".to_string()]);
+        }
+        let muted: HashMap<String, f64> = active.iter().map(|p| (p.clone(), 0.0)).collect();
+        let input = SelectionInput { weights: &muted, ..input };
+        assert!(pick(SpeakerStrategy::Weighted, 4, &input, 0).0.is_empty());
+    }
+
+    #[test]
+    fn least_recent_prefers_silent_personas() {
+        let active = active();
+        let last: HashMap<String, i64> = [("feynman", 10), ("heidegger", 5), ("munger", 12)]
+            .iter()
+            .map(|(p, id)| (p.to_string(), *id))
+            .collect();
+        let (weights, tree) = (HashMap::new(), tree());
+        let input = SelectionInput { active: &active, weights: &weights, cursor: 0, last_spoke: &last, tree: &tree };
+        let (sel, _) = pick(SpeakerStrategy::LeastRecent, 1, &input, 1);
+        assert_eq!(sel, vec!["mcluhan".to_string()]);
+    }
+
+    #[test]
+    fn relevance_favors_matching_interests() {
+        let (active, weights, last, tree) = (active(), HashMap::new(), HashMap::new(), tree());
+        let input = SelectionInput { active: &active, weights: &weights, cursor: 0, last_spoke: &last, tree: &tree };
+        let (sel, _) = pick(SpeakerStrategy::Relevance, 1, &input, 3);
+        assert_eq!(sel, vec!["munger".to_string()]);
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::HashSet;
+
+use crate::llm;
+use crate::models::{DocumentBundle, Edge, TreeNode};
+
+pub const HEATS: &[&str] = &["hot", "warm", "growing", "quiet"];
+
+const MAX_ID_LEN: usize = 64;
+
+/// IDs are lowercase kebab-case: ASCII letters, digits and single hyphens.
+pub fn validate_node_id(id: &str) -> Result<(), String> {
+    if id.is_empty() {
+        return Err("id is required".to_string());
+    }
+    if id.len() > MAX_ID_LEN {
+        return Err(format!("id \"{id}\" is longer than {MAX_ID_LEN} characters"));
+    }
+    let well_formed = id
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !id.starts_with('-')
+        && !id.ends_with('-')
+        && !id.contains("--");
+    if !well_formed {
+        return Err(format!("id \"{id}\" must be lowercase kebab-case (a-z, 0-9, -)"));
+    }
+    Ok(())
+}
+
+pub fn validate_heat(heat: &str) -> Result<(), String> {
+    if HEATS.contains(&heat) {
+        Ok(())
+    } else {
+        Err(format!("heat \"{heat}\" must be one of {}", HEATS.join(", ")))
+    }
+}
+
+pub fn validate_label(label: &str) -> Result<(), String> {
+    if label.trim().is_empty() {
+        Err("label must not be empty".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that `node` can be attached under `parent_id` in `tree`.
+pub fn validate_new_node(tree: &TreeNode, parent_id: &str, node: &TreeNode) -> Result<(), String> {
+    validate_node_id(&node.id)?;
+    validate_label(&node.label)?;
+    validate_heat(&node.heat)?;
+    if tree.find(&node.id).is_some() {
+        return Err(format!("a node with id \"{}\" already exists", node.id));
+    }
+    if tree.find(parent_id).is_none() {
+        return Err(format!("parent \"{parent_id}\" does not exist"));
+    }
+    Ok(())
+}
+
+/// Check the optional fields of an update against the node they apply to.
+pub fn validate_node_update(
+    tree: &TreeNode,
+    id: &str,
+    label: Option<&str>,
+    heat: Option<&str>,
+) -> Result<(), String> {
+    if tree.find(id).is_none() {
+        return Err(format!("node \"{id}\" does not exist"));
+    }
+    if let Some(label) = label {
+        validate_label(label)?;
+    }
+    if let Some(heat) = heat {
+        validate_heat(heat)?;
+    }
+    Ok(())
+}
+
+pub fn validate_edge(tree: &TreeNode, source: &str, target: &str) -> Result<(), String> {
+    if source == target {
+        return Err(format!("edge from \"{source}\" points to itself"));
+    }
+    for id in [source, target] {
+        if tree.find(id).is_none() {
+            return Err(format!("node \"{id}\" does not exist"));
+        }
+    }
+    Ok(())
+}
+
+/// Check a whole tree, as when it arrives from outside rather than one tool
+/// call at a time: every node well-formed, no id twice, and every edge
+/// between two different nodes that exist.
+pub fn validate_tree(tree: &TreeNode, edges: &[Edge]) -> Result<(), String> {
+    fn walk<'a>(node: &'a TreeNode, seen: &mut HashSet<&'a str>) -> Result<(), String> {
+        validate_node_id(&node.id)?;
+        validate_label(&node.label)?;
+        validate_heat(&node.heat)?;
+        if !seen.insert(&node.id) {
+            return Err(format!("a node with id \"{}\" already exists", node.id));
+        }
+        node.children.iter().try_for_each(|c| walk(c, seen))
+    }
+    walk(tree, &mut HashSet::new())?;
+    edges.iter().try_for_each(|e| validate_edge(tree, &e.source, &e.target))
+}
+
+pub fn validate_personality_id(id: &str) -> Result<(), String> {
+    match llm::get_personality(id) {
+        Some(_) => Ok(()),
+        None => Err(format!("Unknown personality \"{id}\"")),
+    }
+}
+
+pub fn validate_weight(weight: f64) -> Result<(), String> {
+    if weight.is_finite() && weight >= 0.0 {
+        Ok(())
+    } else {
+        Err("weights must be finite and non-negative".to_string())
+    }
+}
+
+pub fn validate_dice_sides(sides: u32) -> Result<(), String> {
+    if sides == 0 {
+        Err("dice_sides must be at least 1".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+pub fn validate_repel_force(force: f64) -> Result<(), String> {
+    if force.is_finite() {
+        Ok(())
+    } else {
+        Err("repel_force must be a finite number".to_string())
+    }
+}
+
+/// A `YYYY-MM-DD` date, optionally followed by a `HH:MM[:SS[.fff]]` time
+/// (after `T` or a space) and a `Z` or `±HH:MM` offset. These are the forms
+/// SQLite's `datetime()` reads; anything else it turns into NULL, which would
+/// silently match nothing.
+pub fn validate_timestamp(name: &str, value: &str) -> Result<(), String> {
+    let invalid = || {
+        Err(format!(
+            "{name} \"{value}\" must be a date (YYYY-MM-DD) or date-time (YYYY-MM-DDTHH:MM:SS)"
+        ))
+    };
+    let number = |s: &str, max: u32| {
+        s.len() == 2 && s.bytes().all(|b| b.is_ascii_digit()) && s.parse::<u32>().unwrap() <= max
+    };
+    let (date, time) = match value.find(['T', ' ']) {
+        Some(i) => (&value[..i], Some(&value[i + 1..])),
+        None => (value, None),
+    };
+    let date_ok = match date.split('-').collect::<Vec<_>>()[..] {
+        [y, m, d] => {
+            y.len() == 4
+                && y.bytes().all(|b| b.is_ascii_digit())
+                && number(m, 12)
+                && number(d, 31)
+                && m != "00"
+                && d != "00"
+        }
+        _ => false,
+    };
+    if !date_ok {
+        return invalid();
+    }
+    let Some(time) = time else {
+        return Ok(());
+    };
+    let (clock, offset) = match time.find(['Z', '+', '-']) {
+        Some(i) => (&time[..i], &time[i..]),
+        None => (time, ""),
+    };
+    // `offset` is empty, or starts with Z, + or -
+    let offset_ok = match offset {
+        "" | "Z" => true,
+        _ => offset[1..]
+            .split_once(':')
+            .is_some_and(|(h, m)| number(h, 23) && number(m, 59)),
+    };
+    let (hms, fraction) = clock.split_once('.').unwrap_or((clock, "0"));
+    let clock_ok = match hms.split(':').collect::<Vec<_>>()[..] {
+        [h, m] => number(h, 23) && number(m, 59),
+        [h, m, s] => number(h, 23) && number(m, 59) && number(s, 59),
+        _ => false,
+    } && !fraction.is_empty()
+        && fraction.bytes().all(|b| b.is_ascii_digit());
+    if offset_ok && clock_ok { Ok(()) } else { invalid() }
+}
+
+/// Hold an imported bundle to the same rules as the API that would have
+/// built it: the tree and edges, the personas and the settings.
+pub fn validate_bundle(bundle: &DocumentBundle) -> Result<(), String> {
+    validate_tree(&bundle.document.tree, &bundle.document.edges)?;
+    let mut personas = HashSet::new();
+    for p in &bundle.personalities {
+        validate_personality_id(&p.id)?;
+        validate_weight(p.weight)?;
+        if !personas.insert(p.id.as_str()) {
+            return Err(format!("Personality \"{}\" listed twice", p.id));
+        }
+    }
+    if let Some(settings) = &bundle.settings {
+        validate_dice_sides(settings.dice_sides)?;
+        validate_repel_force(settings.repel_force)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, label: &str, heat: &str) -> TreeNode {
+        TreeNode {
+            id: id.to_string(),
+            label: label.to_string(),
+            prose: String::new(),
+            heat: heat.to_string(),
+            by: "human".to_string(),
+            seen: true,
+            children: vec![],
+        }
+    }
+
+    fn tree() -> TreeNode {
+        let mut root = node("root", "Root", "warm");
+        root.children.push(node("soil", "Soil", "warm"));
+        root
+    }
+
+    #[test]
+    fn ids_are_lowercase_kebab_case() {
+        for id in ["a", "soil", "soil-2", "x1-y2-z3"] {
+            assert_eq!(validate_node_id(id), Ok(()), "{id}");
+        }
+        for id in ["", "Soil", "soil_2", "soil 2", "-soil", "soil-", "soil--2", "sóil"] {
+            assert!(validate_node_id(id).is_err(), "{id}");
+        }
+        assert_eq!(validate_node_id(&"a".repeat(MAX_ID_LEN)), Ok(()));
+        let long = "a".repeat(MAX_ID_LEN + 1);
+        assert_eq!(
+            validate_node_id(&long),
+            Err(format!("id \"{long}\" is longer than 64 characters"))
+        );
+    }
+
+    #[test]
+    fn new_nodes_need_a_heat_a_label_a_fresh_id_and_a_parent() {
+        let tree = tree();
+        assert_eq!(validate_new_node(&tree, "soil", &node("microbes", "Microbes", "hot")), Ok(()));
+        assert_eq!(
+            validate_new_node(&tree, "soil", &node("microbes", "Microbes", "boiling")),
+            Err("heat \"boiling\" must be one of hot, warm, growing, quiet".to_string())
+        );
+        assert_eq!(
+            validate_new_node(&tree, "soil", &node("microbes", "  ", "hot")),
+            Err("label must not be empty".to_string())
+        );
+        assert_eq!(
+            validate_new_node(&tree, "root", &node("soil", "Soil again", "hot")),
+            Err("a node with id \"soil\" already exists".to_string())
+        );
+        assert_eq!(
+            validate_new_node(&tree, "light", &node("microbes", "Microbes", "hot")),
+            Err("parent \"light\" does not exist".to_string())
+        );
+        assert_eq!(
+            validate_node_update(&tree, "soil", Some(""), None),
+            Err("label must not be empty".to_string())
+        );
+    }
+
+    #[test]
+    fn edges_join_two_different_existing_nodes() {
+        let tree = tree();
+        assert_eq!(validate_edge(&tree, "root", "soil"), Ok(()));
+        assert_eq!(
+            validate_edge(&tree, "soil", "soil"),
+            Err("edge from \"soil\" points to itself".to_string())
+        );
+        assert_eq!(
+            validate_edge(&tree, "soil", "light"),
+            Err("node \"light\" does not exist".to_string())
+        );
+    }
+
+    #[test]
+    fn timestamps_are_dates_or_date_times() {
+        for ok in [
+            "2026-10-18",
+            "2026-10-18 09:30",
+            "2026-10-18T09:30:15",
+            "2026-10-18T09:30:15.250Z",
+            "2026-10-18T09:30:15+02:00",
+            "2026-10-18 23:59:59-05:30",
+        ] {
+            assert_eq!(validate_timestamp("since", ok), Ok(()), "{ok}");
+        }
+        for bad in [
+            "",
+            "yesterday",
+            "2026-13-01",
+            "2026-10-00",
+            "18/10/2026",
+            "2026-10-18T",
+            "2026-10-18T25:00",
+            "2026-10-18T09:30:15.",
+            "2026-10-18T09:30+2",
+            "2026-10-18T09:30Zulu",
+        ] {
+            assert!(validate_timestamp("since", bad).is_err(), "{bad}");
+        }
+        assert_eq!(
+            validate_timestamp("until", "soon"),
+            Err("until \"soon\" must be a date (YYYY-MM-DD) or date-time (YYYY-MM-DDTHH:MM:SS)"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn bundles_follow_the_same_rules_as_the_api() {
+        let bundle = || -> DocumentBundle {
+            serde_json::from_value(serde_json::json!({
+                "bundle_version": 1,
+                "document": {
+                    "id": "doc",
+                    "tree": tree(),
+                    "edges": [{ "source": "root", "target": "soil", "label": "feeds" }],
+                    "title": null,
+                    "created_at": "",
+                    "updated_at": "",
+                },
+                "personalities": [{ "id": "feynman", "weight": 2.0 }],
+                "settings": {
+                    "dice_sides": 6,
+                    "repel_force": 20.0,
+                    "max_new_nodes": 3,
+                    "max_deletions": 2,
+                    "max_edges": 3,
+                    "max_tree_size": 150,
+                    "speaker_strategy": "dice",
+                },
+            }))
+            .unwrap()
+        };
+        assert_eq!(validate_bundle(&bundle()), Ok(()));
+
+        let rejects = |change: &dyn Fn(&mut DocumentBundle), expected: &str| {
+            let mut b = bundle();
+            change(&mut b);
+            assert_eq!(validate_bundle(&b), Err(expected.to_string()));
+        };
+        rejects(
+            &|b| b.document.tree.children.push(node("soil", "Soil", "warm")),
+            "a node with id \"soil\" already exists",
+        );
+        rejects(
+            &|b| b.document.tree.children[0].id = "Soil".to_string(),
+            "id \"Soil\" must be lowercase kebab-case (a-z, 0-9, -)",
+        );
+        rejects(
+            &|b| b.document.edges[0].target = "root".to_string(),
+            "edge from \"root\" points to itself",
+        );
+        rejects(
+            &|b| b.personalities[0].id = "nobody".to_string(),
+            "Unknown personality \"nobody\"",
+        );
+        rejects(
+            &|b| {
+                let again = serde_json::json!({ "id": "feynman", "weight": 1.0 });
+                b.personalities.push(serde_json::from_value(again).unwrap());
+            },
+            "Personality \"feynman\" listed twice",
+        );
+        rejects(
+            &|b| b.settings.as_mut().unwrap().dice_sides = 0,
+            "dice_sides must be at least 1",
+        );
+        rejects(
+            &|b| b.settings.as_mut().unwrap().repel_force = f64::NAN,
+            "repel_force must be a finite number",
+        );
+    }
+}
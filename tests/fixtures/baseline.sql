@@ -0,0 +1,53 @@
+-- A database as the first release of grove left it, before any migrations
+-- beyond the initial ad-hoc column checks. Used by the migration tests.
+BEGIN TRANSACTION;
+CREATE TABLE agent_questions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                doc_id TEXT NOT NULL,
+                from_agent TEXT NOT NULL,
+                to_agent TEXT NOT NULL,
+                question TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+INSERT INTO "agent_questions" VALUES(1,'snapdoc','feynman','munger','What would make this fail?','2026-02-06 10:07:00');
+CREATE TABLE doc_personalities (
+                doc_id TEXT NOT NULL,
+                personality_id TEXT NOT NULL,
+                PRIMARY KEY (doc_id, personality_id)
+            );
+INSERT INTO "doc_personalities" VALUES('snapdoc','feynman');
+INSERT INTO "doc_personalities" VALUES('snapdoc','munger');
+CREATE TABLE doc_settings (
+                doc_id TEXT PRIMARY KEY,
+                heartbeat_dice_sides INTEGER NOT NULL DEFAULT 3
+            , repel_force REAL NOT NULL DEFAULT 20.0);
+INSERT INTO "doc_settings" VALUES('snapdoc',4,20.0);
+CREATE TABLE doc_summaries (
+                doc_id TEXT NOT NULL,
+                voice TEXT NOT NULL,
+                content TEXT NOT NULL,
+                tree_hash TEXT NOT NULL,
+                PRIMARY KEY (doc_id, voice)
+            );
+INSERT INTO "doc_summaries" VALUES('snapdoc','claude','A grove about growing.','abc123');
+CREATE TABLE documents (
+                id TEXT PRIMARY KEY,
+                tree TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+            , edges TEXT NOT NULL DEFAULT '[]', title TEXT);
+INSERT INTO "documents" VALUES('snapdoc','{"id": "root", "label": "Big question", "prose": "What are we building?", "heat": "warm", "by": "human", "seen": true, "children": [{"id": "idea", "label": "An idea", "prose": "Grow it slowly.", "heat": "hot", "by": "claude", "seen": false, "children": []}]}','2026-02-06 10:00:00','2026-02-06 11:00:00','[{"source":"idea","target":"root","label":"answers"}]','Snapshot grove');
+CREATE TABLE messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                doc_id TEXT NOT NULL REFERENCES documents(id),
+                role TEXT NOT NULL,
+                content TEXT NOT NULL,
+                hover_node_id TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            , personality TEXT);
+INSERT INTO "messages" VALUES(1,'snapdoc','human','Hello grove',NULL,'2026-02-06 10:05:00',NULL);
+INSERT INTO "messages" VALUES(2,'snapdoc','assistant','Hello back',NULL,'2026-02-06 10:06:00','feynman');
+DELETE FROM "sqlite_sequence";
+INSERT INTO "sqlite_sequence" VALUES('messages',2);
+INSERT INTO "sqlite_sequence" VALUES('agent_questions',1);
+COMMIT;
@@ -0,0 +1,59 @@
+-- A database from just before tree revisions were recorded: every column
+-- but no doc_revisions table. Used by the migration tests.
+BEGIN TRANSACTION;
+CREATE TABLE agent_questions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                doc_id TEXT NOT NULL,
+                from_agent TEXT NOT NULL,
+                to_agent TEXT NOT NULL,
+                question TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            , status TEXT NOT NULL DEFAULT 'pending', age INTEGER NOT NULL DEFAULT 0, answer_message_id INTEGER, answered_at TEXT);
+INSERT INTO "agent_questions" VALUES(1,'snapdoc','feynman','munger','What would make this fail?','2026-02-06 10:07:00','pending',0,NULL,NULL);
+CREATE TABLE doc_personalities (
+                doc_id TEXT NOT NULL,
+                personality_id TEXT NOT NULL, weight REAL NOT NULL DEFAULT 1.0,
+                PRIMARY KEY (doc_id, personality_id)
+            );
+INSERT INTO "doc_personalities" VALUES('snapdoc','feynman',1.0);
+INSERT INTO "doc_personalities" VALUES('snapdoc','munger',1.0);
+CREATE TABLE doc_settings (
+                doc_id TEXT PRIMARY KEY,
+                heartbeat_dice_sides INTEGER NOT NULL DEFAULT 3
+            , repel_force REAL NOT NULL DEFAULT 20.0, max_new_nodes INTEGER NOT NULL DEFAULT 3, max_deletions INTEGER NOT NULL DEFAULT 2, max_edges INTEGER NOT NULL DEFAULT 3, max_tree_size INTEGER NOT NULL DEFAULT 150, speaker_strategy TEXT NOT NULL DEFAULT 'dice', speaker_cursor INTEGER NOT NULL DEFAULT 0);
+INSERT INTO "doc_settings" VALUES('snapdoc',4,20.0,3,2,3,150,'dice',0);
+CREATE TABLE "doc_summaries" (
+                    doc_id TEXT NOT NULL,
+                    voice TEXT NOT NULL,
+                    node_id TEXT NOT NULL DEFAULT '',
+                    format TEXT NOT NULL DEFAULT 'essay',
+                    content TEXT NOT NULL,
+                    tree_hash TEXT NOT NULL,
+                    PRIMARY KEY (doc_id, voice, node_id, format)
+                 );
+INSERT INTO "doc_summaries" VALUES('snapdoc','claude','','essay','A grove about growing.','abc123');
+CREATE TABLE documents (
+                id TEXT PRIMARY KEY,
+                tree TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+            , edges TEXT NOT NULL DEFAULT '[]', title TEXT, archived_at TEXT, forked_from TEXT, forked_at TEXT, fork_base_tree TEXT, fork_base_edges TEXT);
+INSERT INTO "documents" VALUES('snapdoc','{"id": "root", "label": "Big question", "prose": "What are we building?", "heat": "warm", "by": "human", "seen": true, "children": [{"id": "idea", "label": "An idea", "prose": "Grow it slowly.", "heat": "hot", "by": "claude", "seen": false, "children": []}]}','2026-02-06 10:00:00','2026-02-06 11:00:00','[{"source":"idea","target":"root","label":"answers"}]','Snapshot grove',NULL,NULL,NULL,NULL,NULL);
+CREATE TABLE messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                doc_id TEXT NOT NULL REFERENCES documents(id),
+                role TEXT NOT NULL,
+                content TEXT NOT NULL,
+                hover_node_id TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            , personality TEXT, thread_node_id TEXT);
+INSERT INTO "messages" VALUES(1,'snapdoc','human','Hello grove',NULL,'2026-02-06 10:05:00',NULL,NULL);
+INSERT INTO "messages" VALUES(2,'snapdoc','assistant','Hello back',NULL,'2026-02-06 10:06:00','feynman',NULL);
+INSERT INTO "messages" VALUES(3,'snapdoc','human','About this idea',NULL,'2026-02-06 10:08:00',NULL,'idea');
+CREATE INDEX idx_messages_thread ON messages (doc_id, thread_node_id);
+CREATE INDEX idx_messages_doc ON messages (doc_id, id);
+CREATE INDEX idx_messages_hover ON messages (doc_id, hover_node_id);
+DELETE FROM "sqlite_sequence";
+INSERT INTO "sqlite_sequence" VALUES('messages',3);
+INSERT INTO "sqlite_sequence" VALUES('agent_questions',1);
+COMMIT;